@@ -0,0 +1,92 @@
+//! Host context switching built on `getcontext`/`makecontext`/`swapcontext`.
+
+use alloc::boxed::Box;
+use core::mem::MaybeUninit;
+
+use super::{ContextEntry, ContextSwitch, Idle, Multicore};
+use crate::{sim::cores, smp::CoreId, stack::TaskStack};
+
+/// `ucontext_t` plus the entry point of a context that has not started yet.
+///
+/// The `ucontext_t` is boxed because glibc stores pointers into it, so it must
+/// not move once captured.
+pub struct HostContext {
+    ucontext: Box<MaybeUninit<libc::ucontext_t>>,
+    entry: Option<(ContextEntry, usize)>,
+}
+
+impl Default for HostContext {
+    fn default() -> Self {
+        Self {
+            ucontext: Box::new(MaybeUninit::zeroed()),
+            entry: None,
+        }
+    }
+}
+
+pub struct Host;
+
+/// `makecontext` only passes `int` arguments, so the context pointer is split.
+extern "C" fn start(hi: u32, lo: u32) {
+    let ctx = (((hi as u64) << 32) | lo as u64) as *mut HostContext;
+    let (entry, arg) = unsafe { (*ctx).entry.take() }.expect("context started twice");
+    entry(arg)
+}
+
+impl ContextSwitch for Host {
+    type Context = HostContext;
+
+    const MIN_STACK_SIZE: usize = 64 * 1024;
+
+    unsafe fn init(ctx: &mut HostContext, stack: &TaskStack, entry: ContextEntry, arg: usize) {
+        let uc = ctx.ucontext.as_mut_ptr();
+        let rc = libc::getcontext(uc);
+        assert_eq!(rc, 0, "getcontext failed");
+        (*uc).uc_stack.ss_sp = stack.bottom().cast();
+        (*uc).uc_stack.ss_size = stack.len();
+        (*uc).uc_link = core::ptr::null_mut();
+        ctx.entry = Some((entry, arg));
+
+        let ptr = ctx as *mut HostContext as u64;
+        let start: extern "C" fn(u32, u32) = start;
+        libc::makecontext(
+            uc,
+            core::mem::transmute::<extern "C" fn(u32, u32), extern "C" fn()>(start),
+            2,
+            (ptr >> 32) as u32,
+            ptr as u32,
+        );
+    }
+
+    unsafe fn switch(from: *mut HostContext, to: *const HostContext) {
+        let rc = libc::swapcontext((*from).ucontext.as_mut_ptr(), (*to).ucontext.as_ptr());
+        assert_eq!(rc, 0, "swapcontext failed");
+    }
+}
+
+impl Idle for Host {
+    /// The next interrupt in the simulation is the tickless alarm, if one
+    /// is armed, or else the next virtual tick, or a doorbell from the other
+    /// core.
+    fn wait_for_interrupt() {
+        cores::wait_for_interrupt();
+    }
+
+    /// Simulated interrupts are taken synchronously and doorbells stay
+    /// pending until the core waits, so there is nothing to mask.
+    fn interrupt_free<R>(f: impl FnOnce() -> R) -> R {
+        f()
+    }
+}
+
+impl Multicore for Host {
+    fn core_id() -> CoreId {
+        cores::current_core()
+    }
+
+    fn interrupt_core(core: CoreId) {
+        cores::interrupt_core(core);
+    }
+
+    fn enable_core_interrupt() {}
+}
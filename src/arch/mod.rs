@@ -0,0 +1,77 @@
+//! Architecture support for stackful tasks and idling.
+//!
+//! A [`ContextSwitch`] implementation knows how to save the running execution
+//! state and resume another one on a different stack. The kernel uses it to
+//! run [`crate::thread::ThreadTask`]s on their own [`TaskStack`]. The Xtensa
+//! backend is used on hardware; the `sim` build switches with `ucontext`.
+//!
+//! [`Idle`] stops the CPU while the scheduler has nothing to run, and
+//! [`Multicore`] lets the cores of the chip tell each other apart and wake
+//! each other up (see [`crate::smp`]).
+
+use crate::{smp::CoreId, stack::TaskStack};
+
+#[cfg(feature = "sim")]
+mod host;
+#[cfg(feature = "esp32")]
+mod xtensa;
+
+/// Entry point of a freshly initialised context. It must never return.
+pub type ContextEntry = extern "C" fn(usize) -> !;
+
+/// Saving and restoring execution contexts.
+pub trait ContextSwitch {
+    /// Saved execution state of one context.
+    type Context: Default;
+
+    /// Smallest stack a context needs on this architecture.
+    const MIN_STACK_SIZE: usize;
+
+    /// Prepare `ctx` so that switching to it calls `entry(arg)` on `stack`.
+    ///
+    /// # Safety
+    /// `ctx` and `stack` must stay at the same address and outlive every
+    /// switch into `ctx`.
+    unsafe fn init(ctx: &mut Self::Context, stack: &TaskStack, entry: ContextEntry, arg: usize);
+
+    /// Save the current state into `from` and resume `to`. Returns when some
+    /// other context switches back to `from`.
+    ///
+    /// # Safety
+    /// `to` must have been initialised with [`ContextSwitch::init`] or saved
+    /// by a previous switch, and both pointers must be valid.
+    unsafe fn switch(from: *mut Self::Context, to: *const Self::Context);
+}
+
+/// Low-power waiting.
+pub trait Idle {
+    /// Unmask interrupts and stop the CPU until the next one, atomically, so
+    /// it may be called with interrupts masked without missing a wakeup.
+    fn wait_for_interrupt();
+
+    /// Run `f` with this core's interrupts masked. Unlike a critical section
+    /// this takes no lock, so the other core keeps running.
+    fn interrupt_free<R>(f: impl FnOnce() -> R) -> R;
+}
+
+/// Several cores sharing memory.
+pub trait Multicore {
+    /// Core running the caller.
+    fn core_id() -> CoreId;
+
+    /// Raise the inter-processor interrupt of `core`, ending its
+    /// [`Idle::wait_for_interrupt`].
+    fn interrupt_core(core: CoreId);
+
+    /// Route the calling core's inter-processor interrupt to it. Called once
+    /// on each core at startup.
+    fn enable_core_interrupt();
+}
+
+#[cfg(feature = "sim")]
+pub use host::Host as Cpu;
+#[cfg(feature = "esp32")]
+pub use xtensa::Xtensa as Cpu;
+
+/// Saved execution state for the current architecture.
+pub type Context = <Cpu as ContextSwitch>::Context;
@@ -0,0 +1,201 @@
+//! Xtensa LX6 (windowed ABI) context switching.
+//!
+//! A context is the return address (`a0`), stack pointer (`a1`) and `PS` of a
+//! suspended `tg_context_switch` call. Before saving them, every live register
+//! window is spilled to its stack, so all other caller state is already in
+//! memory and is reloaded by the window-underflow handler on `retw`.
+//!
+//! `PS` is part of the context so the tick or doorbell interrupt can switch a
+//! preempted thread out from inside its handler: the scheduler resumes with its own
+//! interrupt level, and the thread later finishes the handler, whose epilogue
+//! restores the interrupted state.
+
+use core::arch::{asm, global_asm};
+
+use esp_hal::{
+    handler,
+    interrupt::{software::SoftwareInterrupt, Priority},
+    system,
+};
+
+use super::{ContextEntry, ContextSwitch, Idle, Multicore};
+use crate::{smp::CoreId, stack::TaskStack};
+
+/// Saved `a0`/`a1`/`PS` of a suspended context.
+#[repr(C)]
+#[derive(Default)]
+pub struct XtensaContext {
+    a0: u32,
+    a1: u32,
+    ps: u32,
+}
+
+pub struct Xtensa;
+
+extern "C" {
+    fn tg_context_switch(from: *mut XtensaContext, to: *const XtensaContext);
+    fn tg_context_start();
+}
+
+// `tg_context_switch(from: a2, to: a3)`
+//
+// The rotw sequence is the standard spill for 64 physical AR registers: it
+// touches a12 in each of the other windows, raising overflow exceptions that
+// store every live caller frame to its stack.
+//
+// `tg_context_start` is reached by `retw` from a fresh context. The window
+// underflow loads its a0-a3 from the frame prepared by `init`: a2 holds the
+// argument and a3 the entry function, which is called with `callx4`.
+global_asm!(
+    r#"
+    .section .iram1.tg_context_switch, "ax"
+    .global tg_context_switch
+    .type tg_context_switch, @function
+    .align 4
+tg_context_switch:
+    entry   a1, 32
+    and     a12, a12, a12
+    rotw    3
+    and     a12, a12, a12
+    rotw    3
+    and     a12, a12, a12
+    rotw    3
+    and     a12, a12, a12
+    rotw    3
+    and     a12, a12, a12
+    rotw    4
+    rsr.ps  a4
+    s32i    a0, a2, 0
+    s32i    a1, a2, 4
+    s32i    a4, a2, 8
+    l32i    a4, a3, 8
+    l32i    a0, a3, 0
+    l32i    a1, a3, 4
+    wsr.ps  a4
+    rsync
+    retw
+
+    .global tg_context_start
+    .type tg_context_start, @function
+    .align 4
+tg_context_start:
+    mov     a6, a2
+    callx4  a3
+    ill
+    "#
+);
+
+/// Window-increment bits for a return into a `call4` frame.
+const CALL4_RETURN: u32 = 1 << 30;
+
+impl ContextSwitch for Xtensa {
+    type Context = XtensaContext;
+
+    const MIN_STACK_SIZE: usize = 1024;
+
+    unsafe fn init(ctx: &mut XtensaContext, stack: &TaskStack, entry: ContextEntry, arg: usize) {
+        // Stack pointers must be 16-byte aligned.
+        let top = (stack.top() as usize) & !0xF;
+        let start_sp = top - 16;
+        let switch_sp = top - 32;
+
+        // Base save area read by the window underflow for tg_context_start:
+        // a0 (no caller), a1, a2 = arg, a3 = entry.
+        let frame = (switch_sp - 16) as *mut u32;
+        frame.write(0);
+        frame.add(1).write(start_sp as u32);
+        frame.add(2).write(arg as u32);
+        frame.add(3).write(entry as usize as u32);
+
+        // New threads start with the interrupt state of the code spawning
+        // them (the scheduler), never inside an interrupt handler.
+        let ps: u32;
+        asm!("rsr.ps {0}", out(reg) ps);
+
+        ctx.a0 = CALL4_RETURN | (tg_context_start as usize as u32 & 0x3FFF_FFFF);
+        ctx.a1 = switch_sp as u32;
+        ctx.ps = ps;
+    }
+
+    unsafe fn switch(from: *mut XtensaContext, to: *const XtensaContext) {
+        tg_context_switch(from, to);
+    }
+}
+
+impl Idle for Xtensa {
+    fn wait_for_interrupt() {
+        // SAFETY: `waiti 0` enables all interrupt levels and halts until one
+        // is taken, so an interrupt left pending by the caller's critical
+        // section ends the wait at once. PS.INTLEVEL stays 0 afterwards; the
+        // caller's critical section restores its saved level on exit.
+        unsafe { asm!("waiti 0") };
+    }
+
+    fn interrupt_free<R>(f: impl FnOnce() -> R) -> R {
+        let ps: u32;
+        // SAFETY: raising the interrupt level only masks interrupts on this
+        // core; the saved PS is written back below.
+        unsafe { asm!("rsil {0}, 15", out(reg) ps) };
+        let result = f();
+        // SAFETY: restores the level saved above.
+        unsafe { asm!("wsr.ps {0}", "rsync", in(reg) ps) };
+        result
+    }
+}
+
+/// Doorbell of the PRO core: software interrupt 0, handled on the PRO core.
+#[handler(priority = Priority::Priority1)]
+fn pro_doorbell() {
+    crate::trace_event!(IsrEnter { irq: "doorbell" });
+    // SAFETY: software interrupts 0 and 1 are reserved for the doorbells.
+    unsafe { SoftwareInterrupt::<0>::steal() }.reset();
+    crate::trace_event!(IsrExit { irq: "doorbell" });
+    // May switch to the scheduler if the tick interrupt forwarded the last
+    // tick of the running thread's time slice.
+    crate::preempt::on_doorbell();
+}
+
+/// Doorbell of the APP core: software interrupt 1, handled on the APP core.
+#[handler(priority = Priority::Priority1)]
+fn app_doorbell() {
+    crate::trace_event!(IsrEnter { irq: "doorbell" });
+    // SAFETY: software interrupts 0 and 1 are reserved for the doorbells.
+    unsafe { SoftwareInterrupt::<1>::steal() }.reset();
+    crate::trace_event!(IsrExit { irq: "doorbell" });
+    // May switch to the scheduler if the tick interrupt forwarded the last
+    // tick of the running thread's time slice.
+    crate::preempt::on_doorbell();
+}
+
+impl Multicore for Xtensa {
+    fn core_id() -> CoreId {
+        match system::Cpu::current() {
+            system::Cpu::ProCpu => CoreId::Pro,
+            system::Cpu::AppCpu => CoreId::App,
+        }
+    }
+
+    fn interrupt_core(core: CoreId) {
+        // SAFETY: software interrupts 0 and 1 are reserved for the doorbells,
+        // and raising one has no other effect.
+        match core {
+            CoreId::Pro => unsafe { SoftwareInterrupt::<0>::steal() }.raise(),
+            CoreId::App => unsafe { SoftwareInterrupt::<1>::steal() }.raise(),
+        }
+    }
+
+    fn enable_core_interrupt() {
+        // Binding a handler enables the interrupt on the calling core. The
+        // handlers acknowledge the interrupt, which ends `waiti`, and count
+        // forwarded time slice ticks.
+        // SAFETY: software interrupts 0 and 1 are reserved for the doorbells.
+        match Self::core_id() {
+            CoreId::Pro => {
+                unsafe { SoftwareInterrupt::<0>::steal() }.set_interrupt_handler(pro_doorbell)
+            }
+            CoreId::App => {
+                unsafe { SoftwareInterrupt::<1>::steal() }.set_interrupt_handler(app_doorbell)
+            }
+        }
+    }
+}
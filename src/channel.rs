@@ -0,0 +1,276 @@
+//! Bounded message channels between tasks.
+//!
+//! A [`Channel`] is a fixed-capacity MPMC queue that can live in a `static`.
+//! Senders and receivers never allocate; when the queue is full or empty they
+//! either get the message back / `None` from the `try_` methods, block with
+//! [`TaskCommand::Wait`] on [`Channel::receivable`] / [`Channel::sendable`],
+//! or `.await` [`Channel::send`] / [`Channel::recv`] from an async task.
+//!
+//! [`TaskCommand::Wait`]: crate::scheduler::TaskCommand::Wait
+
+use core::{
+    cell::RefCell,
+    future::{poll_fn, Future},
+    task::{Poll, Waker},
+};
+
+use critical_section::{CriticalSection, Mutex};
+use heapless::Deque;
+
+use crate::{
+    event::{WaitCondition, WaitQueue},
+    executor::request_wakeup,
+    timer::{Duration, Instant},
+};
+
+/// Type-erased view of a channel used by [`WaitCondition`].
+pub trait ChannelState: Sync {
+    /// Whether a message is queued; if not, register `waker` for the next send.
+    fn receivable_or_register(&self, cs: CriticalSection<'_>, waker: &Waker) -> bool;
+
+    /// Whether there is room; if not, register `waker` for the next receive.
+    fn sendable_or_register(&self, cs: CriticalSection<'_>, waker: &Waker) -> bool;
+
+    /// Drop `waker` once its wait has ended.
+    fn unregister(&self, cs: CriticalSection<'_>, waker: &Waker);
+}
+
+/// Bounded queue holding up to `N` messages of type `T`.
+pub struct Channel<T, const N: usize> {
+    queue: Mutex<RefCell<Deque<T, N>>>,
+    receivers: WaitQueue,
+    senders: WaitQueue,
+}
+
+impl<T, const N: usize> Channel<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            queue: Mutex::new(RefCell::new(Deque::new())),
+            receivers: WaitQueue::new(),
+            senders: WaitQueue::new(),
+        }
+    }
+
+    /// Queue `msg` without blocking, handing it back if the channel is full.
+    /// Safe to call from an ISR.
+    pub fn try_send(&self, msg: T) -> Result<(), T> {
+        critical_section::with(|cs| {
+            self.queue.borrow_ref_mut(cs).push_back(msg)?;
+            self.receivers.wake_all(cs);
+            Ok(())
+        })
+    }
+
+    /// Take the oldest message without blocking.
+    pub fn try_recv(&self) -> Option<T> {
+        critical_section::with(|cs| {
+            let msg = self.queue.borrow_ref_mut(cs).pop_front()?;
+            self.senders.wake_all(cs);
+            Some(msg)
+        })
+    }
+
+    /// Number of queued messages.
+    #[allow(dead_code, reason = "the firmware's tasks never check queue depth")]
+    pub fn len(&self) -> usize {
+        critical_section::with(|cs| self.queue.borrow_ref(cs).len())
+    }
+
+    /// Whether no messages are queued.
+    #[allow(dead_code, reason = "pairs with len")]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Wait until the channel has room, then queue `msg`.
+    #[allow(dead_code, reason = "the firmware's tasks are poll-based")]
+    pub fn send(&self, msg: T) -> impl Future<Output = ()> + '_ {
+        let mut msg = Some(msg);
+        poll_fn(move |cx| {
+            critical_section::with(|cs| {
+                let mut queue = self.queue.borrow_ref_mut(cs);
+                if queue.is_full() {
+                    self.senders.register(cs, cx.waker());
+                    return Poll::Pending;
+                }
+                if let Some(msg) = msg.take() {
+                    let _ = queue.push_back(msg);
+                    self.receivers.wake_all(cs);
+                }
+                Poll::Ready(())
+            })
+        })
+    }
+
+    /// Wait for the next message.
+    #[allow(dead_code, reason = "the firmware's tasks are poll-based")]
+    pub fn recv(&self) -> impl Future<Output = T> + '_ {
+        poll_fn(move |cx| self.poll_recv(cx.waker()))
+    }
+
+    /// Wait for the next message for at most `timeout`.
+    #[allow(dead_code, reason = "the firmware's tasks are poll-based")]
+    pub fn recv_timeout(&self, timeout: Duration) -> impl Future<Output = Option<T>> + '_ {
+        let deadline = Instant::now() + timeout;
+        poll_fn(move |cx| match self.poll_recv(cx.waker()) {
+            Poll::Ready(msg) => Poll::Ready(Some(msg)),
+            Poll::Pending if Instant::now() >= deadline => Poll::Ready(None),
+            Poll::Pending => {
+                request_wakeup(deadline);
+                Poll::Pending
+            }
+        })
+    }
+
+    fn poll_recv(&self, waker: &Waker) -> Poll<T> {
+        critical_section::with(|cs| match self.queue.borrow_ref_mut(cs).pop_front() {
+            Some(msg) => {
+                self.senders.wake_all(cs);
+                Poll::Ready(msg)
+            }
+            None => {
+                self.receivers.register(cs, waker);
+                Poll::Pending
+            }
+        })
+    }
+}
+
+impl<T: Send, const N: usize> Channel<T, N> {
+    /// Condition satisfied once a message is queued.
+    pub fn receivable(&'static self) -> WaitCondition {
+        WaitCondition::Receivable(self)
+    }
+
+    /// Condition satisfied once the channel has room for a message.
+    pub fn sendable(&'static self) -> WaitCondition {
+        WaitCondition::Sendable(self)
+    }
+}
+
+impl<T: Send, const N: usize> ChannelState for Channel<T, N> {
+    fn receivable_or_register(&self, cs: CriticalSection<'_>, waker: &Waker) -> bool {
+        let ready = !self.queue.borrow_ref(cs).is_empty();
+        if !ready {
+            self.receivers.register(cs, waker);
+        }
+        ready
+    }
+
+    fn sendable_or_register(&self, cs: CriticalSection<'_>, waker: &Waker) -> bool {
+        let ready = !self.queue.borrow_ref(cs).is_full();
+        if !ready {
+            self.senders.register(cs, waker);
+        }
+        ready
+    }
+
+    fn unregister(&self, cs: CriticalSection<'_>, waker: &Waker) {
+        self.receivers.remove(cs, waker);
+        self.senders.remove(cs, waker);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc, vec::Vec};
+
+    use super::*;
+    use crate::{
+        executor::AsyncTask,
+        scheduler::{Scheduler, Task, TaskCommand, TaskContext},
+        sim,
+    };
+
+    /// Poll-based consumer blocking on the channel with a timeout.
+    struct Consumer {
+        channel: &'static Channel<u32, 2>,
+        received: Rc<RefCell<Vec<Option<u32>>>>,
+    }
+
+    impl Task for Consumer {
+        fn name(&self) -> &'static str {
+            "consumer"
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            self.received.borrow_mut().push(self.channel.try_recv());
+            TaskCommand::Wait(self.channel.receivable(), Some(Duration::from_millis(100)))
+        }
+    }
+
+    #[test]
+    fn receiver_blocks_until_message_or_timeout() {
+        static CHANNEL: Channel<u32, 2> = Channel::new();
+        let _board = sim::board();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let mut scheduler = Scheduler::new();
+        let consumer = Box::leak(Box::new(Consumer {
+            channel: &CHANNEL,
+            received: received.clone(),
+        }));
+        scheduler.spawn(consumer).unwrap();
+
+        scheduler.run_ready();
+        sim::clock::advance_ms(10);
+        scheduler.run_ready();
+        assert_eq!(*received.borrow(), [None]);
+
+        CHANNEL.try_send(7).unwrap();
+        scheduler.run_ready();
+        assert_eq!(*received.borrow(), [None, Some(7)]);
+
+        sim::clock::advance_ms(100);
+        scheduler.run_ready();
+        assert_eq!(*received.borrow(), [None, Some(7), None]);
+    }
+
+    #[test]
+    fn full_channel_rejects_then_blocks_async_sender() {
+        static CHANNEL: Channel<u32, 2> = Channel::new();
+        let _board = sim::board();
+        assert_eq!(CHANNEL.try_send(1), Ok(()));
+        assert_eq!(CHANNEL.try_send(2), Ok(()));
+        assert_eq!(CHANNEL.try_send(3), Err(3));
+
+        let sent = Rc::new(RefCell::new(false));
+        let task_sent = sent.clone();
+        let mut scheduler = Scheduler::new();
+        let producer = AsyncTask::new("producer", async move {
+            CHANNEL.send(3).await;
+            *task_sent.borrow_mut() = true;
+        });
+        scheduler.spawn(Box::leak(Box::new(producer))).unwrap();
+
+        scheduler.run_ready();
+        assert!(!*sent.borrow());
+
+        assert_eq!(CHANNEL.try_recv(), Some(1));
+        scheduler.run_ready();
+        assert!(*sent.borrow());
+        assert_eq!(CHANNEL.try_recv(), Some(2));
+        assert_eq!(CHANNEL.try_recv(), Some(3));
+        assert!(CHANNEL.is_empty());
+    }
+
+    #[test]
+    fn async_recv_timeout_expires_without_sender() {
+        static CHANNEL: Channel<u32, 2> = Channel::new();
+        let _board = sim::board();
+        let result = Rc::new(RefCell::new(None));
+        let task_result = result.clone();
+        let mut scheduler = Scheduler::new();
+        let receiver = AsyncTask::new("receiver", async move {
+            let msg = CHANNEL.recv_timeout(Duration::from_millis(5)).await;
+            *task_result.borrow_mut() = Some((msg, Instant::now().ticks()));
+        });
+        scheduler.spawn(Box::leak(Box::new(receiver))).unwrap();
+
+        for _ in 0..10 {
+            scheduler.run_ready();
+            sim::clock::advance(1);
+        }
+
+        assert_eq!(*result.borrow(), Some((None, 5)));
+    }
+}
@@ -0,0 +1,27 @@
+//! Console output
+//!
+//! Provides the kernel-wide `println!` macro. On hardware it forwards to
+//! `esp_println`; the `sim` build routes lines to the simulation console sink.
+
+/// Print a line to the system console.
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::console::print_line(format_args!(""))
+    };
+    ($($arg:tt)*) => {
+        $crate::console::print_line(format_args!($($arg)*))
+    };
+}
+
+/// Write one formatted line to the console backend.
+#[cfg(feature = "esp32")]
+pub fn print_line(args: core::fmt::Arguments<'_>) {
+    esp_println::println!("{}", args);
+}
+
+/// Write one formatted line to the console backend.
+#[cfg(feature = "sim")]
+pub fn print_line(args: core::fmt::Arguments<'_>) {
+    crate::sim::console::write_line(args);
+}
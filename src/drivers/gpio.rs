@@ -1,15 +1,29 @@
 use core::cell::RefCell;
 
 use critical_section::{with, Mutex};
-use esp_hal::gpio::{Level, Output, OutputConfig};
+#[cfg(feature = "esp32")]
+use esp_hal::gpio::{Event, Io, Level, OutputConfig};
+#[cfg(feature = "esp32")]
+use esp_hal::handler;
+#[cfg(feature = "esp32")]
 use esp_hal::peripherals::GPIO2;
 
+#[cfg(feature = "sim")]
+pub use crate::sim::gpio::{Input, Output};
+#[cfg(feature = "esp32")]
+pub use esp_hal::gpio::{Input, Output};
+
 use super::{DriverCell, DriverError, DriverHandle};
+use crate::event::EventGroup;
+
+type LedPin = Output<'static>;
 
-static LED_DRIVER: DriverCell<Output<'static>> = Mutex::new(RefCell::new(None));
+#[cfg(feature = "esp32")]
+static LED_DRIVER: DriverCell<LedPin> = Mutex::new(RefCell::new(None));
 
-pub type LedHandle = DriverHandle<Output<'static>>;
+pub type LedHandle = DriverHandle<LedPin>;
 
+#[cfg(feature = "esp32")]
 pub fn init_led(gpio2: GPIO2<'static>) -> Result<LedHandle, DriverError> {
     with(|cs| {
         let mut cell = LED_DRIVER.borrow_ref_mut(cs);
@@ -21,3 +35,104 @@ pub fn init_led(gpio2: GPIO2<'static>) -> Result<LedHandle, DriverError> {
     })?;
     Ok(LedHandle::new(&LED_DRIVER))
 }
+
+/// Simulated boards are created per test, so each LED gets its own cell.
+#[cfg(feature = "sim")]
+pub fn init_led(pin: LedPin) -> Result<LedHandle, DriverError> {
+    let cell: &'static DriverCell<LedPin> =
+        std::boxed::Box::leak(std::boxed::Box::new(Mutex::new(RefCell::new(None))));
+    with(|cs| {
+        *cell.borrow_ref_mut(cs) = Some(pin);
+    });
+    Ok(LedHandle::new(cell))
+}
+
+/// Front-panel navigation buttons (active low, pulled up).
+pub struct Buttons {
+    pub scroll_up: Input<'static>,
+    pub scroll_down: Input<'static>,
+    pub select: Input<'static>,
+}
+
+/// Which buttons are held down at the moment of sampling.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ButtonState {
+    pub scroll_up: bool,
+    pub scroll_down: bool,
+    pub select: bool,
+}
+
+impl Buttons {
+    /// Sample all button levels.
+    pub fn state(&self) -> ButtonState {
+        ButtonState {
+            scroll_up: self.scroll_up.is_low(),
+            scroll_down: self.scroll_down.is_low(),
+            select: self.select.is_low(),
+        }
+    }
+}
+
+pub type ButtonsHandle = DriverHandle<Buttons>;
+
+/// Signalled from the GPIO interrupt whenever a button changes level.
+pub static BUTTON_EVENTS: EventGroup = EventGroup::new();
+/// [`BUTTON_EVENTS`] bit set on any button edge.
+pub const BUTTON_CHANGED: u32 = 1 << 0;
+
+#[cfg(feature = "esp32")]
+static BUTTONS_DRIVER: DriverCell<Buttons> = Mutex::new(RefCell::new(None));
+
+#[cfg(feature = "esp32")]
+pub fn init_buttons(
+    io: &mut Io<'static>,
+    mut buttons: Buttons,
+) -> Result<ButtonsHandle, DriverError> {
+    with(|cs| {
+        let mut cell = BUTTONS_DRIVER.borrow_ref_mut(cs);
+        if cell.is_some() {
+            return Err(DriverError::AlreadyInitialized);
+        }
+        buttons.scroll_up.listen(Event::AnyEdge);
+        buttons.scroll_down.listen(Event::AnyEdge);
+        buttons.select.listen(Event::AnyEdge);
+        *cell = Some(buttons);
+        Ok(())
+    })?;
+    io.set_interrupt_handler(button_isr);
+    Ok(ButtonsHandle::new(&BUTTONS_DRIVER))
+}
+
+#[cfg(feature = "esp32")]
+#[handler]
+fn button_isr() {
+    crate::trace_event!(IsrEnter { irq: "gpio" });
+    with(|cs| {
+        if let Some(buttons) = BUTTONS_DRIVER.borrow_ref_mut(cs).as_mut() {
+            buttons.scroll_up.clear_interrupt();
+            buttons.scroll_down.clear_interrupt();
+            buttons.select.clear_interrupt();
+        }
+    });
+    BUTTON_EVENTS.set(BUTTON_CHANGED);
+    crate::trace_event!(IsrExit { irq: "gpio" });
+}
+
+/// Simulated boards are created per test, so each button set gets its own cell.
+#[cfg(feature = "sim")]
+pub fn init_buttons(mut buttons: Buttons) -> Result<ButtonsHandle, DriverError> {
+    buttons.scroll_up.listen(button_edge);
+    buttons.scroll_down.listen(button_edge);
+    buttons.select.listen(button_edge);
+    let cell: &'static DriverCell<Buttons> =
+        std::boxed::Box::leak(std::boxed::Box::new(Mutex::new(RefCell::new(None))));
+    with(|cs| {
+        *cell.borrow_ref_mut(cs) = Some(buttons);
+    });
+    Ok(ButtonsHandle::new(cell))
+}
+
+#[cfg(feature = "sim")]
+fn button_edge() {
+    BUTTON_EVENTS.set(BUTTON_CHANGED);
+}
@@ -1,11 +1,16 @@
 use core::{cell::RefCell, marker::PhantomData};
 
+use crate::{println, sync};
 use critical_section::Mutex;
-use esp_println::println;
 
 pub type DriverCell<T> = Mutex<RefCell<Option<T>>>;
 
+/// Storage for drivers used only from tasks. Guarded by a kernel mutex, so
+/// slow operations such as an OLED flush run with interrupts enabled.
+pub type TaskDriverCell<T> = sync::Mutex<Option<T>>;
+
 #[derive(Debug)]
+#[cfg_attr(feature = "sim", allow(dead_code, reason = "esp32 drivers only"))]
 pub enum DriverError {
     AlreadyInitialized,
     NotReady,
@@ -33,15 +38,11 @@ impl<T: 'static> DriverHandle<T> {
         }
     }
 
-    #[allow(dead_code)]
-    pub fn is_ready(&self) -> bool {
-        critical_section::with(|cs| self.cell.borrow_ref(cs).is_some())
-    }
-
     pub fn try_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
         critical_section::with(|cs| self.cell.borrow_ref_mut(cs).as_mut().map(f))
     }
 
+    #[cfg_attr(feature = "sim", allow(dead_code, reason = "esp32 drivers only"))]
     pub fn take(&self) -> Option<T> {
         critical_section::with(|cs| {
             let result = self.cell.borrow_ref_mut(cs).take();
@@ -52,12 +53,39 @@ impl<T: 'static> DriverHandle<T> {
         })
     }
 
+    #[cfg_attr(feature = "sim", allow(dead_code, reason = "esp32 drivers only"))]
     pub fn replace(&self, value: T) -> Option<T> {
         critical_section::with(|cs| self.cell.borrow_ref_mut(cs).replace(value))
     }
 }
 
+/// Handle onto a driver stored in a [`TaskDriverCell`].
+pub struct TaskDriverHandle<T: 'static> {
+    cell: &'static TaskDriverCell<T>,
+}
+
+impl<T: 'static> Copy for TaskDriverHandle<T> {}
+
+impl<T: 'static> Clone for TaskDriverHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: 'static> TaskDriverHandle<T> {
+    pub const fn new(cell: &'static TaskDriverCell<T>) -> Self {
+        Self { cell }
+    }
+
+    /// Run `f` on the driver, or return `None` if it is missing or in use by
+    /// another task.
+    pub fn try_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.cell.try_lock()?.as_mut().map(f)
+    }
+}
+
 pub mod gpio;
+#[cfg(feature = "esp32")]
 pub mod i2c;
 pub mod oled;
 pub mod uart;
@@ -1,20 +1,22 @@
-use core::cell::RefCell;
-
-use critical_section::{with, Mutex};
-
+#[cfg(feature = "esp32")]
 use crate::drivers::i2c::I2cHandle;
+#[cfg(feature = "esp32")]
 use crate::oled::OledDisplay;
+#[cfg(feature = "sim")]
+use crate::sim::display::OledDisplay;
 
-use super::{DriverCell, DriverError, DriverHandle};
+use super::{DriverError, TaskDriverCell, TaskDriverHandle};
 
-static OLED_DRIVER: DriverCell<OledDisplay> = Mutex::new(RefCell::new(None));
+#[cfg(feature = "esp32")]
+static OLED_DRIVER: TaskDriverCell<OledDisplay> = TaskDriverCell::new(None);
 
-pub type OledHandle = DriverHandle<OledDisplay>;
+pub type OledHandle = TaskDriverHandle<OledDisplay>;
 
+#[cfg(feature = "esp32")]
 pub fn init_oled(i2c: &I2cHandle) -> Result<OledHandle, DriverError> {
     let bus = i2c.take().ok_or(DriverError::NotReady)?;
 
-    if with(|cs| OLED_DRIVER.borrow_ref(cs).is_some()) {
+    if OLED_DRIVER.try_lock().is_none_or(|cell| cell.is_some()) {
         let _ = i2c.replace(bus);
         return Err(DriverError::AlreadyInitialized);
     }
@@ -22,15 +24,23 @@ pub fn init_oled(i2c: &I2cHandle) -> Result<OledHandle, DriverError> {
     let display = match OledDisplay::new(bus) {
         Ok(display) => display,
         Err(err) => {
-            esp_println::println!("OLED driver creation failed: {:?}", err);
+            crate::println!("OLED driver creation failed: {:?}", err);
             return Err(DriverError::InitFailed("oled init"));
         }
     };
 
-    with(|cs| {
-        let mut cell = OLED_DRIVER.borrow_ref_mut(cs);
-        *cell = Some(display);
-    });
+    match OLED_DRIVER.try_lock() {
+        Some(mut cell) => *cell = Some(display),
+        None => return Err(DriverError::AlreadyInitialized),
+    }
 
     Ok(OledHandle::new(&OLED_DRIVER))
 }
+
+/// Simulated boards are created per test, so each display gets its own cell.
+#[cfg(feature = "sim")]
+pub fn init_oled(display: OledDisplay) -> Result<OledHandle, DriverError> {
+    let cell: &'static TaskDriverCell<OledDisplay> =
+        std::boxed::Box::leak(std::boxed::Box::new(TaskDriverCell::new(Some(display))));
+    Ok(OledHandle::new(cell))
+}
@@ -0,0 +1,445 @@
+//! Event groups and task notifications.
+//!
+//! Kernel objects a task can block on with [`TaskCommand::Wait`] instead of
+//! polling on a timer. An [`EventGroup`] is a set of 32 flag bits that can be
+//! statically allocated and set from tasks or interrupt handlers; every task
+//! also has its own notification bits, signalled through a [`TaskNotifier`].
+//! Setting bits wakes the waiting tasks immediately. Channels from
+//! [`crate::channel`] and locks from [`crate::sync`] plug into the same
+//! [`WaitCondition`].
+//!
+//! [`TaskCommand::Wait`]: crate::scheduler::TaskCommand::Wait
+
+use alloc::sync::Arc;
+use core::{
+    cell::RefCell,
+    fmt,
+    future::{poll_fn, Future},
+    ptr,
+    task::{Poll, Waker},
+};
+
+use critical_section::{CriticalSection, Mutex};
+use heapless::Vec;
+
+use crate::{
+    channel::ChannelState,
+    executor::TaskSignal,
+    scheduler::MAX_TASKS,
+    sync::{MutexState, Semaphore},
+};
+
+/// Wakers of tasks blocked on a kernel object.
+///
+/// Holds one waker per task, up to [`MAX_TASKS`] whatever the size of the
+/// scheduler. A waker leaves the queue when it is woken, when the
+/// [`WaitCondition`] it waited on ends, or when a [`crate::sync::Mutex::lock`]
+/// future is dropped. If more tasks wait at once, the queued ones are woken
+/// early to make room; they re-check their condition and wait again, so the
+/// worst case is a spurious wakeup.
+pub struct WaitQueue {
+    wakers: Mutex<RefCell<Vec<Waker, MAX_TASKS>>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            wakers: Mutex::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Add `waker` to the queue, unless it is already there.
+    pub fn register(&self, cs: CriticalSection<'_>, waker: &Waker) {
+        let mut wakers = self.wakers.borrow_ref_mut(cs);
+        if wakers.iter().any(|queued| queued.will_wake(waker)) {
+            return;
+        }
+        if wakers.is_full() {
+            for queued in core::mem::take(&mut *wakers) {
+                queued.wake();
+            }
+        }
+        let _ = wakers.push(waker.clone());
+    }
+
+    /// Drop `waker` from the queue without waking it.
+    pub fn remove(&self, cs: CriticalSection<'_>, waker: &Waker) {
+        self.wakers
+            .borrow_ref_mut(cs)
+            .retain(|queued| !queued.will_wake(waker));
+    }
+
+    /// Wake and remove every queued task.
+    pub fn wake_all(&self, cs: CriticalSection<'_>) {
+        for waker in core::mem::take(&mut *self.wakers.borrow_ref_mut(cs)) {
+            waker.wake();
+        }
+    }
+}
+
+/// Group of 32 event flags.
+pub struct EventGroup {
+    bits: Mutex<RefCell<u32>>,
+    waiters: WaitQueue,
+}
+
+impl EventGroup {
+    pub const fn new() -> Self {
+        Self {
+            bits: Mutex::new(RefCell::new(0)),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Set `bits` and wake all waiting tasks. Safe to call from an ISR.
+    pub fn set(&self, bits: u32) {
+        critical_section::with(|cs| {
+            *self.bits.borrow_ref_mut(cs) |= bits;
+            self.waiters.wake_all(cs);
+        });
+    }
+
+    /// Clear `bits`, returning the flags as they were before.
+    pub fn clear(&self, bits: u32) -> u32 {
+        critical_section::with(|cs| {
+            let mut current = self.bits.borrow_ref_mut(cs);
+            let before = *current;
+            *current &= !bits;
+            before
+        })
+    }
+
+    /// Current flags.
+    #[allow(dead_code, reason = "for tasks that poll the flags instead of waiting")]
+    pub fn get(&self) -> u32 {
+        critical_section::with(|cs| *self.bits.borrow_ref(cs))
+    }
+
+    /// Condition satisfied once any of `bits` is set.
+    pub fn any(&'static self, bits: u32) -> WaitCondition {
+        WaitCondition::AnyEvent(self, bits)
+    }
+
+    /// Condition satisfied once all of `bits` are set.
+    #[allow(dead_code, reason = "the firmware only waits for any bit")]
+    pub fn all(&'static self, bits: u32) -> WaitCondition {
+        WaitCondition::AllEvents(self, bits)
+    }
+
+    /// Wait until any of `bits` is set, then clear and return them.
+    #[allow(dead_code, reason = "the firmware's tasks are poll-based")]
+    pub fn wait_any(&self, bits: u32) -> impl Future<Output = u32> + '_ {
+        poll_fn(move |cx| {
+            critical_section::with(|cs| {
+                let mut current = self.bits.borrow_ref_mut(cs);
+                let hit = *current & bits;
+                if hit != 0 {
+                    *current &= !hit;
+                    Poll::Ready(hit)
+                } else {
+                    self.waiters.register(cs, cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+    }
+}
+
+/// What a task blocks on with [`TaskCommand::Wait`].
+///
+/// The condition only decides when the task is polled again; the task itself
+/// consumes the event (for example with [`EventGroup::clear`]).
+///
+/// [`TaskCommand::Wait`]: crate::scheduler::TaskCommand::Wait
+#[derive(Clone, Copy)]
+pub enum WaitCondition {
+    /// Any of the bits set in the event group.
+    AnyEvent(&'static EventGroup, u32),
+    /// All of the bits set in the event group.
+    AllEvents(&'static EventGroup, u32),
+    /// Any of the bits among the task's own notification bits.
+    #[allow(dead_code, reason = "built by tasks awaiting notifications")]
+    Notification(u32),
+    /// A message is queued on the channel.
+    Receivable(&'static dyn ChannelState),
+    /// The channel has room for another message.
+    Sendable(&'static dyn ChannelState),
+    /// The mutex is free.
+    Unlocked(&'static dyn MutexState),
+    /// The semaphore has a permit available.
+    Acquirable(&'static Semaphore),
+}
+
+impl WaitCondition {
+    /// Return `true` if the condition already holds; otherwise register
+    /// `waker` to be woken when it may have changed. Both happen atomically
+    /// with respect to [`EventGroup::set`] and [`TaskNotifier::notify`].
+    pub(crate) fn check_or_register(&self, signal: &TaskSignal, waker: &Waker) -> bool {
+        critical_section::with(|cs| match *self {
+            WaitCondition::AnyEvent(group, bits) => {
+                let ready = *group.bits.borrow_ref(cs) & bits != 0;
+                if !ready {
+                    group.waiters.register(cs, waker);
+                }
+                ready
+            }
+            WaitCondition::AllEvents(group, bits) => {
+                let ready = *group.bits.borrow_ref(cs) & bits == bits;
+                if !ready {
+                    group.waiters.register(cs, waker);
+                }
+                ready
+            }
+            // The task's own waker is woken by notify(), nothing to register.
+            WaitCondition::Notification(bits) => signal.notifications() & bits != 0,
+            WaitCondition::Receivable(channel) => channel.receivable_or_register(cs, waker),
+            WaitCondition::Sendable(channel) => channel.sendable_or_register(cs, waker),
+            WaitCondition::Unlocked(mutex) => mutex.unlocked_or_register(cs, signal, waker),
+            WaitCondition::Acquirable(semaphore) => semaphore.acquirable_or_register(cs, waker),
+        })
+    }
+}
+
+impl WaitCondition {
+    /// Undo what [`Self::check_or_register`] did for a wait that has ended,
+    /// whether or not the condition was met: drop `waker` from the object's
+    /// queue, and take back the priority a mutex waiter lent the owner.
+    pub(crate) fn end_wait(&self, signal: &TaskSignal, waker: &Waker) {
+        critical_section::with(|cs| match *self {
+            WaitCondition::AnyEvent(group, _) | WaitCondition::AllEvents(group, _) => {
+                group.waiters.remove(cs, waker)
+            }
+            WaitCondition::Notification(_) => {}
+            WaitCondition::Receivable(channel) | WaitCondition::Sendable(channel) => {
+                channel.unregister(cs, waker)
+            }
+            WaitCondition::Unlocked(mutex) => mutex.withdraw(cs, signal, waker),
+            WaitCondition::Acquirable(semaphore) => semaphore.unregister(cs, waker),
+        })
+    }
+}
+
+impl PartialEq for WaitCondition {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::AnyEvent(a, x), Self::AnyEvent(b, y))
+            | (Self::AllEvents(a, x), Self::AllEvents(b, y)) => ptr::eq(*a, *b) && x == y,
+            (Self::Notification(x), Self::Notification(y)) => x == y,
+            (Self::Receivable(a), Self::Receivable(b)) | (Self::Sendable(a), Self::Sendable(b)) => {
+                ptr::addr_eq(*a, *b)
+            }
+            (Self::Unlocked(a), Self::Unlocked(b)) => ptr::addr_eq(*a, *b),
+            (Self::Acquirable(a), Self::Acquirable(b)) => ptr::eq(*a, *b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for WaitCondition {}
+
+impl fmt::Debug for WaitCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AnyEvent(group, bits) => write!(f, "AnyEvent({:p}, {:#x})", *group, bits),
+            Self::AllEvents(group, bits) => write!(f, "AllEvents({:p}, {:#x})", *group, bits),
+            Self::Notification(bits) => write!(f, "Notification({:#x})", bits),
+            Self::Receivable(channel) => write!(f, "Receivable({:p})", *channel),
+            Self::Sendable(channel) => write!(f, "Sendable({:p})", *channel),
+            Self::Unlocked(mutex) => write!(f, "Unlocked({:p})", *mutex),
+            Self::Acquirable(semaphore) => write!(f, "Acquirable({:p})", *semaphore),
+        }
+    }
+}
+
+/// Handle for sending notification bits to one task.
+///
+/// Obtained from [`TaskContext::notifier`](crate::scheduler::TaskContext::notifier);
+/// can be stored in a static for use from interrupt handlers.
+#[derive(Clone)]
+pub struct TaskNotifier {
+    signal: Arc<TaskSignal>,
+}
+
+impl TaskNotifier {
+    pub(crate) fn new(signal: Arc<TaskSignal>) -> Self {
+        Self { signal }
+    }
+
+    /// Set notification `bits` on the task and wake it. Safe to call from an ISR.
+    pub fn notify(&self, bits: u32) {
+        self.signal.notify(bits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc, vec::Vec};
+    use core::cell::RefCell;
+
+    use super::*;
+    use crate::{
+        scheduler::{Scheduler, Task, TaskCommand, TaskContext},
+        sim,
+        timer::Duration,
+    };
+
+    type SharedNotifier = Rc<RefCell<Option<TaskNotifier>>>;
+    type PollLog = Rc<RefCell<Vec<u32>>>;
+
+    /// Records the notification bits seen on each poll, then waits again.
+    struct Waiter {
+        condition: WaitCondition,
+        timeout: Option<Duration>,
+        notifier: SharedNotifier,
+        polls: PollLog,
+    }
+
+    impl Task for Waiter {
+        fn name(&self) -> &'static str {
+            "waiter"
+        }
+
+        fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+            self.notifier
+                .borrow_mut()
+                .get_or_insert_with(|| ctx.notifier());
+            self.polls
+                .borrow_mut()
+                .push(ctx.take_notifications(u32::MAX));
+            TaskCommand::Wait(self.condition, self.timeout)
+        }
+    }
+
+    fn spawn_waiter(
+        scheduler: &mut Scheduler,
+        condition: WaitCondition,
+        timeout: Option<Duration>,
+    ) -> (SharedNotifier, PollLog) {
+        let notifier = Rc::new(RefCell::new(None));
+        let polls = Rc::new(RefCell::new(Vec::new()));
+        let task = Box::leak(Box::new(Waiter {
+            condition,
+            timeout,
+            notifier: notifier.clone(),
+            polls: polls.clone(),
+        }));
+        scheduler.spawn(task).unwrap();
+        (notifier, polls)
+    }
+
+    #[test]
+    fn event_group_wakes_waiting_task_without_tick() {
+        static EVENTS: EventGroup = EventGroup::new();
+        let _board = sim::board();
+        EVENTS.clear(u32::MAX);
+        let mut scheduler = Scheduler::new();
+        let (_, polls) = spawn_waiter(&mut scheduler, EVENTS.all(0b11), None);
+
+        scheduler.run_ready();
+        sim::clock::advance_ms(1_000);
+        EVENTS.set(0b01);
+        scheduler.run_ready();
+        scheduler.run_ready();
+        assert_eq!(polls.borrow().len(), 1);
+
+        EVENTS.set(0b10);
+        scheduler.run_ready();
+        assert_eq!(polls.borrow().len(), 2);
+    }
+
+    #[test]
+    fn notification_wakes_task_and_delivers_bits() {
+        let _board = sim::board();
+        let mut scheduler = Scheduler::new();
+        let (notifier, polls) =
+            spawn_waiter(&mut scheduler, WaitCondition::Notification(0b100), None);
+
+        scheduler.run_ready();
+        let notifier = notifier.borrow().clone().unwrap();
+        notifier.notify(0b001);
+        scheduler.run_ready();
+        assert_eq!(*polls.borrow(), [0]);
+
+        notifier.notify(0b100);
+        scheduler.run_ready();
+        assert_eq!(*polls.borrow(), [0, 0b101]);
+    }
+
+    fn queued(group: &EventGroup) -> usize {
+        critical_section::with(|cs| group.waiters.wakers.borrow_ref(cs).len())
+    }
+
+    /// Waits on `EVENTS` once, for `timeout`, then sleeps.
+    struct WaitOnce {
+        events: &'static EventGroup,
+        timeout: Option<Duration>,
+        waited: bool,
+    }
+
+    impl Task for WaitOnce {
+        fn name(&self) -> &'static str {
+            "wait-once"
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            if core::mem::replace(&mut self.waited, true) {
+                return TaskCommand::SleepMs(1_000);
+            }
+            TaskCommand::Wait(self.events.any(1), self.timeout)
+        }
+    }
+
+    #[test]
+    fn ended_waits_leave_the_queue() {
+        static EVENTS: EventGroup = EventGroup::new();
+        let _board = sim::board();
+        EVENTS.clear(u32::MAX);
+        let mut scheduler = Scheduler::new();
+        spawn_waiter(&mut scheduler, EVENTS.any(1), None);
+        let killed = scheduler
+            .spawn(Box::new(WaitOnce {
+                events: &EVENTS,
+                timeout: None,
+                waited: false,
+            }))
+            .unwrap();
+        scheduler
+            .spawn(Box::new(WaitOnce {
+                events: &EVENTS,
+                timeout: Some(Duration::from_millis(10)),
+                waited: false,
+            }))
+            .unwrap();
+
+        scheduler.run_ready();
+        assert_eq!(queued(&EVENTS), 3);
+
+        scheduler.kill(killed).unwrap();
+        assert_eq!(queued(&EVENTS), 2);
+
+        sim::clock::advance_ms(10);
+        scheduler.run_ready();
+        assert_eq!(queued(&EVENTS), 1);
+    }
+
+    #[test]
+    fn wait_times_out_without_event() {
+        static EVENTS: EventGroup = EventGroup::new();
+        let _board = sim::board();
+        let mut scheduler = Scheduler::new();
+        let (_, polls) = spawn_waiter(
+            &mut scheduler,
+            EVENTS.any(1),
+            Some(Duration::from_millis(10)),
+        );
+
+        scheduler.run_ready();
+        sim::clock::advance_ms(9);
+        scheduler.run_ready();
+        assert_eq!(polls.borrow().len(), 1);
+
+        sim::clock::advance_ms(1);
+        scheduler.run_ready();
+        assert_eq!(polls.borrow().len(), 2);
+    }
+}
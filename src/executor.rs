@@ -0,0 +1,382 @@
+//! Async task support.
+//!
+//! Lets the scheduler run `async` code next to hand-written [`Task`] state
+//! machines. Every task slot owns a [`TaskSignal`]; the [`Waker`] handed to a
+//! future (and exposed to poll-based tasks through [`TaskContext::waker`])
+//! sets that signal, and the scheduler polls a parked task again once its
+//! signal is set or its wakeup deadline passes.
+//!
+//! Timer futures such as [`sleep`] do not need a separate timer queue: while a
+//! task is being polled they record the earliest instant they need to be
+//! polled again, and [`AsyncTask`] parks the task until then.
+
+use alloc::{boxed::Box, sync::Arc, task::Wake};
+use core::{
+    cell::Cell,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+
+use critical_section::Mutex;
+
+use crate::{
+    scheduler::{Task, TaskCommand, TaskContext, TaskId, TaskPriority},
+    smp::{self, CoreId, PerCore},
+    timer::{Duration, Instant},
+};
+
+/// Wake flag shared between a task slot and the wakers handed out for it,
+/// plus the task's notification bits (see [`crate::event`]) and any priority
+/// it inherited from a task blocked on one of its locks (see [`crate::sync`]).
+///
+/// Setting the flag from another core also interrupts the task's core, in
+/// case it is idle.
+#[derive(Default)]
+pub struct TaskSignal {
+    woken: AtomicBool,
+    notified: AtomicU32,
+    /// Priorities lent by blocked tasks: how many loans there are at each
+    /// [`TaskPriority`] level.
+    lent: [AtomicU8; 3],
+    /// [`TaskPriority`] level plus one that the task lent the owner of the
+    /// lock it waits for, 0 if none.
+    lock_loan: AtomicU8,
+    /// [`CoreId`] index of the core running the task.
+    core: AtomicU8,
+    /// Id of the task, for tracing.
+    task: AtomicU32,
+}
+
+impl TaskSignal {
+    /// Record the task the signal belongs to and the core it runs on.
+    pub fn bind(&self, task: TaskId, core: CoreId) {
+        self.task.store(task, Ordering::Relaxed);
+        self.core.store(core.index() as u8, Ordering::Release);
+    }
+
+    /// Set the wake flag and interrupt the task's core if it is not this one.
+    fn wake(&self) {
+        crate::trace_event!(Wake {
+            task: self.task.load(Ordering::Relaxed)
+        });
+        self.woken.store(true, Ordering::Release);
+        let core = CoreId::from_index(self.core.load(Ordering::Acquire) as usize);
+        if core != smp::current_core() {
+            smp::interrupt_core(core);
+        }
+    }
+
+    /// Whether the task has been woken since it was last polled.
+    pub fn is_woken(&self) -> bool {
+        self.woken.load(Ordering::Acquire)
+    }
+
+    /// Consume a pending wakeup before the task is polled.
+    pub fn clear(&self) {
+        self.woken.store(false, Ordering::Release);
+    }
+
+    /// Set notification bits and wake the task.
+    pub fn notify(&self, bits: u32) {
+        self.notified.fetch_or(bits, Ordering::AcqRel);
+        self.wake();
+    }
+
+    /// Pending notification bits.
+    pub fn notifications(&self) -> u32 {
+        self.notified.load(Ordering::Acquire)
+    }
+
+    /// Clear the notification bits in `mask`, returning those that were set.
+    pub fn take_notifications(&self, mask: u32) -> u32 {
+        self.notified.fetch_and(!mask, Ordering::AcqRel) & mask
+    }
+
+    /// Raise the task to at least `priority` until the loan is withdrawn.
+    pub fn lend(&self, priority: TaskPriority) {
+        self.lent[priority as usize].fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Withdraw one loan made with [`Self::lend`].
+    pub fn withdraw(&self, priority: TaskPriority) {
+        let _ = self.lent[priority as usize].fetch_update(
+            Ordering::AcqRel,
+            Ordering::Acquire,
+            |loans| loans.checked_sub(1),
+        );
+    }
+
+    /// Priority the task lent the owner of the lock it waits for, if any.
+    pub fn lock_loan(&self) -> Option<TaskPriority> {
+        let level = usize::from(self.lock_loan.load(Ordering::Acquire)).checked_sub(1)?;
+        Some([TaskPriority::Low, TaskPriority::Normal, TaskPriority::High][level])
+    }
+
+    /// Record the priority the task lent the owner of the lock it waits for.
+    pub fn set_lock_loan(&self, priority: Option<TaskPriority>) {
+        let level = priority.map_or(0, |priority| priority as u8 + 1);
+        self.lock_loan.store(level, Ordering::Release);
+    }
+
+    /// Highest priority lent by blocked tasks, if any.
+    pub fn inherited_priority(&self) -> Option<TaskPriority> {
+        [TaskPriority::High, TaskPriority::Normal, TaskPriority::Low]
+            .into_iter()
+            .find(|&priority| self.lent[priority as usize].load(Ordering::Acquire) > 0)
+    }
+}
+
+impl Wake for TaskSignal {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        TaskSignal::wake(self);
+    }
+}
+
+/// Earliest wakeup requested by timer futures during the current poll on
+/// each core.
+static REQUESTED_WAKEUP: PerCore<Mutex<Cell<Option<Instant>>>> =
+    PerCore::new([const { Mutex::new(Cell::new(None)) }; smp::NUM_CORES]);
+
+/// Ask the scheduler to poll the current task again no later than `deadline`.
+pub fn request_wakeup(deadline: Instant) {
+    critical_section::with(|cs| {
+        let cell = REQUESTED_WAKEUP.get().borrow(cs);
+        let earliest = match cell.get() {
+            Some(current) => current.min(deadline),
+            None => deadline,
+        };
+        cell.set(Some(earliest));
+    });
+}
+
+fn take_requested_wakeup() -> Option<Instant> {
+    critical_section::with(|cs| REQUESTED_WAKEUP.get().borrow(cs).take())
+}
+
+/// Adapter running a future as a scheduler task.
+pub struct AsyncTask {
+    name: &'static str,
+    priority: TaskPriority,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+#[allow(dead_code, reason = "the firmware runs no AsyncTask")]
+impl AsyncTask {
+    /// Wrap `future` in a task with [`TaskPriority::Normal`].
+    pub fn new(name: &'static str, future: impl Future<Output = ()> + 'static) -> Self {
+        Self {
+            name,
+            priority: TaskPriority::default(),
+            future: Box::pin(future),
+        }
+    }
+
+    /// Set the task priority.
+    pub fn with_priority(mut self, priority: TaskPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+impl Task for AsyncTask {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn priority(&self) -> TaskPriority {
+        self.priority
+    }
+
+    fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+        let _ = take_requested_wakeup();
+        let mut cx = Context::from_waker(ctx.waker());
+        match self.future.as_mut().poll(&mut cx) {
+            Poll::Ready(()) => TaskCommand::Finished,
+            Poll::Pending => TaskCommand::Park(take_requested_wakeup()),
+        }
+    }
+}
+
+/// Future returned by [`sleep`] and [`sleep_until`].
+pub struct Sleep {
+    deadline: Instant,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            Poll::Ready(())
+        } else {
+            request_wakeup(self.deadline);
+            Poll::Pending
+        }
+    }
+}
+
+/// Suspend the current async task for at least `ms` milliseconds.
+#[allow(dead_code, reason = "for futures run by AsyncTask")]
+pub fn sleep(ms: u32) -> Sleep {
+    sleep_for(Duration::from_millis(ms as u64))
+}
+
+/// Suspend the current async task for at least `duration`.
+#[allow(dead_code, reason = "for futures run by AsyncTask")]
+pub fn sleep_for(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration.max(Duration::from_ticks(1)))
+}
+
+/// Suspend the current async task until `deadline`.
+#[allow(dead_code, reason = "for futures run by AsyncTask")]
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline }
+}
+
+/// Future returned by [`yield_now`].
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Let other ready tasks run before continuing.
+#[allow(dead_code, reason = "for futures run by AsyncTask")]
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{rc::Rc, vec::Vec};
+    use core::{cell::RefCell, task::Waker};
+
+    use super::*;
+    use crate::{scheduler::Scheduler, sim};
+
+    type Log = Rc<RefCell<Vec<u64>>>;
+
+    fn spawn(scheduler: &mut Scheduler, task: impl Task + 'static) {
+        scheduler.spawn(Box::leak(Box::new(task))).unwrap();
+    }
+
+    #[test]
+    fn async_sleep_resumes_at_deadline() {
+        let _board = sim::board();
+        let log = Log::default();
+        let task_log = log.clone();
+        let mut scheduler = Scheduler::new();
+        spawn(
+            &mut scheduler,
+            AsyncTask::new("sleeper", async move {
+                for _ in 0..3 {
+                    task_log.borrow_mut().push(Instant::now().ticks());
+                    sleep(10).await;
+                }
+            }),
+        );
+
+        for _ in 0..40 {
+            scheduler.run_ready();
+            sim::clock::advance(1);
+        }
+
+        assert_eq!(*log.borrow(), [0, 10, 20]);
+    }
+
+    /// Poll-based task that wakes a parked waiter on its second poll.
+    struct Notifier {
+        waiter: Rc<RefCell<Option<Waker>>>,
+        polls: u32,
+    }
+
+    impl Task for Notifier {
+        fn name(&self) -> &'static str {
+            "notifier"
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            self.polls += 1;
+            if self.polls == 2 {
+                if let Some(waker) = self.waiter.borrow_mut().take() {
+                    waker.wake();
+                }
+            }
+            TaskCommand::SleepMs(100)
+        }
+    }
+
+    #[test]
+    fn waker_unparks_async_task_without_time_passing() {
+        let _board = sim::board();
+        let waiter = Rc::new(RefCell::new(None::<Waker>));
+        let log = Log::default();
+        let (task_waiter, task_log) = (waiter.clone(), log.clone());
+        let mut scheduler = Scheduler::new();
+        spawn(
+            &mut scheduler,
+            AsyncTask::new(
+                "waiter",
+                core::future::poll_fn(move |cx| {
+                    task_log.borrow_mut().push(Instant::now().ticks());
+                    *task_waiter.borrow_mut() = Some(cx.waker().clone());
+                    Poll::<()>::Pending
+                }),
+            )
+            .with_priority(TaskPriority::High),
+        );
+        spawn(&mut scheduler, Notifier { waiter, polls: 0 });
+
+        scheduler.run_ready();
+        sim::clock::advance_ms(50);
+        scheduler.run_ready();
+        assert_eq!(log.borrow().len(), 1);
+
+        sim::clock::advance_ms(50);
+        scheduler.run_ready();
+        scheduler.run_ready();
+        assert_eq!(*log.borrow(), [0, 100]);
+    }
+
+    #[test]
+    fn yield_now_lets_other_tasks_run() {
+        let _board = sim::board();
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let mut scheduler = Scheduler::new();
+        for name in ["a", "b"] {
+            let order = order.clone();
+            spawn(
+                &mut scheduler,
+                AsyncTask::new(name, async move {
+                    for _ in 0..2 {
+                        order.borrow_mut().push(name);
+                        yield_now().await;
+                    }
+                }),
+            );
+        }
+
+        for _ in 0..3 {
+            scheduler.run_ready();
+        }
+
+        assert_eq!(*order.borrow(), ["a", "b", "a", "b"]);
+    }
+}
@@ -7,6 +7,8 @@ use core::{
 
 use esp_alloc::EspHeap;
 
+use crate::syscall::HeapStats;
+
 /// Size of the global heap (in bytes).
 const HEAP_SIZE: usize = 64 * 1024;
 
@@ -34,6 +36,14 @@ pub unsafe fn init() {
     }
 }
 
+/// Bytes allocated and left on the heap.
+pub fn stats() -> HeapStats {
+    HeapStats {
+        used: GLOBAL_ALLOCATOR.used(),
+        free: GLOBAL_ALLOCATOR.free(),
+    }
+}
+
 #[alloc_error_handler]
 fn alloc_error(layout: Layout) -> ! {
     panic!("Allocation error: {:?}", layout)
@@ -32,7 +32,6 @@ impl From<InterruptPriority> for Priority {
 ///
 /// # Safety
 /// The handler must be an `extern "C"` function that follows ISR safety rules.
-#[allow(dead_code)]
 pub unsafe fn register_handler(
     interrupt: Interrupt,
     handler: extern "C" fn(),
@@ -43,7 +42,6 @@ pub unsafe fn register_handler(
 }
 
 /// Disable a previously enabled peripheral interrupt.
-#[allow(dead_code)]
 pub fn disable_interrupt(interrupt: Interrupt) {
     interrupt::disable(Cpu::current(), interrupt);
 }
@@ -56,7 +54,6 @@ pub struct CriticalSectionGuard {
 impl CriticalSectionGuard {
     /// Enter a critical section, returning a guard that will restore the
     /// previous interrupt state when dropped.
-    #[allow(dead_code)]
     pub fn new() -> Self {
         let state = unsafe { critical_section::acquire() };
         Self { state }
@@ -70,13 +67,11 @@ impl Drop for CriticalSectionGuard {
 }
 
 /// Enter a critical section using RAII semantics.
-#[allow(dead_code)]
 pub fn enter_critical() -> CriticalSectionGuard {
     CriticalSectionGuard::new()
 }
 
 /// Execute the provided closure with interrupts disabled.
-#[allow(dead_code)]
 pub fn with_critical<F, R>(f: F) -> R
 where
     F: FnOnce() -> R,
@@ -1,41 +1,110 @@
-#![no_std]
-#![no_main]
-#![feature(alloc_error_handler)]
+#![cfg_attr(not(feature = "sim"), no_std)]
+#![cfg_attr(not(feature = "sim"), no_main)]
+#![cfg_attr(not(feature = "sim"), feature(alloc_error_handler))]
+
+#[cfg(all(feature = "esp32", feature = "sim"))]
+compile_error!("features `esp32` and `sim` are mutually exclusive");
+#[cfg(not(any(feature = "esp32", feature = "sim")))]
+compile_error!("enable either the `esp32` or the `sim` feature");
 
 extern crate alloc;
 
-use core::mem::MaybeUninit;
+#[cfg(feature = "esp32")]
 use esp_backtrace as _;
+#[cfg(feature = "esp32")]
 use esp_bootloader_esp_idf::esp_app_desc;
+#[cfg(feature = "esp32")]
 use esp_hal::{
-    gpio::{Input, InputConfig, Pull},
+    gpio::{Input, InputConfig, Io, Pull},
+    system::{CpuControl, Stack},
     xtensa_lx_rt::entry,
 };
 
+mod arch;
 mod bootloader_info;
+mod channel;
+mod console;
 mod drivers;
+mod event;
+mod executor;
+#[cfg(feature = "esp32")]
 mod frames;
+#[cfg(feature = "esp32")]
 mod heap;
+#[cfg(feature = "esp32")]
 mod interrupts; // Provides DefaultHandler for interrupt stubs
 mod ml;
+#[cfg(feature = "esp32")]
 mod oled;
+mod os;
+mod policy;
+mod pool;
+mod preempt;
 mod scheduler;
+#[cfg(feature = "sim")]
+mod sim;
+mod smp;
+mod soft_timer;
 mod stack;
+mod stats;
+mod supervisor;
+mod sync;
 mod syscall;
 mod task;
+mod task_local;
+mod thread;
 mod timer;
+mod trace;
+mod watchdog;
+
+use alloc::boxed::Box;
 
 use bootloader_info::{get_app_info, get_partition_info};
-use drivers::{gpio, i2c, oled as oled_driver, uart, DriverError};
+#[cfg(feature = "esp32")]
+use drivers::i2c;
+use drivers::{gpio, oled as oled_driver, uart, DriverError};
+#[cfg(feature = "esp32")]
+use pool::StaticCell;
 use scheduler::Scheduler;
+use supervisor::{ChildSpec, RestartPolicy, Strategy, Supervisor};
+use syscall::DeviceHandle;
 use task::{LedTask, MlTask, UiTask};
+#[cfg(feature = "esp32")]
 esp_app_desc!(); // defaults are fine
 
-static mut SCHEDULER: Scheduler = Scheduler::new();
-static mut UI_TASK: MaybeUninit<UiTask> = MaybeUninit::uninit();
-static mut LED_TASK: MaybeUninit<LedTask> = MaybeUninit::uninit();
-static mut ML_TASK: MaybeUninit<MlTask> = MaybeUninit::uninit();
+#[cfg(feature = "esp32")]
+static SCHEDULER: StaticCell<Scheduler> = StaticCell::new();
+
+/// Scheduler of the APP core.
+#[cfg(feature = "esp32")]
+static APP_SCHEDULER: StaticCell<Scheduler> = StaticCell::new();
+
+/// Stack the APP core starts on.
+#[cfg(feature = "esp32")]
+static APP_CORE_STACK: StaticCell<Stack<APP_CORE_STACK_SIZE>> = StaticCell::new();
+
+#[cfg(feature = "esp32")]
+const APP_CORE_STACK_SIZE: usize = 16 * 1024;
+
+/// How often the firmware prints per-task CPU usage, and with the `trace`
+/// feature the trace buffer.
+#[cfg(feature = "esp32")]
+const STATS_REPORT_INTERVAL: timer::Duration = timer::Duration::from_millis(10_000);
 
+/// How the system timer drives the tick: periodic interrupts unless built
+/// with the `tickless` feature.
+#[cfg(feature = "esp32")]
+const TICK_MODE: timer::TickMode = if cfg!(feature = "tickless") {
+    timer::TickMode::Tickless
+} else {
+    timer::TickMode::Periodic
+};
+
+/// Virtual time the hosted simulation runs for before exiting.
+#[cfg(feature = "sim")]
+const SIM_RUN_MS: u32 = 5_000;
+
+#[cfg(feature = "esp32")]
 fn spin_delay_ms(ms: u32) {
     const INNER_LOOPS: u32 = 25_000;
     for _ in 0..ms {
@@ -47,11 +116,64 @@ fn spin_delay_ms(ms: u32) {
 
 fn log_driver_error(name: &str, err: DriverError) {
     match err {
-        DriverError::InitFailed(reason) => esp_println::println!("{} init failed: {}", name, reason),
-        other => esp_println::println!("{} init failed: {:?}", name, other),
+        DriverError::InitFailed(reason) => println!("{} init failed: {}", name, reason),
+        other => println!("{} init failed: {:?}", name, other),
+    }
+}
+
+/// Main loop of the APP core: runs the tasks [`smp::spawn`] places there
+/// until `running` returns false.
+fn app_core_loop(scheduler: &mut Scheduler, running: impl Fn() -> bool) {
+    smp::init_core();
+    scheduler.set_idle_hook(timer::set_alarm);
+    while running() {
+        scheduler.run_ready();
+        smp::report_health(scheduler.watchdog_healthy());
+        scheduler.idle();
     }
 }
 
+/// Make the initialised drivers available to tasks through [`os::open`].
+fn register_devices(led: Option<gpio::LedHandle>, buttons: Option<gpio::ButtonsHandle>) {
+    if let Some(handle) = led {
+        syscall::register_device(DeviceHandle::Led(handle));
+    }
+    if let Some(handle) = buttons {
+        syscall::register_device(DeviceHandle::Buttons(handle));
+    }
+}
+
+/// UI and LED tasks under one supervisor. The UI drives the LED through its
+/// command channel, so a failure of either restarts both.
+fn ui_supervisor(
+    display: Option<oled_driver::OledHandle>,
+    buttons: gpio::ButtonsHandle,
+    led: gpio::LedHandle,
+) -> Supervisor {
+    let policy = RestartPolicy::Backoff {
+        initial: timer::Duration::from_millis(100),
+        max: timer::Duration::from_millis(5_000),
+    };
+    let window = timer::Duration::from_millis(60_000);
+    let app_info = get_app_info();
+    let ui = ChildSpec::new(move || {
+        let partitions = get_partition_info();
+        let task = UiTask::new(
+            display,
+            buttons,
+            app_info.name,
+            app_info.version,
+            partitions,
+        );
+        Box::new(task).into()
+    });
+    let led = ChildSpec::new(move || Box::new(LedTask::new(led)).into());
+    Supervisor::new("ui-group", Strategy::OneForAll)
+        .with_child(ui.with_policy(policy).with_limit(5, window))
+        .with_child(led.with_policy(policy).with_limit(5, window))
+}
+
+#[cfg(feature = "esp32")]
 #[entry]
 fn main() -> ! {
     let peripherals = esp_hal::init(esp_hal::Config::default());
@@ -66,13 +188,16 @@ fn main() -> ! {
         GPIO19,
         GPIO21,
         GPIO22,
+        IO_MUX,
         TIMG0,
+        TIMG1,
+        CPU_CTRL,
         ..
     } = peripherals;
 
     // Initialize system tick timer (1 kHz)
-    if let Err(err) = unsafe { timer::init(TIMG0) } {
-        esp_println::println!("Timer init failed: {}", err);
+    if let Err(err) = unsafe { timer::init(TIMG0, TICK_MODE) } {
+        println!("Timer init failed: {}", err);
     }
 
     if let Err(err) = uart::init_uart() {
@@ -87,18 +212,29 @@ fn main() -> ! {
         }
     };
 
-    let scroll_up = Input::new(GPIO18, InputConfig::default().with_pull(Pull::Up));
-    let scroll_down = Input::new(GPIO19, InputConfig::default().with_pull(Pull::Up));
-    let select_button = Input::new(GPIO5, InputConfig::default().with_pull(Pull::Up));
+    let mut io = Io::new(IO_MUX);
+    let buttons = gpio::Buttons {
+        scroll_up: Input::new(GPIO18, InputConfig::default().with_pull(Pull::Up)),
+        scroll_down: Input::new(GPIO19, InputConfig::default().with_pull(Pull::Up)),
+        select: Input::new(GPIO5, InputConfig::default().with_pull(Pull::Up)),
+    };
+    let buttons_handle = match gpio::init_buttons(&mut io, buttons) {
+        Ok(handle) => Some(handle),
+        Err(err) => {
+            log_driver_error("Buttons", err);
+            None
+        }
+    };
+    register_devices(led_handle, buttons_handle);
 
-    esp_println::println!("Initializing I2C0 for OLED display...");
+    println!("Initializing I2C0 for OLED display...");
     let i2c_handle = match i2c::init_i2c0(I2C0, GPIO21, GPIO22) {
         Ok(handle) => {
-            esp_println::println!("I2C0 initialised");
+            println!("I2C0 initialised");
             Some(handle)
         }
         Err(err) => {
-            esp_println::println!("I2C initialization failed: {:?}", err);
+            println!("I2C initialization failed: {:?}", err);
             None
         }
     };
@@ -106,7 +242,7 @@ fn main() -> ! {
     let oled_handle: Option<oled_driver::OledHandle> = if let Some(ref handle) = i2c_handle {
         match oled_driver::init_oled(handle) {
             Ok(display_handle) => {
-                esp_println::println!("OLED display initialized");
+                println!("OLED display initialized");
                 Some(display_handle)
             }
             Err(err) => {
@@ -132,45 +268,162 @@ fn main() -> ! {
 
     ml::init();
 
-    esp_println::println!("hello from no_std on ESP32!");
-    esp_println::println!("App: {} v{}", app_info.name, app_info.version);
-    esp_println::println!("Partitions:");
+    println!("hello from no_std on ESP32!");
+    println!("App: {} v{}", app_info.name, app_info.version);
+    println!("Partitions:");
     for part in &partitions {
-        esp_println::println!("  {}: {}", part.name, part.size);
-    }
-
-    #[allow(static_mut_refs)]
-    unsafe {
-        let scheduler = &mut SCHEDULER;
-
-        if let Some(ui_led) = led_handle {
-            let ui_display = oled_handle.clone();
-            let ui_task: &mut dyn scheduler::Task = UI_TASK.write(UiTask::new(
-                ui_display,
-                ui_led,
-                scroll_up,
-                scroll_down,
-                select_button,
-                app_info.name,
-                app_info.version,
-                partitions,
-            ));
-            let _ = scheduler.spawn(ui_task);
-
-            let led_task: &mut dyn scheduler::Task = LED_TASK.write(LedTask::new(ui_led));
-            let _ = scheduler.spawn(led_task);
-        } else {
-            esp_println::println!("Skipping UI/LED tasks: LED handle unavailable");
-        }
+        println!("  {}: {}", part.name, part.size);
+    }
+
+    smp::init_core();
+    let scheduler = SCHEDULER.init(Scheduler::new());
+    scheduler.set_idle_hook(timer::set_alarm);
+
+    let mut cpu_control = CpuControl::new(CPU_CTRL);
+    let app_core = cpu_control.start_app_core(APP_CORE_STACK.init(Stack::new()), || {
+        let scheduler = APP_SCHEDULER.init(Scheduler::new().on_core(smp::CoreId::App));
+        app_core_loop(scheduler, || true);
+    });
+    if let Err(err) = &app_core {
+        println!("APP core start failed: {:?}", err);
+    }
+
+    if let (Some(ui_led), Some(ui_buttons)) = (led_handle, buttons_handle) {
+        let _ = scheduler.spawn(Box::new(ui_supervisor(oled_handle, ui_buttons, ui_led)));
+    } else {
+        println!("Skipping UI/LED tasks: LED or button handle unavailable");
+    }
+
+    let _ = smp::spawn(Box::new(MlTask::new()));
+    let _ = scheduler.spawn(Box::new(soft_timer::TIMERS.task()));
 
-        let ml_task: &mut dyn scheduler::Task = ML_TASK.write(MlTask::new());
-        let _ = scheduler.spawn(ml_task);
+    if let Err(err) = watchdog::init(TIMG1) {
+        println!("Watchdog init failed: {}", err);
     }
 
+    let mut next_report = timer::Instant::now() + STATS_REPORT_INTERVAL;
     loop {
-        #[allow(static_mut_refs)]
-        unsafe {
-            SCHEDULER.run_ready();
+        scheduler.run_ready();
+
+        if scheduler.watchdog_healthy() & smp::other_cores_healthy() {
+            watchdog::feed();
         }
+
+        if timer::Instant::now() >= next_report {
+            println!("{}", scheduler.stats());
+            scheduler.reset_stats();
+            #[cfg(feature = "trace")]
+            trace::export();
+            next_report += STATS_REPORT_INTERVAL;
+        }
+
+        scheduler.idle();
+    }
+}
+
+/// Hosted entry point: boots the kernel on simulated peripherals and runs the
+/// scheduler against the virtual clock.
+#[cfg(feature = "sim")]
+fn main() {
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use sim::{clock, cores, display::OledDisplay, gpio::Input};
+
+    static APP_CORE_RUNNING: AtomicBool = AtomicBool::new(true);
+
+    if let Err(err) = uart::init_uart() {
+        log_driver_error("UART", err);
     }
+
+    let led_handle = match gpio::init_led(gpio::Output::new()) {
+        Ok(handle) => Some(handle),
+        Err(err) => {
+            log_driver_error("LED", err);
+            None
+        }
+    };
+
+    let buttons = gpio::Buttons {
+        scroll_up: Input::pulled_up(),
+        scroll_down: Input::pulled_up(),
+        select: Input::pulled_up(),
+    };
+    let buttons_handle = match gpio::init_buttons(buttons) {
+        Ok(handle) => Some(handle),
+        Err(err) => {
+            log_driver_error("Buttons", err);
+            None
+        }
+    };
+    register_devices(led_handle, buttons_handle);
+
+    let oled_handle = match oled_driver::init_oled(OledDisplay::new()) {
+        Ok(handle) => Some(handle),
+        Err(err) => {
+            log_driver_error("OLED", err);
+            None
+        }
+    };
+
+    if let Some(handle) = &oled_handle {
+        let _ = handle.try_with(|display| display.show_boot_progress("Starting..."));
+    }
+
+    let app_info = get_app_info();
+    if let Some(handle) = &oled_handle {
+        let _ = handle.try_with(|display| display.show_app_info(app_info.name, app_info.version));
+        let _ = handle.try_with(|display| display.play_boot_animation(clock::advance_ms));
+    }
+
+    ml::init();
+
+    println!("hello from the hosted simulation!");
+    println!("App: {} v{}", app_info.name, app_info.version);
+
+    let mut scheduler = Scheduler::new();
+    scheduler.set_idle_hook(timer::set_alarm);
+
+    if let (Some(ui_led), Some(ui_buttons)) = (led_handle, buttons_handle) {
+        let _ = scheduler.spawn(Box::new(ui_supervisor(oled_handle, ui_buttons, ui_led)));
+    }
+    let _ = scheduler.spawn(Box::new(soft_timer::TIMERS.task()));
+
+    let app_core = cores::start_core(smp::CoreId::App, || {
+        let mut scheduler = Scheduler::new().on_core(smp::CoreId::App);
+        app_core_loop(&mut scheduler, || APP_CORE_RUNNING.load(Ordering::Acquire));
+        println!("{}", scheduler.stats());
+    });
+    let _ = smp::spawn(Box::new(MlTask::new()));
+
+    if let Err(err) = watchdog::init() {
+        println!("Watchdog init failed: {}", err);
+    }
+
+    while timer::get_ticks() < timer::ms_to_ticks(SIM_RUN_MS) {
+        scheduler.run_ready();
+        if scheduler.watchdog_healthy() & smp::other_cores_healthy() {
+            watchdog::feed();
+        }
+        if sim::watchdog::expired() {
+            println!("watchdog reset");
+            break;
+        }
+        if sim::system::reset_requested() {
+            println!("software reset");
+            break;
+        }
+        // Idling advances the virtual clock by a tick; otherwise do it here.
+        if !scheduler.idle() {
+            clock::advance(1);
+        }
+    }
+
+    APP_CORE_RUNNING.store(false, Ordering::Release);
+    smp::interrupt_core(smp::CoreId::App);
+    let _ = app_core.join();
+
+    println!("{}", scheduler.stats());
+    #[cfg(feature = "trace")]
+    trace::export();
+    println!("simulation finished after {} ticks", timer::get_ticks());
 }
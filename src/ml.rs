@@ -4,8 +4,10 @@ use core::fmt;
 
 /// Fixed-point number type (Q15.16 format: 15 integer bits, 16 fractional bits)
 #[derive(Clone, Copy)]
+#[allow(dead_code, reason = "building blocks for the inference model")]
 pub struct FixedPoint(i32);
 
+#[allow(dead_code, reason = "building blocks for the inference model")]
 impl FixedPoint {
     /// Create from integer value
     pub const fn from_int(val: i16) -> Self {
@@ -42,6 +44,7 @@ impl fmt::Display for FixedPoint {
 }
 
 /// Simple matrix-vector multiplication (for neural network inference)
+#[allow(dead_code, reason = "building blocks for the inference model")]
 pub fn matvec_mult(
     weights: &[FixedPoint],
     input: &[FixedPoint],
@@ -18,7 +18,7 @@ use ssd1306::{
     I2CDisplayInterface, Ssd1306,
 };
 
-use crate::{frames, i2c::I2cBus};
+use crate::{drivers::i2c::I2cBus, frames};
 
 /// Convenience result type for OLED operations.
 pub type OledResult<T> = Result<T, DisplayError>;
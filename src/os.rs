@@ -0,0 +1,262 @@
+//! Application interface to the kernel.
+//!
+//! Typed wrappers over [`handle_syscall`] for use by application tasks, so
+//! they need not reach into the scheduler or driver statics. Calls that block
+//! suspend the calling [`ThreadTask`](crate::thread::ThreadTask); from other
+//! tasks they fail with [`Error::WouldBlock`] (see [`crate::syscall`]).
+#![allow(dead_code, reason = "the firmware's tasks predate this API")]
+
+use crate::{
+    channel::Channel,
+    scheduler::{JoinHandle, TaskBox, TaskId},
+    syscall::{handle_syscall, Syscall, SyscallResult},
+    timer::{Duration, Instant},
+};
+
+pub use crate::syscall::{Device, Fd, HeapStats, SyscallError as Error};
+
+/// Start `task` on the caller's scheduler after the current pass.
+pub fn spawn(task: impl Into<TaskBox>) -> Result<JoinHandle, Error> {
+    match handle_syscall(Syscall::Spawn(task.into()))? {
+        SyscallResult::Spawned(handle) => Ok(handle),
+        _ => unreachable!(),
+    }
+}
+
+/// End the calling thread with `code`.
+///
+/// # Panics
+///
+/// If the caller is not a thread task.
+pub fn exit(code: i32) -> ! {
+    match handle_syscall(Syscall::Exit(code)) {
+        Err(err) => panic!("os::exit outside a thread: {:?}", err),
+        Ok(_) => unreachable!(),
+    }
+}
+
+/// Let other ready tasks run.
+pub fn yield_now() -> Result<(), Error> {
+    handle_syscall(Syscall::Yield).map(drop)
+}
+
+/// Block for at least `duration`.
+pub fn sleep(duration: Duration) -> Result<(), Error> {
+    handle_syscall(Syscall::Sleep(duration)).map(drop)
+}
+
+/// Current system time.
+pub fn now() -> Instant {
+    match handle_syscall(Syscall::Time) {
+        Ok(SyscallResult::Time(now)) => now,
+        _ => unreachable!(),
+    }
+}
+
+/// Id of the calling task, or `None` outside a task.
+pub fn task_id() -> Option<TaskId> {
+    match handle_syscall(Syscall::TaskId) {
+        Ok(SyscallResult::TaskId(id)) => Some(id),
+        _ => None,
+    }
+}
+
+/// Send `msg` on `channel`, blocking while it is full. Returns the message
+/// if it could not be sent.
+pub fn send<T: Send + 'static, const N: usize>(
+    channel: &'static Channel<T, N>,
+    msg: T,
+) -> Result<(), T> {
+    let mut msg = Some(msg);
+    match handle_syscall(Syscall::ChannelSend {
+        channel,
+        msg: &mut msg,
+    }) {
+        Ok(_) => Ok(()),
+        // The call leaves an unsent message in place.
+        Err(_) => Err(msg.unwrap()),
+    }
+}
+
+/// Receive from `channel`, blocking while it is empty. Returns `None` if the
+/// caller cannot block and nothing is queued.
+pub fn recv<T: Send + 'static, const N: usize>(channel: &'static Channel<T, N>) -> Option<T> {
+    let mut msg = None;
+    handle_syscall(Syscall::ChannelRecv {
+        channel,
+        msg: &mut msg,
+    })
+    .ok()?;
+    msg
+}
+
+/// Get a descriptor for `device`.
+pub fn open(device: Device) -> Result<Fd, Error> {
+    match handle_syscall(Syscall::DriverOpen(device))? {
+        SyscallResult::Fd(fd) => Ok(fd),
+        _ => unreachable!(),
+    }
+}
+
+/// Read from `fd` into `buf`, returning the bytes read.
+pub fn read(fd: Fd, buf: &mut [u8]) -> Result<usize, Error> {
+    match handle_syscall(Syscall::DriverRead { fd, buf })? {
+        SyscallResult::Bytes(count) => Ok(count),
+        _ => unreachable!(),
+    }
+}
+
+/// Write `buf` to `fd`, returning the bytes written.
+pub fn write(fd: Fd, buf: &[u8]) -> Result<usize, Error> {
+    match handle_syscall(Syscall::DriverWrite { fd, buf })? {
+        SyscallResult::Bytes(count) => Ok(count),
+        _ => unreachable!(),
+    }
+}
+
+/// Heap usage.
+pub fn heap_stats() -> HeapStats {
+    match handle_syscall(Syscall::HeapStats) {
+        Ok(SyscallResult::HeapStats(stats)) => stats,
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc, vec::Vec};
+    use core::cell::RefCell;
+
+    use super::*;
+    use crate::{
+        scheduler::{ExitStatus, Scheduler, Task, TaskCommand, TaskContext},
+        sim,
+        thread::ThreadTask,
+    };
+
+    type Log = Rc<RefCell<Vec<(&'static str, u64)>>>;
+
+    fn record(log: &Log, what: &'static str, value: u64) {
+        log.borrow_mut().push((what, value));
+    }
+
+    fn run(scheduler: &mut Scheduler, ms: u32) {
+        for _ in 0..ms {
+            scheduler.run_ready();
+            sim::clock::advance(1);
+        }
+    }
+
+    #[test]
+    fn thread_spawns_child_that_sleeps_and_exits() {
+        let _board = sim::board();
+        let log = Log::default();
+        let parent_log = log.clone();
+        let mut scheduler = Scheduler::new();
+        let parent = ThreadTask::new("parent", move || {
+            let child_log = parent_log.clone();
+            let child = ThreadTask::new("child", move || {
+                sleep(Duration::from_millis(5)).unwrap();
+                record(&child_log, "child", now().ticks());
+                exit(7);
+            });
+            let handle = spawn(Box::new(child)).unwrap();
+            record(&parent_log, "parent", task_id().unwrap() as u64);
+            while handle.try_join().is_none() {
+                yield_now().unwrap();
+            }
+            record(&parent_log, "joined", now().ticks());
+            assert_eq!(handle.try_join(), Some(ExitStatus::Exited(7)));
+        });
+        let parent_id = scheduler.spawn(Box::new(parent)).unwrap();
+
+        run(&mut scheduler, 10);
+
+        // The child starts on the pass after the spawn, and the parent sees
+        // it finish on the pass after that.
+        assert_eq!(
+            *log.borrow(),
+            [("parent", parent_id as u64), ("child", 6), ("joined", 7)]
+        );
+        assert_eq!(scheduler.task_count(), 0);
+    }
+
+    #[test]
+    fn threads_block_on_full_and_empty_channels() {
+        static CHANNEL: Channel<u32, 1> = Channel::new();
+        let _board = sim::board();
+        let log = Log::default();
+        let mut scheduler = Scheduler::new();
+        let producer_log = log.clone();
+        let producer = ThreadTask::new("producer", move || {
+            for value in 1..=3 {
+                send(&CHANNEL, value).unwrap();
+                record(&producer_log, "sent", value as u64);
+            }
+        });
+        let consumer_log = log.clone();
+        let consumer = ThreadTask::new("consumer", move || {
+            for _ in 0..3 {
+                sleep(Duration::from_millis(2)).unwrap();
+                let value = recv(&CHANNEL).unwrap();
+                record(&consumer_log, "received", value as u64);
+            }
+        });
+        scheduler.spawn(Box::new(producer)).unwrap();
+        scheduler.spawn(Box::new(consumer)).unwrap();
+
+        run(&mut scheduler, 10);
+
+        assert_eq!(
+            *log.borrow(),
+            [
+                ("sent", 1),
+                ("received", 1),
+                ("sent", 2),
+                ("received", 2),
+                ("sent", 3),
+                ("received", 3),
+            ]
+        );
+        assert_eq!(scheduler.task_count(), 0);
+    }
+
+    /// Makes blocking calls from a poll-based task.
+    struct Poller {
+        results: Rc<RefCell<Vec<Result<(), Error>>>>,
+    }
+
+    impl Task for Poller {
+        fn name(&self) -> &'static str {
+            "poller"
+        }
+
+        fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+            static EMPTY: Channel<u8, 1> = Channel::new();
+            let mut results = self.results.borrow_mut();
+            results.push(yield_now());
+            results.push(sleep(Duration::from_millis(1)));
+            results.push(recv(&EMPTY).ok_or(Error::WouldBlock).map(drop));
+            assert_eq!(task_id(), Some(ctx.id));
+            TaskCommand::Finished
+        }
+    }
+
+    #[test]
+    fn blocking_calls_fail_outside_threads() {
+        let _board = sim::board();
+        let results = Rc::default();
+        let mut scheduler = Scheduler::new();
+        let poller = Poller {
+            results: Rc::clone(&results),
+        };
+        scheduler.spawn(Box::new(poller)).unwrap();
+
+        scheduler.run_ready();
+
+        assert_eq!(*results.borrow(), [Err(Error::WouldBlock); 3]);
+        assert_eq!(task_id(), None);
+        let thread = ThreadTask::new("orphan", || {});
+        assert!(matches!(spawn(Box::new(thread)), Err(Error::NotInTask)));
+    }
+}
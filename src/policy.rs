@@ -0,0 +1,194 @@
+//! Scheduling policies.
+//!
+//! A [`SchedulingPolicy`] decides in which order [`Scheduler::run_ready`]
+//! polls the tasks that are ready in a pass. The policy is a type parameter of
+//! the [`Scheduler`], so each product picks one at build time:
+//!
+//! - [`FixedPriority`] (the default): highest [`TaskPriority`] first; among
+//!   equal priorities the task due earliest, then round-robin.
+//! - [`EarliestDeadlineFirst`]: the task whose [`Task::deadline`] expires
+//!   first.
+//! - [`RateMonotonic`]: the task with the shortest [`Task::period`] first.
+//!
+//! Tasks that do not declare a deadline or period run after those that do, in
+//! fixed-priority order. Priority inheritance through [`crate::sync::Mutex`]
+//! only affects that fixed-priority order.
+//!
+//! [`Scheduler`]: crate::scheduler::Scheduler
+//! [`Scheduler::run_ready`]: crate::scheduler::Scheduler::run_ready
+//! [`Task::deadline`]: crate::scheduler::Task::deadline
+//! [`Task::period`]: crate::scheduler::Task::period
+
+use core::cmp::Ordering;
+
+use crate::{
+    scheduler::TaskPriority,
+    timer::{Duration, Instant},
+};
+
+/// What a policy knows about a task when ordering a pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskInfo {
+    /// Priority including any inherited through a mutex.
+    pub priority: TaskPriority,
+    /// When the task is due to run. Tasks parked on an event have a wakeup
+    /// time in the future (or [`Instant::MAX`]) until their timeout.
+    pub release: Instant,
+    /// Absolute deadline of the current activation, if the task has one.
+    pub deadline: Option<Instant>,
+    /// Activation period, if the task is periodic.
+    pub period: Option<Duration>,
+    /// Position in the round-robin queue; the least recently polled task has
+    /// the lowest value.
+    pub turn: u64,
+}
+
+/// Order in which ready tasks are polled.
+pub trait SchedulingPolicy {
+    /// `Less` if `a` should be polled before `b`.
+    fn compare(&self, a: &TaskInfo, b: &TaskInfo) -> Ordering;
+}
+
+/// Highest priority first. Equal priorities run in order of release, so
+/// tasks due now go ahead of ones an event may wake later in the pass, and
+/// then round-robin.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FixedPriority;
+
+impl SchedulingPolicy for FixedPriority {
+    fn compare(&self, a: &TaskInfo, b: &TaskInfo) -> Ordering {
+        b.priority
+            .cmp(&a.priority)
+            .then_with(|| a.release.cmp(&b.release))
+            .then_with(|| a.turn.cmp(&b.turn))
+    }
+}
+
+/// Earliest absolute deadline first.
+#[derive(Debug, Clone, Copy, Default)]
+#[allow(dead_code, reason = "the firmware uses FixedPriority")]
+pub struct EarliestDeadlineFirst;
+
+impl SchedulingPolicy for EarliestDeadlineFirst {
+    fn compare(&self, a: &TaskInfo, b: &TaskInfo) -> Ordering {
+        let deadline = |info: &TaskInfo| info.deadline.unwrap_or(Instant::MAX);
+        deadline(a)
+            .cmp(&deadline(b))
+            .then_with(|| FixedPriority.compare(a, b))
+    }
+}
+
+/// Shortest period first.
+#[derive(Debug, Clone, Copy, Default)]
+#[allow(dead_code, reason = "the firmware uses FixedPriority")]
+pub struct RateMonotonic;
+
+impl SchedulingPolicy for RateMonotonic {
+    fn compare(&self, a: &TaskInfo, b: &TaskInfo) -> Ordering {
+        let period = |info: &TaskInfo| info.period.map_or(u64::MAX, Duration::ticks);
+        period(a)
+            .cmp(&period(b))
+            .then_with(|| FixedPriority.compare(a, b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc, vec::Vec};
+    use core::cell::RefCell;
+
+    use super::*;
+    use crate::{
+        scheduler::{Scheduler, Task, TaskCommand, TaskContext},
+        sim,
+    };
+
+    type PollLog = Rc<RefCell<Vec<&'static str>>>;
+
+    fn info(priority: TaskPriority, turn: u64) -> TaskInfo {
+        TaskInfo {
+            priority,
+            release: Instant::from_ticks(0),
+            deadline: None,
+            period: None,
+            turn,
+        }
+    }
+
+    #[test]
+    fn fixed_priority_takes_turns_among_equals() {
+        let high = info(TaskPriority::High, 5);
+        let waited = info(TaskPriority::Normal, 1);
+        let just_ran = info(TaskPriority::Normal, 4);
+        let parked = TaskInfo {
+            release: Instant::MAX,
+            ..info(TaskPriority::Normal, 0)
+        };
+
+        assert_eq!(FixedPriority.compare(&high, &waited), Ordering::Less);
+        assert_eq!(FixedPriority.compare(&waited, &just_ran), Ordering::Less);
+        assert_eq!(FixedPriority.compare(&just_ran, &waited), Ordering::Greater);
+        assert_eq!(FixedPriority.compare(&just_ran, &parked), Ordering::Less);
+    }
+
+    #[test]
+    fn rate_monotonic_prefers_short_periods() {
+        let fast = TaskInfo {
+            period: Some(Duration::from_millis(10)),
+            ..info(TaskPriority::Low, 2)
+        };
+        let slow = TaskInfo {
+            period: Some(Duration::from_millis(100)),
+            ..info(TaskPriority::High, 1)
+        };
+        let aperiodic = info(TaskPriority::High, 0);
+
+        assert_eq!(RateMonotonic.compare(&fast, &slow), Ordering::Less);
+        assert_eq!(RateMonotonic.compare(&slow, &aperiodic), Ordering::Less);
+    }
+
+    /// Task with a relative deadline that logs each poll.
+    struct Deadlined {
+        name: &'static str,
+        deadline: Option<Duration>,
+        log: PollLog,
+    }
+
+    impl Task for Deadlined {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn priority(&self) -> TaskPriority {
+            TaskPriority::High
+        }
+
+        fn deadline(&self) -> Option<Duration> {
+            self.deadline
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            self.log.borrow_mut().push(self.name);
+            TaskCommand::Continue
+        }
+    }
+
+    #[test]
+    fn edf_polls_earliest_deadline_first() {
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler: Scheduler<4, _> = Scheduler::with_policy(EarliestDeadlineFirst);
+        for (name, deadline) in [("none", None), ("late", Some(50)), ("soon", Some(5))] {
+            let task = Deadlined {
+                name,
+                deadline: deadline.map(Duration::from_millis),
+                log: log.clone(),
+            };
+            scheduler.spawn(Box::new(task)).unwrap();
+        }
+
+        scheduler.run_ready();
+
+        assert_eq!(*log.borrow(), ["soon", "late", "none"]);
+    }
+}
@@ -0,0 +1,202 @@
+//! Statically allocated task storage.
+//!
+//! A [`TaskPool`] reserves room for `N` tasks of one type in a `static`, so
+//! short-lived tasks can be spawned repeatedly without the heap and without
+//! a `static mut` slot per task. A slot is claimed when the task is spawned
+//! and released when the scheduler reaps it.
+//!
+//! A [`StaticCell`] holds a single value, such as a long-lived task or the
+//! [`Scheduler`](crate::scheduler::Scheduler) itself, that is initialized at
+//! runtime and then lives for the rest of the program.
+
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::scheduler::Task;
+
+/// Fixed set of `N` slots for tasks of type `T`.
+pub struct TaskPool<T, const N: usize> {
+    slots: [UnsafeCell<MaybeUninit<T>>; N],
+    used: [AtomicBool; N],
+}
+
+// SAFETY: a slot is only accessed through the `PoolTask` that claimed it.
+unsafe impl<T: Send, const N: usize> Sync for TaskPool<T, N> {}
+
+impl<T: Task + 'static, const N: usize> TaskPool<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            used: [const { AtomicBool::new(false) }; N],
+        }
+    }
+
+    /// Move `task` into a free slot, handing it back if the pool is full.
+    pub fn claim(&'static self, task: T) -> Result<PoolTask, T> {
+        for (slot, used) in self.slots.iter().zip(&self.used) {
+            if used
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                // SAFETY: the slot was free and is now exclusively ours.
+                let task: &'static mut T = unsafe { (*slot.get()).write(task) };
+                return Ok(PoolTask { task, used });
+            }
+        }
+        Err(task)
+    }
+
+    /// Number of slots in use.
+    #[cfg(test)]
+    pub fn in_use(&self) -> usize {
+        self.used
+            .iter()
+            .filter(|used| used.load(Ordering::Relaxed))
+            .count()
+    }
+}
+
+/// Static storage for one `T`, initialized once at runtime.
+#[cfg_attr(feature = "sim", allow(dead_code, reason = "esp32 main only"))]
+pub struct StaticCell<T> {
+    value: UnsafeCell<MaybeUninit<T>>,
+    used: AtomicBool,
+}
+
+// SAFETY: the cell never touches the value after `init`; it is only reachable
+// through the one `&mut T` handed out, which can cross threads only if `T` is
+// `Send`. The value is never dropped.
+unsafe impl<T> Sync for StaticCell<T> {}
+
+#[cfg_attr(feature = "sim", allow(dead_code, reason = "esp32 main only"))]
+impl<T> StaticCell<T> {
+    pub const fn new() -> Self {
+        Self {
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            used: AtomicBool::new(false),
+        }
+    }
+
+    /// Move `value` into the cell and return the only reference to it, or
+    /// hand `value` back if the cell was already initialized.
+    #[allow(clippy::mut_from_ref)]
+    pub fn try_init(&'static self, value: T) -> Result<&'static mut T, T> {
+        if self
+            .used
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(value);
+        }
+        // SAFETY: the cell was empty and is now exclusively ours.
+        Ok(unsafe { (*self.value.get()).write(value) })
+    }
+
+    /// Move `value` into the cell and return the only reference to it.
+    ///
+    /// Panics if the cell was already initialized.
+    #[allow(clippy::mut_from_ref)]
+    pub fn init(&'static self, value: T) -> &'static mut T {
+        match self.try_init(value) {
+            Ok(value) => value,
+            Err(_) => panic!("StaticCell initialized twice"),
+        }
+    }
+}
+
+/// Task living in a [`TaskPool`] slot; dropping it frees the slot.
+pub struct PoolTask {
+    task: &'static mut dyn Task,
+    used: &'static AtomicBool,
+}
+
+impl Deref for PoolTask {
+    type Target = dyn Task;
+
+    fn deref(&self) -> &(dyn Task + 'static) {
+        self.task
+    }
+}
+
+impl DerefMut for PoolTask {
+    fn deref_mut(&mut self) -> &mut (dyn Task + 'static) {
+        self.task
+    }
+}
+
+impl Drop for PoolTask {
+    fn drop(&mut self) {
+        // SAFETY: the slot holds an initialized task that nothing else refers to.
+        unsafe { ptr::drop_in_place(self.task as *mut dyn Task) };
+        self.used.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+
+    use super::*;
+    use crate::{
+        policy::FixedPriority,
+        scheduler::{Scheduler, SchedulerError, TaskCommand, TaskContext},
+        sim,
+    };
+
+    struct Job;
+
+    impl Task for Job {
+        fn name(&self) -> &'static str {
+            "job"
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            TaskCommand::Finished
+        }
+    }
+
+    #[test]
+    fn slot_is_released_when_task_is_reaped() {
+        static POOL: TaskPool<Job, 1> = TaskPool::new();
+        let _board = sim::board();
+        let mut scheduler = Scheduler::new();
+
+        scheduler.spawn(POOL.claim(Job).ok().unwrap()).unwrap();
+        assert!(POOL.claim(Job).is_err());
+        assert_eq!(POOL.in_use(), 1);
+
+        scheduler.run_ready();
+
+        assert_eq!(POOL.in_use(), 0);
+        assert!(POOL.claim(Job).is_ok());
+    }
+
+    #[test]
+    fn static_cell_holds_scheduler_and_tasks() {
+        static SCHEDULER: StaticCell<Scheduler<2>> = StaticCell::new();
+        static JOB: StaticCell<Job> = StaticCell::new();
+        let _board = sim::board();
+
+        let scheduler = SCHEDULER.init(Scheduler::with_policy(FixedPriority));
+        scheduler.spawn(JOB.init(Job)).unwrap();
+        assert!(JOB.try_init(Job).is_err());
+        assert!(SCHEDULER
+            .try_init(Scheduler::with_policy(FixedPriority))
+            .is_err());
+
+        scheduler.spawn(Box::new(Job)).unwrap();
+        assert_eq!(
+            scheduler.spawn(Box::new(Job)),
+            Err(SchedulerError::NoCapacity)
+        );
+
+        scheduler.run_ready();
+
+        assert_eq!(scheduler.task_count(), 0);
+    }
+}
@@ -0,0 +1,333 @@
+//! Optional preemptive time-slicing.
+//!
+//! The kernel is cooperative by default. When time-slicing is enabled, every
+//! [`ThreadTask`](crate::thread::ThreadTask) gets a budget of ticks per
+//! scheduling slot based on its [`TaskPriority`]; the system tick interrupt
+//! counts it down and switches back to the scheduler once it runs out, so a
+//! long computation no longer starves the other tasks.
+//!
+//! Poll-based and async tasks share the scheduler's stack and cannot be
+//! interrupted mid-poll; long-running work belongs in a thread task. Code
+//! running inside a critical section is never preempted because the tick
+//! interrupt is masked. Nor is a thread inside a kernel call that holds
+//! scheduler state, such as spawning a task: a slice that runs out there ends
+//! when the call returns.
+//!
+//! The tick interrupt counts the slice on the core it runs on and rings the
+//! doorbell of every other core running a slice, whose doorbell interrupt
+//! counts the tick there (see [`crate::smp`]).
+
+use core::{
+    cell::Cell,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+use critical_section::Mutex;
+
+use crate::{
+    scheduler::TaskPriority,
+    smp::{self, CoreId, PerCore},
+    thread,
+    timer::Duration,
+};
+
+/// Time slice granted to thread tasks of each priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSlices {
+    pub low: Duration,
+    pub normal: Duration,
+    pub high: Duration,
+}
+
+impl TimeSlices {
+    /// Same slice length for every priority.
+    #[allow(dead_code, reason = "the firmware stays cooperative")]
+    pub const fn uniform(slice: Duration) -> Self {
+        Self {
+            low: slice,
+            normal: slice,
+            high: slice,
+        }
+    }
+
+    fn for_priority(&self, priority: TaskPriority) -> Duration {
+        match priority {
+            TaskPriority::Low => self.low,
+            TaskPriority::Normal => self.normal,
+            TaskPriority::High => self.high,
+        }
+    }
+}
+
+impl Default for TimeSlices {
+    fn default() -> Self {
+        Self {
+            low: Duration::from_millis(5),
+            normal: Duration::from_millis(10),
+            high: Duration::from_millis(20),
+        }
+    }
+}
+
+/// Active configuration; `None` means cooperative scheduling.
+static SLICES: Mutex<Cell<Option<TimeSlices>>> = Mutex::new(Cell::new(None));
+
+/// Ticks left in the slice of the thread running on each core (0 when no
+/// slice is armed).
+static REMAINING: PerCore<AtomicU32> = PerCore::new([const { AtomicU32::new(0) }; smp::NUM_CORES]);
+
+/// Kernel calls in progress on each core; see [`without_preemption`].
+static KERNEL_CALLS: PerCore<AtomicU32> =
+    PerCore::new([const { AtomicU32::new(0) }; smp::NUM_CORES]);
+
+/// The slice on each core ran out during a kernel call.
+static DEFERRED: PerCore<AtomicBool> =
+    PerCore::new([const { AtomicBool::new(false) }; smp::NUM_CORES]);
+
+/// A tick forwarded to each core that its doorbell interrupt has not counted
+/// yet.
+static FORWARDED: PerCore<AtomicBool> =
+    PerCore::new([const { AtomicBool::new(false) }; smp::NUM_CORES]);
+
+/// Switch to preemptive time-slicing for thread tasks.
+#[allow(dead_code, reason = "the firmware stays cooperative")]
+pub fn enable(slices: TimeSlices) {
+    critical_section::with(|cs| SLICES.borrow(cs).set(Some(slices)));
+}
+
+/// Return to purely cooperative scheduling.
+#[allow(dead_code, reason = "the firmware stays cooperative")]
+pub fn disable() {
+    critical_section::with(|cs| SLICES.borrow(cs).set(None));
+    for core in (0..smp::NUM_CORES).map(CoreId::from_index) {
+        REMAINING.of(core).store(0, Ordering::Release);
+    }
+}
+
+/// Arm the slice for a thread about to run at `priority`.
+pub(crate) fn begin_slice(priority: TaskPriority) {
+    let ticks = critical_section::with(|cs| SLICES.borrow(cs).get())
+        .map(|slices| {
+            slices
+                .for_priority(priority)
+                .ticks()
+                .clamp(1, u32::MAX as u64) as u32
+        })
+        .unwrap_or(0);
+    REMAINING.get().store(ticks, Ordering::Release);
+}
+
+/// Disarm the slice once the thread has switched out.
+pub(crate) fn end_slice() {
+    REMAINING.get().store(0, Ordering::Release);
+    DEFERRED.get().store(false, Ordering::Release);
+    FORWARDED.get().store(false, Ordering::Release);
+}
+
+/// Run kernel code that must not be switched out, because a scheduler pass
+/// in between would find its state half-updated. A slice that runs out
+/// during `f` ends as soon as it returns. Calls may nest.
+pub(crate) fn without_preemption<R>(f: impl FnOnce() -> R) -> R {
+    KERNEL_CALLS.get().fetch_add(1, Ordering::AcqRel);
+    let result = f();
+    if KERNEL_CALLS.get().fetch_sub(1, Ordering::AcqRel) == 1
+        && DEFERRED.get().swap(false, Ordering::AcqRel)
+    {
+        thread::preempt_current();
+    }
+    result
+}
+
+/// Called from the system tick interrupt (must not hold a critical section).
+pub(crate) fn on_tick() {
+    let here = smp::current_core();
+    for core in (0..smp::NUM_CORES).map(CoreId::from_index) {
+        if core != here && REMAINING.of(core).load(Ordering::Acquire) > 0 {
+            FORWARDED.of(core).store(true, Ordering::Release);
+            smp::interrupt_core(core);
+        }
+    }
+    count_tick();
+}
+
+/// Called from the doorbell interrupt: counts a tick forwarded by [`on_tick`].
+pub(crate) fn on_doorbell() {
+    if FORWARDED.get().swap(false, Ordering::AcqRel) {
+        count_tick();
+    }
+}
+
+/// Take a tick off the calling core's slice, switching the thread out once
+/// it runs out.
+fn count_tick() {
+    let expired = REMAINING
+        .get()
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |left| {
+            left.checked_sub(1)
+        })
+        .is_ok_and(|left| left == 1);
+    if !expired {
+        return;
+    }
+    if KERNEL_CALLS.get().load(Ordering::Acquire) > 0 {
+        DEFERRED.get().store(true, Ordering::Release);
+    } else {
+        thread::preempt_current();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc, vec::Vec};
+    use core::cell::RefCell;
+
+    use super::*;
+    use crate::{
+        os,
+        scheduler::{Scheduler, Task, TaskCommand, TaskContext},
+        sim::{self, cores},
+        stack::DEFAULT_STACK_SIZE,
+        thread::ThreadTask,
+        timer::Instant,
+    };
+
+    type Log = Rc<RefCell<Vec<u64>>>;
+
+    /// Poll-based task standing in for the UI: records when it gets to run.
+    struct Ui {
+        log: Log,
+    }
+
+    impl Task for Ui {
+        fn name(&self) -> &'static str {
+            "ui"
+        }
+
+        fn priority(&self) -> TaskPriority {
+            TaskPriority::High
+        }
+
+        fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+            self.log.borrow_mut().push(ctx.now.ticks());
+            TaskCommand::Continue
+        }
+    }
+
+    /// Scheduler with a UI task and a thread that never yields, burning one
+    /// tick of CPU time per loop iteration.
+    fn busy_system(log: &Log) -> Scheduler {
+        let mut scheduler = Scheduler::new();
+        let ui = Ui { log: log.clone() };
+        scheduler.spawn(Box::leak(Box::new(ui))).unwrap();
+        let busy = ThreadTask::new("ml", || {
+            while Instant::now().ticks() < 30 {
+                sim::clock::advance(1);
+            }
+        })
+        .with_priority(TaskPriority::Low);
+        scheduler.spawn(Box::leak(Box::new(busy))).unwrap();
+        scheduler
+    }
+
+    #[test]
+    fn cooperative_mode_lets_busy_thread_starve_others() {
+        let _board = sim::board();
+        let log = Log::default();
+        let mut scheduler = busy_system(&log);
+
+        scheduler.run_ready();
+        scheduler.run_ready();
+
+        assert_eq!(*log.borrow(), [0, 30]);
+    }
+
+    #[test]
+    fn expired_slice_preempts_busy_thread() {
+        let _board = sim::board();
+        enable(TimeSlices {
+            low: Duration::from_ticks(5),
+            ..TimeSlices::default()
+        });
+        let log = Log::default();
+        let mut scheduler = busy_system(&log);
+
+        for _ in 0..7 {
+            scheduler.run_ready();
+        }
+
+        assert_eq!(*log.borrow(), [0, 5, 10, 15, 20, 25, 30]);
+    }
+
+    /// Task whose setup takes long enough for the spawner's slice to run out.
+    struct SlowSetup {
+        log: Log,
+    }
+
+    impl Task for SlowSetup {
+        fn name(&self) -> &'static str {
+            "slow-setup"
+        }
+
+        fn stack_size(&self) -> usize {
+            sim::clock::advance(5);
+            self.log.borrow_mut().push(100 + Instant::now().ticks());
+            DEFAULT_STACK_SIZE
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            TaskCommand::Finished
+        }
+    }
+
+    #[test]
+    fn slice_expiring_during_spawn_ends_after_the_call() {
+        let _board = sim::board();
+        enable(TimeSlices::uniform(Duration::from_ticks(2)));
+        let log = Log::default();
+        let mut scheduler = Scheduler::new();
+        let ui = Ui { log: log.clone() };
+        scheduler.spawn(Box::leak(Box::new(ui))).unwrap();
+        let spawner_log = log.clone();
+        let spawner = ThreadTask::new("spawner", move || {
+            let task = SlowSetup {
+                log: spawner_log.clone(),
+            };
+            os::spawn(Box::new(task)).unwrap();
+            spawner_log.borrow_mut().push(200 + Instant::now().ticks());
+        })
+        .with_priority(TaskPriority::Low);
+        scheduler.spawn(Box::new(spawner)).unwrap();
+
+        for _ in 0..3 {
+            scheduler.run_ready();
+        }
+
+        // The slice runs out inside the spawn (100 + 5), but the thread is
+        // only switched out once it returns, before its next line (200 + 5).
+        assert_eq!(*log.borrow(), [0, 105, 5, 205, 5]);
+        assert_eq!(scheduler.task_count(), 1);
+    }
+
+    #[test]
+    fn ticks_reach_the_other_core_through_its_doorbell() {
+        let _board = sim::board();
+        enable(TimeSlices::uniform(Duration::from_ticks(2)));
+        let app_slice = || REMAINING.of(CoreId::App).load(Ordering::Acquire);
+
+        // An idle APP core is left alone.
+        sim::clock::advance(1);
+        assert!(!FORWARDED.of(CoreId::App).load(Ordering::Acquire));
+
+        cores::start_core(CoreId::App, || begin_slice(TaskPriority::Normal))
+            .join()
+            .unwrap();
+        sim::clock::advance(1);
+        assert_eq!(app_slice(), 2);
+        cores::start_core(CoreId::App, on_doorbell).join().unwrap();
+        assert_eq!(app_slice(), 1);
+
+        // Doorbells rung for other reasons count nothing.
+        cores::start_core(CoreId::App, on_doorbell).join().unwrap();
+        assert_eq!(app_slice(), 1);
+    }
+}
@@ -2,20 +2,62 @@
 //!
 //! Tasks implement the [`Task`] trait and are polled cooperatively. Each call to
 //! [`Scheduler::run_ready`] polls every task that is ready to run based on the
-//! system tick counter maintained by `timer`.
+//! system tick counter maintained by `timer`. Tasks may also park until their
+//! waker is invoked, which is how `async` tasks from [`crate::executor`] run.
+//!
+//! Tasks can be suspended, resumed and killed by [`TaskId`]. When a task exits
+//! its slot is reaped at the end of the scheduler pass, freeing the stack and
+//! making room for new tasks; the exit status stays available to anyone
+//! holding a [`JoinHandle`].
+//!
+//! Running tasks spawn children through [`TaskContext::spawner`]; the children
+//! join the scheduler once the current pass is over.
+//!
+//! The task table is a fixed array sized by the scheduler's `N` parameter
+//! ([`MAX_TASKS`] by default), so the scheduler itself can live in a `static`;
+//! see [`crate::pool::StaticCell`].
+//!
+//! When nothing is ready, [`Scheduler::idle`] halts the CPU until the next
+//! interrupt instead of spinning.
+//!
+//! On a multi-core chip each core runs its own scheduler; see [`crate::smp`]
+//! for how tasks are placed on cores.
+//!
+//! The order in which ready tasks are polled is set by the scheduler's
+//! [`SchedulingPolicy`]; see [`crate::policy`].
+//!
+//! Every poll is timed; see [`crate::stats`] for the resulting counters and
+//! [`crate::watchdog`] for the budgets tasks can declare. With the `trace`
+//! feature every poll, sleep and exit is also recorded; see [`crate::trace`].
 
-use core::cmp::Ordering;
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    cell::{Cell, RefCell},
+    future::{poll_fn, Future},
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Poll, Waker},
+};
 
+use critical_section::Mutex;
 use heapless::Vec;
 
-use esp_println::println;
-
 use crate::{
+    arch::{Cpu, Idle},
+    event::{TaskNotifier, WaitCondition, WaitQueue},
+    executor::TaskSignal,
+    policy::{FixedPriority, SchedulingPolicy, TaskInfo},
+    pool::PoolTask,
+    preempt, println,
+    smp::{self, Affinity, CoreId, PerCore},
     stack::{TaskStack, DEFAULT_STACK_SIZE},
-    timer,
+    stats::{SchedulerStats, TaskSnapshot, TaskStats},
+    task_local::{self, LocalKey, TaskLocals},
+    timer::{self, Duration, Instant},
+    watchdog::WatchdogConfig,
 };
 
-/// Maximum number of tasks supported by the kernel.
+/// Default task capacity of a [`Scheduler`].
 pub const MAX_TASKS: usize = 8;
 
 /// Unique identifier assigned to each spawned task.
@@ -28,43 +70,218 @@ pub enum SchedulerError {
     NoCapacity,
     /// Allocation failed when reserving per-task stack.
     OutOfMemory,
+    /// No live task has the given id.
+    NoSuchTask,
 }
 
 /// Result of polling a task.
-#[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TaskCommand {
     /// Continue running on the next scheduler cycle (no delay).
     Continue,
     /// Sleep for the given number of ticks.
+    #[allow(dead_code, reason = "the firmware's tasks sleep by deadline")]
     SleepTicks(u32),
-    /// Sleep for the given number of milliseconds.
+    /// Sleep for the given number of milliseconds (rounded up to whole ticks).
     SleepMs(u32),
+    /// Sleep for the given duration.
+    Sleep(Duration),
+    /// Sleep until one period after this activation's release, so the rate
+    /// does not drift with poll time or latency. Releases that have already
+    /// passed are skipped and reported in [`TaskContext::overruns`].
+    Periodic(Duration),
+    /// Sleep until the given instant; a past instant counts as an overrun.
+    #[allow(dead_code, reason = "the firmware's tasks sleep by deadline")]
+    SleepUntil(Instant),
+    /// Block until the task's waker is invoked or the optional deadline passes.
+    Park(Option<Instant>),
+    /// Block until the condition holds or the optional timeout expires.
+    Wait(WaitCondition, Option<Duration>),
     /// Task has completed and will be removed from the scheduler.
     Finished,
+    /// Task has completed with the given exit code.
+    Exit(i32),
+}
+
+/// How a task ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The task returned [`TaskCommand::Finished`] (code 0) or
+    /// [`TaskCommand::Exit`].
+    Exited(i32),
+    /// The task was removed with [`Scheduler::kill`].
+    Killed,
+    /// The task overran its stack guard.
+    StackOverflow,
+}
+
+/// Exit status of a task, shared between its slot and its join handles.
+struct JoinState {
+    status: Mutex<Cell<Option<ExitStatus>>>,
+    joiners: WaitQueue,
+}
+
+impl JoinState {
+    fn complete(&self, status: ExitStatus) {
+        critical_section::with(|cs| {
+            self.status.borrow(cs).set(Some(status));
+            self.joiners.wake_all(cs);
+        });
+    }
+}
+
+/// Handle for waiting on a task's exit status.
+#[derive(Clone)]
+pub struct JoinHandle {
+    id: TaskId,
+    state: Arc<JoinState>,
+}
+
+impl JoinHandle {
+    /// Id of the task this handle joins.
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// Exit status if the task has ended.
+    pub fn try_join(&self) -> Option<ExitStatus> {
+        critical_section::with(|cs| self.state.status.borrow(cs).get())
+    }
+
+    /// Exit status if the task has ended; otherwise register `waker` to be
+    /// woken when it does. Poll-based tasks pass [`TaskContext::waker`] and
+    /// return [`TaskCommand::Park`].
+    pub fn poll_join(&self, waker: &Waker) -> Option<ExitStatus> {
+        critical_section::with(|cs| {
+            let status = self.state.status.borrow(cs).get();
+            if status.is_none() {
+                self.state.joiners.register(cs, waker);
+            }
+            status
+        })
+    }
+
+    /// Wait for the task to end.
+    #[allow(dead_code, reason = "the firmware runs no AsyncTask")]
+    pub fn join(&self) -> impl Future<Output = ExitStatus> + '_ {
+        poll_fn(move |cx| match self.poll_join(cx.waker()) {
+            Some(status) => Poll::Ready(status),
+            None => Poll::Pending,
+        })
+    }
 }
 
 /// Task priority used for cooperative ordering (higher runs earlier).
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TaskPriority {
     Low = 0,
+    #[default]
     Normal = 1,
     High = 2,
 }
 
-impl Default for TaskPriority {
-    fn default() -> Self {
-        TaskPriority::Normal
+/// Context passed to each task when it is polled.
+pub struct TaskContext<'a> {
+    /// The unique identifier for the task being polled.
+    #[allow(dead_code, reason = "read by tasks that log or signal themselves")]
+    pub id: TaskId,
+    /// System time at which the task was polled.
+    pub now: Instant,
+    /// When this activation was due: the wakeup time the task asked for, or
+    /// `now` if an event woke it before then.
+    #[allow(dead_code, reason = "for periodic tasks timing from release")]
+    pub release: Instant,
+    /// Periodic releases skipped, or past [`TaskCommand::SleepUntil`]
+    /// deadlines, since the previous poll.
+    pub overruns: u32,
+    waker: &'a Waker,
+    signal: &'a Arc<TaskSignal>,
+    stack: &'a TaskStack,
+    spawner: Spawner<'a>,
+    locals: &'a TaskLocals,
+}
+
+impl TaskContext<'_> {
+    /// Spawner for starting child tasks.
+    pub fn spawner(&self) -> Spawner<'_> {
+        self.spawner
+    }
+
+    /// Waker that makes this task ready again after [`TaskCommand::Park`].
+    pub fn waker(&self) -> &Waker {
+        self.waker
+    }
+
+    /// Handle other tasks and ISRs use to notify this task.
+    #[allow(dead_code, reason = "the firmware's tasks are never notified")]
+    pub fn notifier(&self) -> TaskNotifier {
+        TaskNotifier::new(self.signal.clone())
+    }
+
+    /// Clear the notification bits in `mask`, returning those that were set.
+    #[allow(dead_code, reason = "the firmware's tasks are never notified")]
+    pub fn take_notifications(&self, mask: u32) -> u32 {
+        self.signal.take_notifications(mask)
+    }
+
+    /// Stack reserved for this task, used by stackful tasks.
+    pub fn stack(&self) -> &TaskStack {
+        self.stack
+    }
+
+    /// Run `f` with this task's value for `key`; see [`crate::task_local`].
+    #[allow(dead_code, reason = "the firmware's tasks keep no task-local state")]
+    pub fn with_local<T, R>(&self, key: &'static LocalKey<T>, f: impl FnOnce(&T) -> R) -> R {
+        self.locals.with(key, f)
     }
 }
 
-/// Context passed to each task when it is polled.
-#[allow(dead_code)]
-pub struct TaskContext {
-    /// The unique identifier for the task being polled.
+/// The task being polled, for kernel objects that need to know their caller.
+#[derive(Clone)]
+pub(crate) struct CurrentTask {
     pub id: TaskId,
-    /// Current system tick when the task was polled.
-    pub current_ticks: u32,
+    /// Effective priority, including any inherited boost.
+    pub priority: TaskPriority,
+    pub signal: Arc<TaskSignal>,
+}
+
+static CURRENT_TASK: PerCore<Mutex<RefCell<Option<CurrentTask>>>> =
+    PerCore::new([const { Mutex::new(RefCell::new(None)) }; smp::NUM_CORES]);
+
+/// The task being polled on this core, or `None` outside of
+/// [`Scheduler::run_ready`].
+pub(crate) fn current_task() -> Option<CurrentTask> {
+    critical_section::with(|cs| CURRENT_TASK.get().borrow_ref(cs).clone())
+}
+
+fn set_current_task(task: Option<CurrentTask>) {
+    critical_section::with(|cs| *CURRENT_TASK.get().borrow_ref_mut(cs) = task);
+}
+
+/// Spawn requests of the scheduler polling a task on a core.
+#[derive(Clone, Copy)]
+struct RequestsPtr(*const dyn SpawnRequests);
+
+// SAFETY: only dereferenced by `with_current_spawner` on the core that
+// stored it, while the scheduler it points into is polling.
+unsafe impl Send for RequestsPtr {}
+
+static CURRENT_SPAWNER: PerCore<Mutex<Cell<Option<RequestsPtr>>>> =
+    PerCore::new([const { Mutex::new(Cell::new(None)) }; smp::NUM_CORES]);
+
+/// Run `f` with the [`Spawner`] of the task being polled on this core, or
+/// return `None` outside of [`Scheduler::run_ready`].
+pub(crate) fn with_current_spawner<R>(f: impl FnOnce(Spawner<'_>) -> R) -> Option<R> {
+    let requests = critical_section::with(|cs| CURRENT_SPAWNER.get().borrow(cs).get())?;
+    // SAFETY: the pointer is cleared before the scheduler's pass ends, and
+    // `f` cannot keep the spawner past this call.
+    let requests = unsafe { &*requests.0 };
+    Some(f(Spawner { requests }))
+}
+
+fn set_current_spawner(requests: Option<*const dyn SpawnRequests>) {
+    let requests = requests.map(RequestsPtr);
+    critical_section::with(|cs| CURRENT_SPAWNER.get().borrow(cs).set(requests));
 }
 
 /// Trait implemented by cooperative tasks.
@@ -82,134 +299,1381 @@ pub trait Task {
         DEFAULT_STACK_SIZE
     }
 
+    /// Watchdog budget for this task, if it should be supervised.
+    fn watchdog(&self) -> Option<WatchdogConfig> {
+        None
+    }
+
+    /// Time from becoming ready until each poll must have happened, used by
+    /// [`crate::policy::EarliestDeadlineFirst`].
+    fn deadline(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Activation period, used by [`crate::policy::RateMonotonic`].
+    fn period(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Cores the task may run on, used by [`crate::smp::spawn`].
+    fn affinity(&self) -> Affinity {
+        Affinity::default()
+    }
+
     /// Poll the task once.
     fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand;
 }
 
+/// Storage of a spawned task.
+pub enum TaskBox {
+    /// Task in memory that outlives the scheduler, such as a `static`.
+    Static(&'static mut dyn Task),
+    /// Heap-allocated task, freed when it is reaped.
+    Boxed(Box<dyn Task>),
+    /// Task in a [`crate::pool::TaskPool`] slot, released when it is reaped.
+    Pooled(PoolTask),
+}
+
+impl Deref for TaskBox {
+    type Target = dyn Task;
+
+    fn deref(&self) -> &(dyn Task + 'static) {
+        match self {
+            TaskBox::Static(task) => &**task,
+            TaskBox::Boxed(task) => &**task,
+            TaskBox::Pooled(task) => &**task,
+        }
+    }
+}
+
+impl DerefMut for TaskBox {
+    fn deref_mut(&mut self) -> &mut (dyn Task + 'static) {
+        match self {
+            TaskBox::Static(task) => &mut **task,
+            TaskBox::Boxed(task) => &mut **task,
+            TaskBox::Pooled(task) => &mut **task,
+        }
+    }
+}
+
+impl<T: Task + 'static> From<&'static mut T> for TaskBox {
+    fn from(task: &'static mut T) -> Self {
+        TaskBox::Static(task)
+    }
+}
+
+impl From<&'static mut dyn Task> for TaskBox {
+    fn from(task: &'static mut dyn Task) -> Self {
+        TaskBox::Static(task)
+    }
+}
+
+impl<T: Task + 'static> From<Box<T>> for TaskBox {
+    fn from(task: Box<T>) -> Self {
+        TaskBox::Boxed(task)
+    }
+}
+
+impl From<Box<dyn Task>> for TaskBox {
+    fn from(task: Box<dyn Task>) -> Self {
+        TaskBox::Boxed(task)
+    }
+}
+
+impl From<PoolTask> for TaskBox {
+    fn from(task: PoolTask) -> Self {
+        TaskBox::Pooled(task)
+    }
+}
+
+/// Starts and stops tasks from inside a running task. Spawned tasks are
+/// queued and join the scheduler after the current [`Scheduler::run_ready`]
+/// pass; kills take effect at the end of it.
+#[derive(Clone, Copy)]
+pub struct Spawner<'a> {
+    requests: &'a dyn SpawnRequests,
+}
+
+impl Spawner<'_> {
+    /// Kill task `id` at the end of the current scheduler pass, as
+    /// [`Scheduler::kill`] would. A task spawned during the pass is killed
+    /// before it starts.
+    pub fn kill(&self, id: TaskId) -> Result<(), SchedulerError> {
+        preempt::without_preemption(|| self.requests.kill(id))
+    }
+
+    /// Queue `task` to start after the current scheduler pass.
+    pub fn spawn(&self, task: impl Into<TaskBox>) -> Result<JoinHandle, SchedulerError> {
+        let task = task.into();
+        preempt::without_preemption(|| self.requests.spawn(task))
+    }
+}
+
+/// Requests queued through a [`Spawner`], whatever the scheduler's capacity.
+trait SpawnRequests {
+    fn spawn(&self, task: TaskBox) -> Result<JoinHandle, SchedulerError>;
+    fn kill(&self, id: TaskId) -> Result<(), SchedulerError>;
+}
+
+/// Next task id, shared by the schedulers on all cores so that ids are unique
+/// system-wide.
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Start numbering tasks from 1 again.
+#[cfg(test)]
+pub(crate) fn reset_ids() {
+    NEXT_ID.store(1, Ordering::Relaxed);
+}
+
+/// Task ids in use and the spawns and kills requested during a pass, for a
+/// scheduler of `N` tasks.
+struct Registry<const N: usize> {
+    pending: RefCell<Vec<TaskSlot, N>>,
+    /// Ids of live and pending tasks; its length bounds the task count.
+    ids: RefCell<Vec<TaskId, N>>,
+    kills: RefCell<Vec<TaskId, N>>,
+}
+
+impl<const N: usize> Registry<N> {
+    const fn new() -> Self {
+        Self {
+            pending: RefCell::new(Vec::new()),
+            ids: RefCell::new(Vec::new()),
+            kills: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Reserve an id and build the slot for `task`.
+    fn new_slot(&self, task: TaskBox) -> Result<TaskSlot, SchedulerError> {
+        let id = self.allocate_id()?;
+        TaskSlot::new(id, task, Instant::now()).inspect_err(|_| self.release_id(id))
+    }
+
+    /// Next free task id, skipping 0 and ids still in use after wrapping
+    /// around.
+    fn allocate_id(&self) -> Result<TaskId, SchedulerError> {
+        let mut ids = self.ids.borrow_mut();
+        if ids.is_full() {
+            return Err(SchedulerError::NoCapacity);
+        }
+        loop {
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+            if id != 0 && !ids.contains(&id) {
+                let _ = ids.push(id);
+                return Ok(id);
+            }
+        }
+    }
+
+    fn release_id(&self, id: TaskId) {
+        self.ids.borrow_mut().retain(|&live| live != id);
+    }
+}
+
+impl<const N: usize> SpawnRequests for Registry<N> {
+    fn spawn(&self, task: TaskBox) -> Result<JoinHandle, SchedulerError> {
+        let slot = self.new_slot(task)?;
+        let handle = slot.join_handle();
+        // Cannot fail: `ids` bounds live plus pending tasks to `N`.
+        let _ = self.pending.borrow_mut().push(slot);
+        Ok(handle)
+    }
+
+    fn kill(&self, id: TaskId) -> Result<(), SchedulerError> {
+        let mut kills = self.kills.borrow_mut();
+        if !self.ids.borrow().contains(&id) || kills.contains(&id) {
+            return Err(SchedulerError::NoSuchTask);
+        }
+        // Cannot fail: at most one request per id in `ids`.
+        let _ = kills.push(id);
+        Ok(())
+    }
+}
+
 struct TaskSlot {
     id: TaskId,
-    task: &'static mut dyn Task,
+    task: TaskBox,
     priority: TaskPriority,
-    next_run_tick: u32,
-    finished: bool,
+    next_run: Instant,
+    parked: bool,
+    /// Condition of the [`TaskCommand::Wait`] the task is parked on.
+    waiting: Option<WaitCondition>,
+    suspended: bool,
+    exit: Option<ExitStatus>,
     stack: TaskStack,
+    signal: Arc<TaskSignal>,
+    waker: Waker,
+    join: Arc<JoinState>,
+    /// Task-local values, dropped with the slot.
+    locals: TaskLocals,
+    stats: TaskStats,
+    watchdog: Option<WatchdogConfig>,
+    deadline: Option<Duration>,
+    period: Option<Duration>,
+    /// Round-robin position; see [`TaskInfo::turn`].
+    turn: u64,
+    /// Overruns not yet reported to the task.
+    overruns: u32,
+    last_poll: Instant,
+    /// Overdue for a poll under its liveness interval; cleared by the next poll.
+    stalled: bool,
+    /// A violation happened since the last watchdog check.
+    unhealthy: bool,
 }
 
 impl TaskSlot {
-    fn new(id: TaskId, task: &'static mut dyn Task, now: u32) -> Result<Self, SchedulerError> {
+    fn new(id: TaskId, task: TaskBox, now: Instant) -> Result<Self, SchedulerError> {
         let priority = task.priority();
+        let watchdog = task.watchdog();
+        let deadline = task.deadline();
+        let period = task.period();
         let stack = TaskStack::new(task.stack_size()).ok_or(SchedulerError::OutOfMemory)?;
+        let signal = Arc::new(TaskSignal::default());
+        let waker = Waker::from(signal.clone());
         Ok(Self {
             id,
             task,
             priority,
-            next_run_tick: now,
-            finished: false,
+            next_run: now,
+            parked: false,
+            waiting: None,
+            suspended: false,
+            exit: None,
             stack,
+            signal,
+            waker,
+            join: Arc::new(JoinState {
+                status: Mutex::new(Cell::new(None)),
+                joiners: WaitQueue::new(),
+            }),
+            locals: TaskLocals::default(),
+            stats: TaskStats::default(),
+            watchdog,
+            deadline,
+            period,
+            turn: 0,
+            overruns: 0,
+            last_poll: now,
+            stalled: false,
+            unhealthy: false,
         })
     }
+
+    fn join_handle(&self) -> JoinHandle {
+        JoinHandle {
+            id: self.id,
+            state: self.join.clone(),
+        }
+    }
+
+    /// Count a watchdog violation and withhold the next hardware feed.
+    fn watchdog_violation(&mut self) {
+        self.stats.watchdog_violations = self.stats.watchdog_violations.saturating_add(1);
+        self.unhealthy = true;
+    }
+
+    /// Check the poll that just finished against the watchdog budget.
+    fn check_poll_budget(&mut self, busy_us: u64) {
+        let Some(budget) = self.watchdog else {
+            return;
+        };
+        if busy_us > budget.max_poll.as_micros() {
+            println!(
+                "Watchdog: task {} polled for {} us (budget {} us)",
+                self.task.name(),
+                busy_us,
+                budget.max_poll.as_micros()
+            );
+            self.watchdog_violation();
+        }
+    }
+
+    /// Check that the task has been polled recently enough.
+    fn check_liveness(&mut self, now: Instant) {
+        let Some(interval) = self.watchdog.and_then(|budget| budget.liveness) else {
+            return;
+        };
+        if self.suspended {
+            return;
+        }
+        let silent = now.saturating_duration_since(self.last_poll);
+        if silent <= interval {
+            return;
+        }
+        // Log a stall once, but keep withholding feeds until it ends.
+        if !self.stalled {
+            println!(
+                "Watchdog: task {} not polled for {} ms (limit {} ms)",
+                self.task.name(),
+                silent.as_millis(),
+                interval.as_millis()
+            );
+            self.stalled = true;
+            self.watchdog_violation();
+        }
+        self.unhealthy = true;
+    }
+
+    /// Record how the task ended and wake its joiners. The slot is reaped
+    /// at the end of the current scheduler pass.
+    fn finish(&mut self, status: ExitStatus) {
+        if let Some(condition) = self.waiting.take() {
+            condition.end_wait(&self.signal, &self.waker);
+        }
+        if self.exit.is_none() {
+            crate::trace_event!(Finish {
+                task: self.id,
+                status
+            });
+            self.exit = Some(status);
+            self.join.complete(status);
+        }
+    }
+
+    /// Base priority raised by any priority inherited through a kernel mutex.
+    fn effective_priority(&self) -> TaskPriority {
+        self.signal
+            .inherited_priority()
+            .map_or(self.priority, |inherited| inherited.max(self.priority))
+    }
+
+    /// Scheduling view of the task for a pass starting at `now`.
+    fn info(&self, now: Instant) -> TaskInfo {
+        // A task woken early by an event was released at the start of the pass.
+        let released = self.next_run.min(now);
+        TaskInfo {
+            priority: self.effective_priority(),
+            release: self.next_run,
+            deadline: self.deadline.map(|deadline| released + deadline),
+            period: self.period,
+            turn: self.turn,
+        }
+    }
+
+    /// Whether the task should be polled at `now`.
+    fn is_ready(&self, now: Instant) -> bool {
+        !self.suspended && (now >= self.next_run || (self.parked && self.signal.is_woken()))
+    }
+
+    /// Whether a task woken before its wait timed out should stay parked
+    /// because its condition still fails. Consumes the wakeup and registers
+    /// for the next one.
+    fn still_waiting(&self, now: Instant) -> bool {
+        let Some(condition) = self.waiting else {
+            return false;
+        };
+        if now >= self.next_run {
+            return false;
+        }
+        self.signal.clear();
+        !condition.check_or_register(&self.signal, &self.waker)
+    }
 }
 
-/// Cooperative multitasking scheduler.
-pub struct Scheduler {
-    tasks: Vec<TaskSlot, MAX_TASKS>,
-    next_id: TaskId,
+/// Called before the CPU idles, with the earliest task wakeup deadline
+/// (`None` if every task waits for an event), and again once it wakes, with
+/// the next tick: the core is busy until it next idles.
+pub type IdleHook = fn(Option<Instant>);
+
+/// Cooperative multitasking scheduler for up to `N` tasks, ordering each pass
+/// by the policy `P`.
+pub struct Scheduler<const N: usize = MAX_TASKS, P: SchedulingPolicy = FixedPriority> {
+    tasks: Vec<TaskSlot, N>,
+    registry: Registry<N>,
+    policy: P,
+    /// Core this scheduler runs on.
+    core: CoreId,
+    /// Next round-robin position to hand out.
+    next_turn: u64,
+    /// Start of the statistics window, in microseconds; set on first use.
+    stats_since: Option<u64>,
+    idle_hook: Option<IdleHook>,
 }
 
 impl Scheduler {
-    /// Create an empty scheduler.
+    /// Create an empty fixed-priority scheduler.
     pub const fn new() -> Self {
+        Self::with_policy(FixedPriority)
+    }
+}
+
+impl<const N: usize, P: SchedulingPolicy> Scheduler<N, P> {
+    /// Create an empty scheduler using `policy`.
+    pub const fn with_policy(policy: P) -> Self {
         Self {
             tasks: Vec::new(),
-            next_id: 1,
+            registry: Registry::new(),
+            policy,
+            core: CoreId::Pro,
+            next_turn: 0,
+            stats_since: None,
+            idle_hook: None,
         }
     }
 
-    /// Register a new task with the scheduler.
-    pub fn spawn(&mut self, task: &'static mut dyn Task) -> Result<TaskId, SchedulerError> {
-        if self.tasks.is_full() {
-            return Err(SchedulerError::NoCapacity);
-        }
+    /// Run this scheduler on `core` (the PRO core by default), taking the
+    /// tasks [`smp::spawn`] places there.
+    pub fn on_core(mut self, core: CoreId) -> Self {
+        self.core = core;
+        self
+    }
 
-        let id = self.next_id;
-        self.next_id = self.next_id.wrapping_add(1);
-        let slot = TaskSlot::new(id, task, timer::get_ticks())?;
-        self.tasks
-            .push(slot)
-            .map_err(|_| SchedulerError::NoCapacity)?;
+    /// Register a new task with the scheduler. It runs on this scheduler's
+    /// core whatever its [`Task::affinity`].
+    pub fn spawn(&mut self, task: impl Into<TaskBox>) -> Result<TaskId, SchedulerError> {
+        let slot = self.registry.new_slot(task.into())?;
+        let id = slot.id;
+        self.admit(slot);
+        smp::set_load(self.core, self.tasks.len());
         Ok(id)
     }
 
+    fn slot(&mut self, id: TaskId) -> Option<&mut TaskSlot> {
+        self.tasks
+            .iter_mut()
+            .find(|slot| slot.id == id && slot.exit.is_none())
+    }
+
+    /// Stop polling a task until [`Self::resume`]. Wakeups and deadlines that
+    /// arrive meanwhile take effect once it is resumed.
+    #[allow(dead_code, reason = "the firmware's own tasks run until reset")]
+    pub fn suspend(&mut self, id: TaskId) -> Result<(), SchedulerError> {
+        let slot = self.slot(id).ok_or(SchedulerError::NoSuchTask)?;
+        slot.suspended = true;
+        Ok(())
+    }
+
+    /// Let a suspended task run again.
+    #[allow(dead_code, reason = "the firmware's own tasks run until reset")]
+    pub fn resume(&mut self, id: TaskId) -> Result<(), SchedulerError> {
+        let slot = self.slot(id).ok_or(SchedulerError::NoSuchTask)?;
+        slot.suspended = false;
+        Ok(())
+    }
+
+    /// Remove a task immediately, freeing its slot and stack.
+    ///
+    /// A stackful task is abandoned where it stands: destructors of values
+    /// on its stack do not run.
+    #[allow(dead_code, reason = "the firmware's own tasks run until reset")]
+    pub fn kill(&mut self, id: TaskId) -> Result<(), SchedulerError> {
+        self.slot(id)
+            .ok_or(SchedulerError::NoSuchTask)?
+            .finish(ExitStatus::Killed);
+        self.reap_finished();
+        Ok(())
+    }
+
+    /// Handle for waiting on a task's exit status.
+    #[allow(dead_code, reason = "the firmware's own tasks run until reset")]
+    pub fn join_handle(&mut self, id: TaskId) -> Option<JoinHandle> {
+        self.slot(id).map(|slot| slot.join_handle())
+    }
+
+    /// Earliest instant at which a task becomes ready, or `None` if every
+    /// task waits for an event. A past instant means a task is ready now.
+    pub fn next_wakeup(&self) -> Option<Instant> {
+        let now = Instant::now();
+        if !self.registry.pending.borrow().is_empty() || smp::has_spawned(self.core) {
+            return Some(now);
+        }
+        self.tasks
+            .iter()
+            .filter(|slot| !slot.suspended && slot.exit.is_none())
+            .map(|slot| {
+                if slot.is_ready(now) {
+                    now
+                } else {
+                    slot.next_run
+                }
+            })
+            .filter(|&wakeup| wakeup != Instant::MAX)
+            .min()
+    }
+
+    /// Run `hook` each time the scheduler is about to idle.
+    pub fn set_idle_hook(&mut self, hook: IdleHook) {
+        self.idle_hook = Some(hook);
+    }
+
+    /// If no task is ready, run the idle hook and halt the CPU until the next
+    /// interrupt (a timer tick or alarm, or a device event). Returns whether
+    /// it idled.
+    ///
+    /// This core's interrupts stay masked from the readiness check until the
+    /// CPU halts, so a wakeup raised in between ends the halt instead of being
+    /// missed. A wakeup from the other core rings this core's doorbell, which
+    /// stays pending until the halt.
+    pub fn idle(&mut self) -> bool {
+        Cpu::interrupt_free(|| {
+            let wakeup = self.next_wakeup();
+            if wakeup.is_some_and(|wakeup| wakeup <= Instant::now()) {
+                return false;
+            }
+            if let Some(hook) = self.idle_hook {
+                hook(wakeup);
+            }
+            Cpu::wait_for_interrupt();
+            // The wake may have come from a device or the other core, with the
+            // alarm still set for the far deadline.
+            if let Some(hook) = self.idle_hook {
+                hook(Some(Instant::now() + Duration::from_ticks(1)));
+            }
+            true
+        })
+    }
+
     /// Poll all tasks that are ready to run at the current tick.
     pub fn run_ready(&mut self) {
-        let now = timer::get_ticks();
+        let now = Instant::now();
+        self.stats_since.get_or_insert_with(timer::now_micros);
 
-        // Sort tasks so higher priority ones run first.
-        self.tasks.sort_unstable_by(|a, b| match b.priority.cmp(&a.priority) {
-            Ordering::Equal => a.next_run_tick.cmp(&b.next_run_tick),
-            other => other,
-        });
+        // Nothing to do: skip sorting the task table.
+        if self.next_wakeup().is_none_or(|wakeup| wakeup > now) {
+            return;
+        }
+
+        let policy = &self.policy;
+        self.tasks
+            .sort_unstable_by(|a, b| policy.compare(&a.info(now), &b.info(now)));
 
         for slot in self.tasks.iter_mut() {
-            if slot.finished {
+            if slot.exit.is_some() {
                 continue;
             }
 
             if !slot.stack.verify() {
                 println!("Stack guard tripped for task {}", slot.task.name());
-                slot.finished = true;
+                crate::trace_event!(StackOverflow { task: slot.id });
+                slot.finish(ExitStatus::StackOverflow);
                 continue;
             }
 
-            if now < slot.next_run_tick {
+            if !slot.is_ready(now) || slot.still_waiting(now) {
                 continue;
             }
 
+            // Wakeups that arrive while the task is being polled are kept.
+            slot.signal.clear();
+            slot.parked = false;
+            if let Some(condition) = slot.waiting.take() {
+                condition.end_wait(&slot.signal, &slot.waker);
+            }
+
+            set_current_task(Some(CurrentTask {
+                id: slot.id,
+                priority: slot.effective_priority(),
+                signal: slot.signal.clone(),
+            }));
+            set_current_spawner(Some(&self.registry as *const Registry<N>));
+
+            // Deadlines only apply to sleeps, not to wakeups before them.
+            let lateness = now.saturating_duration_since(slot.next_run);
+            let release = slot.next_run.min(now);
+            let poll_start = timer::now_micros();
+
             let mut ctx = TaskContext {
                 id: slot.id,
-                current_ticks: now,
+                now,
+                release,
+                overruns: core::mem::take(&mut slot.overruns),
+                waker: &slot.waker,
+                signal: &slot.signal,
+                stack: &slot.stack,
+                spawner: Spawner {
+                    requests: &self.registry,
+                },
+                locals: &slot.locals,
             };
 
-            match slot.task.poll(&mut ctx) {
+            crate::trace_event!(PollStart {
+                task: slot.id,
+                name: slot.task.name()
+            });
+            task_local::set_current(Some(&slot.locals));
+            let command = slot.task.poll(&mut ctx);
+            task_local::set_current(None);
+            crate::trace_event!(PollEnd { task: slot.id });
+            slot.turn = self.next_turn;
+            self.next_turn += 1;
+            let busy_us = timer::now_micros().saturating_sub(poll_start);
+            slot.stats.record_poll(busy_us, lateness);
+            slot.last_poll = now;
+            slot.stalled = false;
+            slot.check_poll_budget(busy_us);
+
+            match command {
                 TaskCommand::Continue => {
-                    slot.next_run_tick = now;
+                    slot.next_run = now;
                 }
                 TaskCommand::SleepTicks(ticks) => {
-                    slot.next_run_tick = now.wrapping_add(ticks.max(1));
+                    slot.next_run = wake_after(now, Duration::from_ticks(ticks as u64));
                 }
                 TaskCommand::SleepMs(ms) => {
-                    let ticks = timer::ms_to_ticks(ms).max(1);
-                    slot.next_run_tick = now.wrapping_add(ticks);
+                    slot.next_run = wake_after(now, Duration::from_millis(ms as u64));
+                }
+                TaskCommand::Sleep(duration) => {
+                    slot.next_run = wake_after(now, duration);
+                }
+                TaskCommand::Periodic(period) => {
+                    let (next, skipped) = next_release(release, period, Instant::now());
+                    slot.next_run = next;
+                    slot.overruns = slot.overruns.saturating_add(skipped);
+                }
+                TaskCommand::SleepUntil(deadline) => {
+                    if deadline < Instant::now() {
+                        slot.overruns = slot.overruns.saturating_add(1);
+                    }
+                    slot.next_run = deadline;
+                }
+                TaskCommand::Park(deadline) => {
+                    slot.parked = true;
+                    slot.next_run = deadline.unwrap_or(Instant::MAX);
+                }
+                TaskCommand::Wait(condition, timeout) => {
+                    if condition.check_or_register(&slot.signal, &slot.waker) {
+                        slot.next_run = now;
+                    } else {
+                        slot.parked = true;
+                        slot.waiting = Some(condition);
+                        slot.next_run = timeout.map_or(Instant::MAX, |t| wake_after(now, t));
+                    }
                 }
                 TaskCommand::Finished => {
-                    slot.finished = true;
+                    slot.finish(ExitStatus::Exited(0));
+                }
+                TaskCommand::Exit(code) => {
+                    slot.finish(ExitStatus::Exited(code));
                 }
             }
 
+            set_current_task(None);
+            set_current_spawner(None);
+
+            // Record what the task is waiting for, if anything.
+            #[cfg(feature = "trace")]
+            if slot.parked {
+                let timeout = (slot.next_run != Instant::MAX).then_some(slot.next_run);
+                crate::trace_event!(Block {
+                    task: slot.id,
+                    timeout
+                });
+            } else if slot.exit.is_none() && slot.next_run > now {
+                crate::trace_event!(Sleep {
+                    task: slot.id,
+                    until: slot.next_run
+                });
+            }
+
             if !slot.stack.verify() {
                 println!("Stack guard tripped after polling task {}", slot.task.name());
-                slot.finished = true;
+                crate::trace_event!(StackOverflow { task: slot.id });
+                slot.finish(ExitStatus::StackOverflow);
+            }
+        }
+
+        self.admit_spawned();
+        self.apply_kills();
+        self.reap_finished();
+        smp::set_load(self.core, self.tasks.len());
+    }
+
+    /// Carry out kills requested through the [`Spawner`] during the pass.
+    fn apply_kills(&mut self) {
+        let kills = core::mem::take(&mut *self.registry.kills.borrow_mut());
+        for id in kills {
+            if let Some(slot) = self.slot(id) {
+                slot.finish(ExitStatus::Killed);
+            }
+        }
+    }
+
+    /// Remove exited tasks, freeing their stacks and slots.
+    fn reap_finished(&mut self) {
+        let registry = &self.registry;
+        self.tasks.retain(|slot| {
+            if slot.exit.is_some() {
+                registry.release_id(slot.id);
+            }
+            slot.exit.is_none()
+        });
+    }
+
+    /// Move tasks spawned during the last pass, and those other cores placed
+    /// on this one while there is room, into the task table.
+    fn admit_spawned(&mut self) {
+        let spawned = core::mem::take(&mut *self.registry.pending.borrow_mut());
+        for slot in spawned {
+            self.admit(slot);
+        }
+        while !self.tasks.is_full() {
+            let Some(task) = smp::take_spawned(self.core) else {
+                break;
+            };
+            match self.registry.new_slot(task) {
+                Ok(slot) => self.admit(slot),
+                Err(err) => println!("Dropped task spawned on {:?}: {:?}", self.core, err),
             }
         }
     }
 
-    /// Remove finished tasks (optional housekeeping).
-    #[allow(dead_code)]
-    pub fn reap_finished(&mut self) {
-        self.tasks.retain(|slot| !slot.finished);
+    /// Add a new slot at the back of the round-robin queue.
+    fn admit(&mut self, mut slot: TaskSlot) {
+        slot.turn = self.take_turn();
+        slot.signal.bind(slot.id, self.core);
+        crate::trace_event!(Spawn {
+            task: slot.id,
+            name: slot.task.name()
+        });
+        // Cannot fail: the registry bounds live plus pending tasks to `N`.
+        let _ = self.tasks.push(slot);
+    }
+
+    /// Move to the back of the round-robin queue.
+    fn take_turn(&mut self) -> u64 {
+        let turn = self.next_turn;
+        self.next_turn += 1;
+        turn
+    }
+
+    /// Check every supervised task against its watchdog budget. Returns
+    /// `true` if no task has violated it since the previous check, meaning the
+    /// hardware watchdog may be fed.
+    pub fn watchdog_healthy(&mut self) -> bool {
+        let now = Instant::now();
+        let mut healthy = true;
+        for slot in self.tasks.iter_mut() {
+            slot.check_liveness(now);
+            healthy &= !core::mem::take(&mut slot.unhealthy);
+        }
+        healthy
+    }
+
+    /// Counters for every live task since the last [`Self::reset_stats`].
+    pub fn stats(&self) -> SchedulerStats<N> {
+        let since = self.stats_since.unwrap_or_else(timer::now_micros);
+        let mut tasks = Vec::new();
+        for slot in &self.tasks {
+            let _ = tasks.push(TaskSnapshot {
+                id: slot.id,
+                name: slot.task.name(),
+                priority: slot.effective_priority(),
+                stats: slot.stats,
+                stack_size: slot.stack.len(),
+                stack_used: slot.stack.high_water_mark(),
+            });
+        }
+        tasks.sort_unstable_by_key(|task: &TaskSnapshot| task.id);
+        SchedulerStats {
+            elapsed_us: timer::now_micros().saturating_sub(since),
+            tasks,
+        }
+    }
+
+    /// Zero all counters and start a new statistics window.
+    #[cfg_attr(feature = "sim", allow(dead_code, reason = "esp32 main only"))]
+    pub fn reset_stats(&mut self) {
+        for slot in self.tasks.iter_mut() {
+            slot.stats = TaskStats::default();
+        }
+        self.stats_since = Some(timer::now_micros());
     }
 
     /// Total number of tasks currently managed by the scheduler.
-    #[allow(dead_code)]
+    #[cfg(test)]
     pub fn task_count(&self) -> usize {
         self.tasks.len()
     }
+}
+
+/// Deadline for a sleep starting at `now`; every sleep lasts at least one tick.
+fn wake_after(now: Instant, duration: Duration) -> Instant {
+    now + duration.max(Duration::from_ticks(1))
+}
+
+/// First release one or more periods after `release` that is not yet past at
+/// `now`, and how many releases were skipped to reach it.
+fn next_release(release: Instant, period: Duration, now: Instant) -> (Instant, u32) {
+    let period = period.max(Duration::from_ticks(1));
+    let next = release + period;
+    if next >= now {
+        return (next, 0);
+    }
+    let skipped = (now - next).ticks().div_ceil(period.ticks());
+    let next = next + Duration::from_ticks(skipped.saturating_mul(period.ticks()));
+    (next, u32::try_from(skipped).unwrap_or(u32::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc, vec::Vec};
+    use core::cell::RefCell;
 
-    /// Put the currently running task to sleep for the given number of ticks.
-    #[allow(dead_code)]
-    pub fn current_task_sleep(&mut self, ticks: u32) {
-        if let Some(slot) = self.tasks.iter_mut().find(|slot| !slot.finished) {
-            slot.next_run_tick = timer::get_ticks().wrapping_add(ticks.max(1));
+    use super::*;
+    use crate::sim::{self, cores};
+
+    type PollLog = Rc<RefCell<Vec<&'static str>>>;
+
+    struct Recorder {
+        name: &'static str,
+        priority: TaskPriority,
+        command: TaskCommand,
+        log: PollLog,
+    }
+
+    impl Task for Recorder {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn priority(&self) -> TaskPriority {
+            self.priority
         }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            self.log.borrow_mut().push(self.name);
+            self.command
+        }
+    }
+
+    fn spawn_recorder(
+        scheduler: &mut Scheduler,
+        name: &'static str,
+        priority: TaskPriority,
+        command: TaskCommand,
+        log: &PollLog,
+    ) -> TaskId {
+        let task = Box::leak(Box::new(Recorder {
+            name,
+            priority,
+            command,
+            log: log.clone(),
+        }));
+        scheduler.spawn(task).unwrap()
+    }
+
+    #[test]
+    fn higher_priority_tasks_are_polled_first() {
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        spawn_recorder(
+            &mut scheduler,
+            "low",
+            TaskPriority::Low,
+            TaskCommand::Continue,
+            &log,
+        );
+        spawn_recorder(
+            &mut scheduler,
+            "high",
+            TaskPriority::High,
+            TaskCommand::Continue,
+            &log,
+        );
+        spawn_recorder(
+            &mut scheduler,
+            "normal",
+            TaskPriority::Normal,
+            TaskCommand::Continue,
+            &log,
+        );
+
+        scheduler.run_ready();
+
+        assert_eq!(*log.borrow(), ["high", "normal", "low"]);
+    }
+
+    #[test]
+    fn sleeping_task_waits_for_its_deadline() {
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        spawn_recorder(
+            &mut scheduler,
+            "sleeper",
+            TaskPriority::Normal,
+            TaskCommand::SleepMs(10),
+            &log,
+        );
+
+        scheduler.run_ready();
+        sim::clock::advance_ms(9);
+        scheduler.run_ready();
+        assert_eq!(log.borrow().len(), 1);
+
+        sim::clock::advance_ms(1);
+        scheduler.run_ready();
+        assert_eq!(log.borrow().len(), 2);
+    }
+
+    #[test]
+    fn finished_task_is_not_polled_again() {
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        spawn_recorder(
+            &mut scheduler,
+            "once",
+            TaskPriority::Normal,
+            TaskCommand::Finished,
+            &log,
+        );
+
+        for _ in 0..3 {
+            scheduler.run_ready();
+            sim::clock::advance(1);
+        }
+
+        assert_eq!(*log.borrow(), ["once"]);
+    }
+
+    #[test]
+    fn sleeping_task_wakes_across_32_bit_tick_rollover() {
+        let _board = sim::board();
+        sim::clock::set(u32::MAX as u64 - 5);
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        spawn_recorder(
+            &mut scheduler,
+            "sleeper",
+            TaskPriority::Normal,
+            TaskCommand::SleepMs(10),
+            &log,
+        );
+
+        scheduler.run_ready();
+        sim::clock::advance_ms(5);
+        scheduler.run_ready();
+        assert_eq!(log.borrow().len(), 1);
+
+        sim::clock::advance_ms(5);
+        scheduler.run_ready();
+        assert_eq!(log.borrow().len(), 2);
+    }
+
+    #[test]
+    fn exited_tasks_free_their_slots() {
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        for _ in 0..MAX_TASKS {
+            spawn_recorder(
+                &mut scheduler,
+                "job",
+                TaskPriority::Normal,
+                TaskCommand::Finished,
+                &log,
+            );
+        }
+        let extra = || {
+            Box::leak(Box::new(Recorder {
+                name: "extra",
+                priority: TaskPriority::Normal,
+                command: TaskCommand::Finished,
+                log: log.clone(),
+            }))
+        };
+        assert_eq!(
+            scheduler.spawn(extra()).err(),
+            Some(SchedulerError::NoCapacity)
+        );
+
+        scheduler.run_ready();
+
+        assert_eq!(scheduler.task_count(), 0);
+        assert!(scheduler.spawn(extra()).is_ok());
+    }
+
+    struct Job;
+
+    impl Task for Job {
+        fn name(&self) -> &'static str {
+            "job"
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            TaskCommand::Finished
+        }
+    }
+
+    #[test]
+    fn task_ids_are_unique_across_cores() {
+        let _board = sim::board();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let app_core = cores::start_core(CoreId::App, move || {
+            let mut scheduler = Scheduler::new().on_core(CoreId::App);
+            for _ in 0..3 {
+                let id = scheduler.spawn(Box::new(Job)).unwrap();
+                sender.send(id).unwrap();
+            }
+        });
+        let mut scheduler = Scheduler::new();
+        let mut ids: Vec<TaskId> = (0..3)
+            .map(|_| scheduler.spawn(Box::new(Job)).unwrap())
+            .collect();
+        app_core.join().unwrap();
+        ids.extend(receiver.iter());
+
+        assert_eq!(ids.len(), 6);
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 6);
+    }
+
+    #[test]
+    fn suspended_task_is_skipped_until_resumed() {
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        let id = spawn_recorder(
+            &mut scheduler,
+            "worker",
+            TaskPriority::Normal,
+            TaskCommand::Continue,
+            &log,
+        );
+
+        scheduler.suspend(id).unwrap();
+        scheduler.run_ready();
+        assert!(log.borrow().is_empty());
+
+        scheduler.resume(id).unwrap();
+        scheduler.run_ready();
+        assert_eq!(*log.borrow(), ["worker"]);
+    }
+
+    #[test]
+    fn killed_task_reports_status_to_joiner() {
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        let id = spawn_recorder(
+            &mut scheduler,
+            "victim",
+            TaskPriority::Normal,
+            TaskCommand::Continue,
+            &log,
+        );
+        let handle = scheduler.join_handle(id).unwrap();
+        assert_eq!(handle.try_join(), None);
+
+        scheduler.kill(id).unwrap();
+
+        assert_eq!(handle.try_join(), Some(ExitStatus::Killed));
+        assert_eq!(scheduler.task_count(), 0);
+        assert_eq!(scheduler.kill(id), Err(SchedulerError::NoSuchTask));
+    }
+
+    #[test]
+    fn async_joiner_receives_exit_code() {
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        let id = spawn_recorder(
+            &mut scheduler,
+            "job",
+            TaskPriority::Low,
+            TaskCommand::Exit(3),
+            &log,
+        );
+        let handle = scheduler.join_handle(id).unwrap();
+        let joined = Rc::new(Cell::new(None));
+        let task_joined = joined.clone();
+        let joiner = crate::executor::AsyncTask::new("joiner", async move {
+            task_joined.set(Some(handle.join().await));
+        })
+        .with_priority(TaskPriority::High);
+        scheduler.spawn(Box::leak(Box::new(joiner))).unwrap();
+
+        scheduler.run_ready();
+        assert_eq!(joined.get(), None);
+
+        scheduler.run_ready();
+        assert_eq!(joined.get(), Some(ExitStatus::Exited(3)));
+    }
+
+    /// Spawns a boxed child on its first poll and waits for it.
+    struct Parent {
+        child: Option<JoinHandle>,
+        log: PollLog,
+    }
+
+    impl Task for Parent {
+        fn name(&self) -> &'static str {
+            "parent"
+        }
+
+        fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+            self.log.borrow_mut().push("parent");
+            let child = self.child.get_or_insert_with(|| {
+                let child = Box::new(Recorder {
+                    name: "child",
+                    priority: TaskPriority::High,
+                    command: TaskCommand::Exit(7),
+                    log: self.log.clone(),
+                });
+                ctx.spawner().spawn(child).unwrap()
+            });
+            match child.poll_join(ctx.waker()) {
+                Some(ExitStatus::Exited(code)) => TaskCommand::Exit(code + 1),
+                Some(_) => TaskCommand::Finished,
+                None => TaskCommand::Park(None),
+            }
+        }
+    }
+
+    #[test]
+    fn task_spawns_child_after_current_pass() {
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        let parent = scheduler
+            .spawn(Box::new(Parent {
+                child: None,
+                log: log.clone(),
+            }))
+            .unwrap();
+        let parent = scheduler.join_handle(parent).unwrap();
+
+        scheduler.run_ready();
+        assert_eq!(*log.borrow(), ["parent"]);
+        assert_eq!(scheduler.task_count(), 2);
+
+        scheduler.run_ready();
+        assert_eq!(*log.borrow(), ["parent", "child", "parent"]);
+        assert_eq!(parent.try_join(), Some(ExitStatus::Exited(8)));
+        assert_eq!(scheduler.task_count(), 0);
+    }
+
+    /// Kills `victim` twice, an unknown id and a child it has just spawned.
+    struct Reaper {
+        victim: TaskId,
+        results: Rc<RefCell<Vec<Result<(), SchedulerError>>>>,
+        child: Rc<RefCell<Option<JoinHandle>>>,
+        log: PollLog,
+    }
+
+    impl Task for Reaper {
+        fn name(&self) -> &'static str {
+            "reaper"
+        }
+
+        fn priority(&self) -> TaskPriority {
+            TaskPriority::High
+        }
+
+        fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+            let spawner = ctx.spawner();
+            let child = spawner
+                .spawn(Box::new(Recorder {
+                    name: "child",
+                    priority: TaskPriority::High,
+                    command: TaskCommand::Continue,
+                    log: self.log.clone(),
+                }))
+                .unwrap();
+            let mut results = self.results.borrow_mut();
+            results.push(spawner.kill(self.victim));
+            results.push(spawner.kill(self.victim));
+            results.push(spawner.kill(TaskId::MAX));
+            results.push(spawner.kill(child.id()));
+            *self.child.borrow_mut() = Some(child);
+            TaskCommand::Finished
+        }
+    }
+
+    #[test]
+    fn spawner_kill_checks_the_id() {
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        let victim = spawn_recorder(
+            &mut scheduler,
+            "victim",
+            TaskPriority::Low,
+            TaskCommand::Continue,
+            &log,
+        );
+        let victim_handle = scheduler.join_handle(victim).unwrap();
+        let results = Rc::new(RefCell::new(Vec::new()));
+        let child = Rc::new(RefCell::new(None));
+        scheduler
+            .spawn(Box::new(Reaper {
+                victim,
+                results: results.clone(),
+                child: child.clone(),
+                log: log.clone(),
+            }))
+            .unwrap();
+
+        scheduler.run_ready();
+        assert_eq!(
+            *results.borrow(),
+            [
+                Ok(()),
+                Err(SchedulerError::NoSuchTask),
+                Err(SchedulerError::NoSuchTask),
+                Ok(()),
+            ]
+        );
+        assert_eq!(victim_handle.try_join(), Some(ExitStatus::Killed));
+        let child = child.borrow_mut().take().unwrap();
+        assert_eq!(child.try_join(), Some(ExitStatus::Killed));
+
+        scheduler.run_ready();
+        assert_eq!(*log.borrow(), ["victim"]);
+        assert_eq!(scheduler.task_count(), 0);
+    }
+
+    #[test]
+    fn next_wakeup_is_earliest_sleep_deadline() {
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        assert_eq!(scheduler.next_wakeup(), None);
+
+        spawn_recorder(
+            &mut scheduler,
+            "slow",
+            TaskPriority::Normal,
+            TaskCommand::SleepMs(50),
+            &log,
+        );
+        spawn_recorder(
+            &mut scheduler,
+            "fast",
+            TaskPriority::Normal,
+            TaskCommand::SleepMs(20),
+            &log,
+        );
+        spawn_recorder(
+            &mut scheduler,
+            "parked",
+            TaskPriority::Normal,
+            TaskCommand::Park(None),
+            &log,
+        );
+        assert_eq!(scheduler.next_wakeup(), Some(Instant::now()));
+
+        scheduler.run_ready();
+        assert_eq!(
+            scheduler.next_wakeup(),
+            Some(Instant::now() + Duration::from_millis(20))
+        );
+    }
+
+    #[test]
+    fn idle_runs_hook_and_advances_to_next_tick() {
+        static HOOK_DEADLINE: Mutex<Cell<Option<Option<Instant>>>> = Mutex::new(Cell::new(None));
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        scheduler.set_idle_hook(|deadline| {
+            critical_section::with(|cs| HOOK_DEADLINE.borrow(cs).set(Some(deadline)))
+        });
+        spawn_recorder(
+            &mut scheduler,
+            "sleeper",
+            TaskPriority::Normal,
+            TaskCommand::SleepTicks(2),
+            &log,
+        );
+
+        // A freshly spawned task is ready, so there is nothing to wait for.
+        assert!(!scheduler.idle());
+        scheduler.run_ready();
+
+        assert!(scheduler.idle());
+        assert_eq!(
+            critical_section::with(|cs| HOOK_DEADLINE.borrow(cs).get()),
+            Some(Some(Instant::from_ticks(2)))
+        );
+        assert!(scheduler.idle());
+        assert!(!scheduler.idle());
+        assert_eq!(Instant::now().ticks(), 2);
+
+        scheduler.run_ready();
+        assert_eq!(*log.borrow(), ["sleeper", "sleeper"]);
+    }
+
+    /// Periodic task logging `(now, release, overruns)`, busy for `busy`
+    /// ticks on its first poll.
+    struct Sampler {
+        busy: u64,
+        log: Rc<RefCell<Vec<(u64, u64, u32)>>>,
+    }
+
+    impl Task for Sampler {
+        fn name(&self) -> &'static str {
+            "sampler"
+        }
+
+        fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+            let entry = (ctx.now.ticks(), ctx.release.ticks(), ctx.overruns);
+            self.log.borrow_mut().push(entry);
+            sim::clock::advance(core::mem::take(&mut self.busy));
+            TaskCommand::Periodic(Duration::from_ticks(10))
+        }
+    }
+
+    #[test]
+    fn periodic_task_keeps_its_phase_when_polled_late() {
+        let _board = sim::board();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut scheduler = Scheduler::new();
+        let sampler = Sampler {
+            busy: 0,
+            log: log.clone(),
+        };
+        scheduler.spawn(Box::new(sampler)).unwrap();
+
+        for tick in [0, 13, 20] {
+            sim::clock::set(tick);
+            scheduler.run_ready();
+        }
+
+        assert_eq!(*log.borrow(), [(0, 0, 0), (13, 10, 0), (20, 20, 0)]);
+    }
+
+    #[test]
+    fn periodic_overrun_skips_missed_releases() {
+        let _board = sim::board();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut scheduler = Scheduler::new();
+        let sampler = Sampler {
+            busy: 25,
+            log: log.clone(),
+        };
+        scheduler.spawn(Box::new(sampler)).unwrap();
+
+        scheduler.run_ready();
+        sim::clock::set(29);
+        scheduler.run_ready();
+        sim::clock::set(30);
+        scheduler.run_ready();
+
+        // Releases at 10 and 20 passed while the first poll ran.
+        assert_eq!(*log.borrow(), [(0, 0, 0), (30, 30, 2)]);
+    }
+
+    #[test]
+    fn sleep_until_past_instant_is_an_overrun() {
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        sim::clock::set(100);
+        spawn_recorder(
+            &mut scheduler,
+            "late",
+            TaskPriority::Normal,
+            TaskCommand::SleepUntil(Instant::from_ticks(50)),
+            &log,
+        );
+
+        scheduler.run_ready();
+        let slot = scheduler.tasks.first().unwrap();
+        assert_eq!(slot.overruns, 1);
+        assert_eq!(slot.next_run, Instant::from_ticks(50));
     }
 }
@@ -0,0 +1,100 @@
+//! Virtual tick source.
+//!
+//! Stands in for the TIMG0 periodic interrupt. Time only moves when the
+//! simulation calls [`advance`], which makes scheduling fully deterministic.
+//! An armed tickless alarm lets an idle CPU skip straight to it. Each core
+//! has its own alarm; see [`super::cores`].
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::cores;
+use crate::smp::{CoreId, NUM_CORES};
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Tick of each core's armed one-shot alarm, `u64::MAX` if none.
+static ALARMS: [AtomicU64; NUM_CORES] = [const { AtomicU64::new(u64::MAX) }; NUM_CORES];
+
+/// Current virtual tick count.
+pub fn now() -> u64 {
+    TICKS.load(Ordering::SeqCst)
+}
+
+/// Advance virtual time by `ticks` timer interrupts.
+///
+/// Each tick runs the same kernel hook as the hardware tick interrupt, so a
+/// thread task calling this to model busy work can be preempted.
+pub fn advance(ticks: u64) {
+    for _ in 0..ticks {
+        TICKS.fetch_add(1, Ordering::SeqCst);
+        crate::trace_event!(IsrEnter { irq: "timer" });
+        cores::tick();
+        crate::trace_event!(IsrExit { irq: "timer" });
+        crate::preempt::on_tick();
+    }
+}
+
+/// Advance virtual time by the given number of milliseconds.
+pub fn advance_ms(ms: u32) {
+    advance(crate::timer::ms_to_ticks(ms));
+}
+
+/// Set the virtual tick counter to an arbitrary value.
+#[cfg(test)]
+pub fn set(ticks: u64) {
+    TICKS.store(ticks, Ordering::SeqCst);
+}
+
+/// Arm (or with `None`, disarm) the calling core's one-shot alarm for the
+/// given tick.
+pub fn set_alarm(tick: Option<u64>) {
+    ALARMS[cores::current_core().index()].store(tick.unwrap_or(u64::MAX), Ordering::SeqCst);
+}
+
+/// Tick `core`'s alarm is armed for, if any.
+#[cfg(test)]
+pub fn alarm(core: CoreId) -> Option<u64> {
+    match ALARMS[core.index()].load(Ordering::SeqCst) {
+        u64::MAX => None,
+        tick => Some(tick),
+    }
+}
+
+/// Disarm every core's alarm.
+#[cfg(test)]
+pub(super) fn clear_alarms() {
+    for alarm in &ALARMS {
+        alarm.store(u64::MAX, Ordering::SeqCst);
+    }
+}
+
+/// Whether `core`'s alarm tick has been reached.
+pub(super) fn alarm_due(core: CoreId) -> bool {
+    ALARMS[core.index()].load(Ordering::SeqCst) <= now()
+}
+
+/// Disarm `core`'s alarm if its tick has been reached, returning whether it
+/// had.
+pub(super) fn take_due_alarm(core: CoreId) -> bool {
+    let due = alarm_due(core);
+    if due {
+        ALARMS[core.index()].store(u64::MAX, Ordering::SeqCst);
+    }
+    due
+}
+
+/// Advance to the next interrupt of the timer core: the earliest alarm of
+/// any core, or else the next tick. The caller's own alarm is disarmed; the
+/// other cores disarm theirs when they wake.
+pub fn wait_for_interrupt() {
+    let own = ALARMS[cores::current_core().index()].swap(u64::MAX, Ordering::SeqCst);
+    let alarm = ALARMS
+        .iter()
+        .map(|alarm| alarm.load(Ordering::SeqCst))
+        .fold(own, u64::min);
+    let ticks = match alarm {
+        u64::MAX => 1,
+        alarm => alarm.saturating_sub(now()).max(1),
+    };
+    advance(ticks);
+}
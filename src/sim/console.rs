@@ -0,0 +1,28 @@
+//! Console sink for the simulation build.
+//!
+//! Lines are echoed to stdout and captured so tests can assert on kernel and
+//! task log output.
+
+use core::fmt;
+use std::{string::String, sync::Mutex, vec::Vec};
+
+static CAPTURED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn captured() -> std::sync::MutexGuard<'static, Vec<String>> {
+    CAPTURED
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Record and echo one console line.
+pub fn write_line(args: fmt::Arguments<'_>) {
+    let line = std::fmt::format(args);
+    std::println!("{}", line);
+    captured().push(line);
+}
+
+/// Drain all captured lines.
+#[cfg(test)]
+pub fn take_lines() -> Vec<String> {
+    core::mem::take(&mut *captured())
+}
@@ -0,0 +1,151 @@
+//! Simulated CPU cores.
+//!
+//! Each ESP32 core is modelled by a host thread: the thread that boots the
+//! kernel is the PRO core, and [`start_core`] runs the APP core on a new one.
+//! Cores interrupt each other through a doorbell, standing in for the
+//! FROM_CPU interrupts on hardware.
+//!
+//! Virtual time belongs to the PRO core, which owns the tick timer: it moves
+//! when the PRO core idles or calls [`clock::advance`]. An idle APP core
+//! sleeps until its alarm tick passes or its doorbell rings. Before moving
+//! time, an idle PRO core waits for the other cores to halt too, so they
+//! keep up with virtual time; a core that never halts only holds it up for
+//! [`CATCH_UP`] of host time.
+
+use std::{
+    cell::Cell,
+    sync::{Condvar, Mutex, MutexGuard},
+    thread,
+    time::{Duration as HostDuration, Instant as HostInstant},
+};
+
+use super::clock;
+use crate::smp::{CoreId, NUM_CORES};
+
+/// Longest the PRO core waits for the other cores to halt before idling.
+const CATCH_UP: HostDuration = HostDuration::from_millis(10);
+
+thread_local! {
+    static CORE: Cell<CoreId> = const { Cell::new(CoreId::Pro) };
+}
+
+struct Cores {
+    /// Pending doorbell of each core.
+    doorbells: [bool; NUM_CORES],
+    /// Cores waiting for an interrupt or not running at all.
+    halted: [bool; NUM_CORES],
+}
+
+impl Cores {
+    /// Whether `core` is halted with no interrupt pending that would
+    /// resume it.
+    fn is_idle(&self, core: usize) -> bool {
+        self.halted[core] && !self.doorbells[core] && !clock::alarm_due(CoreId::from_index(core))
+    }
+}
+
+static CORES: Mutex<Cores> = Mutex::new(Cores {
+    doorbells: [false; NUM_CORES],
+    halted: [true; NUM_CORES],
+});
+
+/// Signalled when a doorbell rings, a core halts or virtual time moves.
+static INTERRUPT: Condvar = Condvar::new();
+
+fn cores() -> MutexGuard<'static, Cores> {
+    CORES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn wait(cores: MutexGuard<'static, Cores>, timeout: HostDuration) -> MutexGuard<'static, Cores> {
+    INTERRUPT
+        .wait_timeout(cores, timeout)
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .0
+}
+
+/// Core the calling thread runs as.
+pub fn current_core() -> CoreId {
+    CORE.with(Cell::get)
+}
+
+/// Run `body` as `core` on a new host thread.
+pub fn start_core(core: CoreId, body: impl FnOnce() + Send + 'static) -> thread::JoinHandle<()> {
+    cores().halted[core.index()] = false;
+    thread::spawn(move || {
+        CORE.with(|current| current.set(core));
+        body();
+        cores().halted[core.index()] = true;
+        INTERRUPT.notify_all();
+    })
+}
+
+/// Ring the doorbell of `core`.
+pub fn interrupt_core(core: CoreId) {
+    cores().doorbells[core.index()] = true;
+    INTERRUPT.notify_all();
+}
+
+/// Let idle cores look at the clock again; called on every virtual tick.
+pub(super) fn tick() {
+    let _cores = cores();
+    INTERRUPT.notify_all();
+}
+
+/// Stop the calling core until its next interrupt: for the PRO core the
+/// next alarm or tick (see [`clock::wait_for_interrupt`]), for the others
+/// their alarm. A pending doorbell ends the wait at once, and runs the
+/// doorbell interrupt's kernel hook.
+pub fn wait_for_interrupt() {
+    if halt() {
+        crate::preempt::on_doorbell();
+    }
+}
+
+/// Wait as [`wait_for_interrupt`] describes, returning whether the doorbell
+/// ended the wait.
+fn halt() -> bool {
+    let core = current_core().index();
+    let mut cores = cores();
+    if core::mem::take(&mut cores.doorbells[core]) {
+        return true;
+    }
+    if core == CoreId::Pro.index() {
+        let start = HostInstant::now();
+        while (0..NUM_CORES).any(|other| other != core && !cores.is_idle(other)) {
+            let Some(left) = CATCH_UP.checked_sub(start.elapsed()) else {
+                break;
+            };
+            cores = wait(cores, left);
+        }
+        if core::mem::take(&mut cores.doorbells[core]) {
+            return true;
+        }
+        drop(cores);
+        clock::wait_for_interrupt();
+        return false;
+    }
+    cores.halted[core] = true;
+    INTERRUPT.notify_all();
+    let rung = loop {
+        if core::mem::take(&mut cores.doorbells[core]) {
+            break true;
+        }
+        if clock::take_due_alarm(current_core()) {
+            break false;
+        }
+        // The timeout only guards against a test forgetting to stop the core.
+        cores = wait(cores, HostDuration::from_millis(100));
+    };
+    cores.halted[core] = false;
+    rung
+}
+
+/// Return to power-on state.
+#[cfg(test)]
+pub(super) fn clear() {
+    let mut cores = cores();
+    cores.doorbells = [false; NUM_CORES];
+    cores.halted = [true; NUM_CORES];
+}
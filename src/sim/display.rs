@@ -0,0 +1,80 @@
+//! Fake SSD1306 OLED display.
+//!
+//! Implements the same drawing helpers as [`crate::oled::OledDisplay`] on
+//! hardware, recording the text shown instead of rendering pixels.
+
+use std::{
+    string::{String, ToString},
+    sync::{Arc, Mutex},
+    vec::Vec,
+};
+
+use display_interface::DisplayError;
+
+/// Convenience result type for OLED operations.
+pub type OledResult<T> = Result<T, DisplayError>;
+
+/// Handle used by the simulation to inspect what is on screen.
+#[derive(Clone, Default)]
+pub struct DisplayProbe {
+    lines: Arc<Mutex<Vec<String>>>,
+}
+
+#[cfg(test)]
+impl DisplayProbe {
+    /// Text lines currently shown on the panel.
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().clone()
+    }
+}
+
+/// Simulated OLED display.
+pub struct OledDisplay {
+    probe: DisplayProbe,
+}
+
+impl OledDisplay {
+    /// Create a blank simulated display.
+    pub fn new() -> Self {
+        Self {
+            probe: DisplayProbe::default(),
+        }
+    }
+
+    /// Probe used to observe this display from the simulation.
+    #[cfg(test)]
+    pub fn probe(&self) -> DisplayProbe {
+        self.probe.clone()
+    }
+
+    fn render_lines<'a>(&mut self, lines: impl IntoIterator<Item = &'a str>) -> OledResult<()> {
+        let mut shown = self.probe.lines.lock().unwrap();
+        shown.clear();
+        shown.extend(lines.into_iter().map(|line| line.to_string()));
+        Ok(())
+    }
+
+    /// Display a collection of text lines, starting from the top of the panel.
+    pub fn show_lines(&mut self, lines: &[&str]) -> OledResult<()> {
+        self.render_lines(lines.iter().copied())
+    }
+
+    /// The boot animation is skipped in simulation; only the delays are kept.
+    pub fn play_boot_animation<F>(&mut self, mut delay_ms: F) -> OledResult<()>
+    where
+        F: FnMut(u32),
+    {
+        delay_ms(300);
+        Ok(())
+    }
+
+    /// Display a boot progress message.
+    pub fn show_boot_progress(&mut self, message: &str) -> OledResult<()> {
+        self.render_lines(["Booting ESP32", message])
+    }
+
+    /// Display application name and version.
+    pub fn show_app_info(&mut self, app_name: &str, app_version: &str) -> OledResult<()> {
+        self.render_lines(["TrustG33k", app_name, "Version", app_version])
+    }
+}
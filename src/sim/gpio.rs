@@ -0,0 +1,121 @@
+//! Fake GPIO pins.
+//!
+//! Mirror the subset of the `esp_hal::gpio` API used by tasks. Each pin shares
+//! its level with a [`PinProbe`] that the simulation uses to drive inputs or
+//! observe outputs. Inputs can listen for edges, standing in for the GPIO
+//! interrupt: the handler runs synchronously when the probe changes the level.
+
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use std::sync::{Arc, Mutex};
+
+struct PinState {
+    high: AtomicBool,
+    on_edge: Mutex<Option<fn()>>,
+}
+
+/// Handle onto a simulated pin level.
+#[derive(Clone)]
+pub struct PinProbe {
+    state: Arc<PinState>,
+}
+
+impl PinProbe {
+    fn new(high: bool) -> Self {
+        Self {
+            state: Arc::new(PinState {
+                high: AtomicBool::new(high),
+                on_edge: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Current pin level.
+    pub fn is_high(&self) -> bool {
+        self.state.high.load(Ordering::Relaxed)
+    }
+
+    /// Drive the pin to the given level, raising an edge event if it changed.
+    pub fn set_high(&self, high: bool) {
+        let was_high = self.state.high.swap(high, Ordering::Relaxed);
+        let on_edge = *self.state.on_edge.lock().unwrap();
+        if was_high != high {
+            if let Some(handler) = on_edge {
+                handler();
+            }
+        }
+    }
+
+    /// Simulate pressing an active-low button.
+    #[cfg(test)]
+    pub fn press(&self) {
+        self.set_high(false);
+    }
+}
+
+/// Simulated input pin with a pull-up (reads high until driven low).
+pub struct Input<'d> {
+    probe: PinProbe,
+    _pin: PhantomData<&'d ()>,
+}
+
+impl Input<'_> {
+    /// Create a pulled-up input pin.
+    pub fn pulled_up() -> Self {
+        Self {
+            probe: PinProbe::new(true),
+            _pin: PhantomData,
+        }
+    }
+
+    /// Probe used to drive this pin from the simulation.
+    #[cfg(test)]
+    pub fn probe(&self) -> PinProbe {
+        self.probe.clone()
+    }
+
+    /// Run `handler` on every edge of this pin.
+    pub fn listen(&mut self, handler: fn()) {
+        *self.probe.state.on_edge.lock().unwrap() = Some(handler);
+    }
+
+    pub fn is_low(&self) -> bool {
+        !self.probe.is_high()
+    }
+}
+
+/// Simulated push-pull output pin (starts low).
+pub struct Output<'d> {
+    probe: PinProbe,
+    _pin: PhantomData<&'d ()>,
+}
+
+impl Output<'_> {
+    /// Create an output pin driven low.
+    pub fn new() -> Self {
+        Self {
+            probe: PinProbe::new(false),
+            _pin: PhantomData,
+        }
+    }
+
+    /// Probe used to observe this pin from the simulation.
+    #[cfg(test)]
+    pub fn probe(&self) -> PinProbe {
+        self.probe.clone()
+    }
+
+    pub fn set_high(&mut self) {
+        self.probe.set_high(true);
+    }
+
+    pub fn set_low(&mut self) {
+        self.probe.set_high(false);
+    }
+
+    pub fn is_set_high(&self) -> bool {
+        self.probe.is_high()
+    }
+}
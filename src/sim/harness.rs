@@ -0,0 +1,379 @@
+//! Deterministic scheduler test harness.
+//!
+//! A [`Harness`] claims the simulated board, owns a [`Scheduler`] and drives
+//! virtual time itself: each [`Harness::step`] runs one scheduler pass and
+//! then moves the clock to the next task wakeup, as the idle loop would. The
+//! only randomness is an optional jitter added to each step, drawn from a
+//! generator seeded by the test, so a run is reproduced bit-for-bit by its
+//! seed and a failing seed can be kept as a regression test.
+//!
+//! Tasks spawned through the harness are wrapped so every poll is recorded in
+//! order (see [`Harness::polls`]) and faults can be injected into them:
+//! see [`Fault`] and [`Harness::fail_stack_allocs`].
+
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use super::{board, clock, BoardGuard};
+use crate::{
+    policy::{FixedPriority, SchedulingPolicy},
+    scheduler::{
+        Scheduler, SchedulerError, Task, TaskBox, TaskCommand, TaskContext, TaskId, TaskPriority,
+        MAX_TASKS,
+    },
+    smp::Affinity,
+    timer::{Duration, Instant, MAX_IDLE},
+    watchdog::{self, WatchdogConfig},
+};
+
+/// Fault injected into a task's next poll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Overwrite the task's stack guard during the poll, as an overflow
+    /// would.
+    CorruptStackGuard,
+    /// Keep the CPU for the given time before the task's code runs, as a
+    /// task that does not yield would.
+    Hang(Duration),
+}
+
+/// One recorded poll.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PollRecord {
+    /// Tick the poll started at.
+    pub tick: u64,
+    pub task: TaskId,
+    pub name: &'static str,
+    /// What the task returned.
+    pub command: TaskCommand,
+}
+
+/// SplitMix64 generator; the same seed always gives the same sequence.
+pub struct SimRng(u64);
+
+impl SimRng {
+    pub const fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Value in `0..bound`, or 0 if `bound` is 0.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            0
+        } else {
+            self.next_u64() % bound
+        }
+    }
+}
+
+/// Poll log and pending faults, shared with the wrapped tasks.
+#[derive(Default)]
+struct Shared {
+    polls: Vec<PollRecord>,
+    faults: Vec<(TaskId, Fault)>,
+}
+
+/// Task wrapper recording polls and applying injected faults.
+struct Observed {
+    task: TaskBox,
+    shared: Rc<RefCell<Shared>>,
+}
+
+impl Task for Observed {
+    fn name(&self) -> &'static str {
+        self.task.name()
+    }
+
+    fn priority(&self) -> TaskPriority {
+        self.task.priority()
+    }
+
+    fn stack_size(&self) -> usize {
+        self.task.stack_size()
+    }
+
+    fn watchdog(&self) -> Option<WatchdogConfig> {
+        self.task.watchdog()
+    }
+
+    fn deadline(&self) -> Option<Duration> {
+        self.task.deadline()
+    }
+
+    fn period(&self) -> Option<Duration> {
+        self.task.period()
+    }
+
+    fn affinity(&self) -> Affinity {
+        self.task.affinity()
+    }
+
+    fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+        let faults: Vec<Fault> = {
+            let mut shared = self.shared.borrow_mut();
+            let (own, others) = shared.faults.drain(..).partition(|(id, _)| *id == ctx.id);
+            shared.faults = others;
+            own.into_iter().map(|(_, fault)| fault).collect()
+        };
+        for fault in faults {
+            match fault {
+                Fault::CorruptStackGuard => ctx.stack().clobber_guard(),
+                Fault::Hang(duration) => clock::advance(duration.ticks()),
+            }
+        }
+        let command = self.task.poll(ctx);
+        self.shared.borrow_mut().polls.push(PollRecord {
+            tick: ctx.now.ticks(),
+            task: ctx.id,
+            name: self.task.name(),
+            command,
+        });
+        command
+    }
+}
+
+/// Scheduler on a claimed board, driven in virtual time from a seed.
+pub struct Harness<const N: usize = MAX_TASKS, P: SchedulingPolicy = FixedPriority> {
+    scheduler: Scheduler<N, P>,
+    shared: Rc<RefCell<Shared>>,
+    rng: SimRng,
+    /// Most ticks added to each step.
+    jitter: u64,
+    _board: BoardGuard,
+}
+
+impl Harness {
+    /// Claim the board and start an empty fixed-priority scheduler.
+    pub fn new(seed: u64) -> Self {
+        Self::with_scheduler(seed, Scheduler::new())
+    }
+}
+
+impl<const N: usize, P: SchedulingPolicy> Harness<N, P> {
+    /// Claim the board and drive `scheduler`, which must be empty.
+    pub fn with_scheduler(seed: u64, scheduler: Scheduler<N, P>) -> Self {
+        let board = board();
+        Self {
+            scheduler,
+            shared: Rc::default(),
+            rng: SimRng::new(seed),
+            jitter: 0,
+            _board: board,
+        }
+    }
+
+    /// Delay each step by up to `max` more, chosen by the seeded generator,
+    /// to vary when passes run relative to task deadlines.
+    pub fn with_jitter(mut self, max: Duration) -> Self {
+        self.jitter = max.ticks();
+        self
+    }
+
+    /// The driven scheduler.
+    pub fn scheduler(&mut self) -> &mut Scheduler<N, P> {
+        &mut self.scheduler
+    }
+
+    /// Spawn `task` with its polls recorded.
+    pub fn spawn(&mut self, task: impl Into<TaskBox>) -> Result<TaskId, SchedulerError> {
+        self.scheduler.spawn(Box::new(Observed {
+            task: task.into(),
+            shared: self.shared.clone(),
+        }))
+    }
+
+    /// Apply `fault` during the next poll of task `id`.
+    pub fn inject(&mut self, id: TaskId, fault: Fault) {
+        self.shared.borrow_mut().faults.push((id, fault));
+    }
+
+    /// Make the next `count` stack allocations fail, as if the heap were
+    /// exhausted.
+    pub fn fail_stack_allocs(&mut self, count: u32) {
+        STACK_ALLOC_FAILURES.store(count, Ordering::SeqCst);
+    }
+
+    /// Run one scheduler pass and feed the hardware watchdog if every task
+    /// stayed within its budget, as the main loop does. Then advance the
+    /// clock to the next wakeup (at least one tick, at most [`MAX_IDLE`])
+    /// plus jitter.
+    pub fn step(&mut self) {
+        self.scheduler.run_ready();
+        if self.scheduler.watchdog_healthy() {
+            watchdog::feed();
+        }
+        let now = Instant::now();
+        let wakeup = self
+            .scheduler
+            .next_wakeup()
+            .unwrap_or(now + MAX_IDLE)
+            .min(now + MAX_IDLE);
+        let ticks = wakeup.saturating_duration_since(now).ticks().max(1);
+        clock::advance(ticks + self.rng.below(self.jitter + 1));
+    }
+
+    /// Step until `duration` of virtual time has passed.
+    pub fn run_for(&mut self, duration: Duration) {
+        let end = Instant::now() + duration;
+        while Instant::now() < end {
+            self.step();
+        }
+    }
+
+    /// Polls recorded so far, in the order they ran.
+    pub fn polls(&self) -> Vec<PollRecord> {
+        self.shared.borrow().polls.clone()
+    }
+}
+
+/// Stack allocations still to fail; see [`Harness::fail_stack_allocs`].
+static STACK_ALLOC_FAILURES: AtomicU32 = AtomicU32::new(0);
+
+/// Whether the stack allocation being made should fail.
+pub(crate) fn stack_alloc_fails() -> bool {
+    STACK_ALLOC_FAILURES
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+            left.checked_sub(1)
+        })
+        .is_ok()
+}
+
+/// Return to power-on state.
+pub(super) fn clear() {
+    STACK_ALLOC_FAILURES.store(0, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        scheduler::ExitStatus,
+        sim::{self, console},
+    };
+
+    /// Sleeps for its own period forever.
+    struct Ticker {
+        name: &'static str,
+        period_ms: u32,
+    }
+
+    impl Task for Ticker {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn watchdog(&self) -> Option<WatchdogConfig> {
+            Some(WatchdogConfig::new(Duration::from_millis(10)))
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            TaskCommand::SleepMs(self.period_ms)
+        }
+    }
+
+    fn tickers(harness: &mut Harness) -> [TaskId; 3] {
+        [("fast", 3), ("mid", 7), ("slow", 11)]
+            .map(|(name, period_ms)| harness.spawn(Box::new(Ticker { name, period_ms })).unwrap())
+    }
+
+    fn run_seed(seed: u64) -> Vec<PollRecord> {
+        let mut harness = Harness::new(seed).with_jitter(Duration::from_millis(4));
+        tickers(&mut harness);
+        harness.run_for(Duration::from_millis(200));
+        harness.polls()
+    }
+
+    #[test]
+    fn same_seed_reproduces_the_same_run() {
+        let first = run_seed(7);
+        assert!(first.len() > 30);
+        assert_eq!(run_seed(7), first);
+        assert_ne!(run_seed(8), first);
+    }
+
+    #[test]
+    fn polls_are_recorded_in_order() {
+        let mut harness = Harness::new(0);
+        let [fast, mid, slow] = tickers(&mut harness);
+        harness.run_for(Duration::from_millis(7));
+
+        let polls: Vec<(u64, TaskId)> = harness
+            .polls()
+            .iter()
+            .map(|poll| (poll.tick, poll.task))
+            .collect();
+        assert_eq!(
+            polls,
+            [(0, fast), (0, mid), (0, slow), (3, fast), (6, fast)]
+        );
+        assert_eq!(harness.polls()[0].command, TaskCommand::SleepMs(3));
+    }
+
+    #[test]
+    fn corrupted_stack_guard_ends_the_task() {
+        let mut harness = Harness::new(1);
+        let [fast, mid, _] = tickers(&mut harness);
+        let handle = harness.scheduler().join_handle(mid).unwrap();
+        harness.step();
+        let _ = console::take_lines();
+
+        harness.inject(mid, Fault::CorruptStackGuard);
+        harness.run_for(Duration::from_millis(10));
+
+        assert_eq!(handle.try_join(), Some(ExitStatus::StackOverflow));
+        assert_eq!(
+            console::take_lines(),
+            ["Stack guard tripped after polling task mid"]
+        );
+        assert_eq!(harness.scheduler().task_count(), 2);
+        assert!(harness
+            .polls()
+            .iter()
+            .any(|poll| poll.task == fast && poll.tick >= 9));
+    }
+
+    #[test]
+    fn hung_task_starves_the_hardware_watchdog() {
+        let mut harness = Harness::new(2);
+        let [fast, ..] = tickers(&mut harness);
+        sim::watchdog::enable(Duration::from_millis(100));
+        // Healthy steps keep feeding it well past its timeout.
+        harness.run_for(Duration::from_millis(300));
+        assert!(!sim::watchdog::expired());
+
+        harness.inject(fast, Fault::Hang(Duration::from_millis(150)));
+        harness.step();
+
+        assert!(sim::watchdog::expired());
+        assert!(console::take_lines()
+            .iter()
+            .any(|line| line == "Watchdog: task fast polled for 150000 us (budget 10000 us)"));
+    }
+
+    #[test]
+    fn failed_stack_allocation_is_reported_to_the_spawner() {
+        let mut harness = Harness::new(3);
+        harness.fail_stack_allocs(1);
+        let ticker = || {
+            Box::new(Ticker {
+                name: "late",
+                period_ms: 1,
+            })
+        };
+
+        assert_eq!(harness.spawn(ticker()), Err(SchedulerError::OutOfMemory));
+        assert!(harness.spawn(ticker()).is_ok());
+        assert_eq!(harness.scheduler().task_count(), 1);
+    }
+}
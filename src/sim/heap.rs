@@ -0,0 +1,44 @@
+//! Heap accounting for the simulation build.
+//!
+//! The host allocator serves all allocations; this wrapper only counts the
+//! bytes in use so [`crate::syscall::HeapStats`] reports real numbers. The
+//! host heap has no fixed size, so the free space reported is the largest
+//! size an allocation may have, less what is in use.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::syscall::HeapStats;
+
+struct CountingAllocator;
+
+static USED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            USED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        USED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Bytes allocated and left on the heap.
+pub fn stats() -> HeapStats {
+    let used = USED.load(Ordering::Relaxed);
+    HeapStats {
+        used,
+        free: (isize::MAX as usize).saturating_sub(used),
+    }
+}
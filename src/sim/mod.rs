@@ -0,0 +1,55 @@
+//! Hosted simulation backend.
+//!
+//! Replaces the ESP32-specific pieces of the kernel (tick interrupt, second
+//! core, console, GPIO and OLED peripherals, watchdog, system reset) with host
+//! implementations so the scheduler and real task implementations can run
+//! under `cargo test` on a workstation.
+//!
+//! There is one simulated board per process, mirroring the kernel's global
+//! state on hardware. Tests claim it with [`board`], which serialises them
+//! and resets the board to power-on state, or with a [`harness::Harness`],
+//! which also drives the scheduler and injects faults.
+
+#[cfg(test)]
+use std::sync::{Mutex, MutexGuard};
+
+pub mod clock;
+pub mod console;
+pub mod cores;
+pub mod display;
+pub mod gpio;
+#[cfg(test)]
+pub mod harness;
+pub mod heap;
+pub mod system;
+pub mod watchdog;
+
+#[cfg(test)]
+static BOARD: Mutex<()> = Mutex::new(());
+
+/// Exclusive claim on the simulated board, released when dropped.
+#[cfg(test)]
+pub struct BoardGuard {
+    _lock: MutexGuard<'static, ()>,
+}
+
+/// Claim the simulated board for the caller and reset it.
+#[cfg(test)]
+pub fn board() -> BoardGuard {
+    // A failed test poisons the lock; the board is reset anyway.
+    let lock = BOARD
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    clock::set(0);
+    clock::clear_alarms();
+    cores::clear();
+    crate::preempt::disable();
+    watchdog::disable();
+    system::clear();
+    harness::clear();
+    crate::syscall::unregister_devices();
+    crate::scheduler::reset_ids();
+    crate::drivers::gpio::BUTTON_EVENTS.clear(u32::MAX);
+    let _ = console::take_lines();
+    BoardGuard { _lock: lock }
+}
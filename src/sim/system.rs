@@ -0,0 +1,24 @@
+//! Simulated system reset.
+//!
+//! Instead of restarting the process, a software reset is recorded so the
+//! simulation loop and tests can ask whether the chip would have rebooted.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+static RESET_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Record a software reset.
+pub fn software_reset() {
+    RESET_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Whether a software reset was requested since the board was reset.
+pub fn reset_requested() -> bool {
+    RESET_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Return to power-on state.
+#[cfg(test)]
+pub(super) fn clear() {
+    RESET_REQUESTED.store(false, Ordering::SeqCst);
+}
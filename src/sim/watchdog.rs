@@ -0,0 +1,46 @@
+//! Simulated TIMG watchdog.
+//!
+//! Records feeds against the virtual clock. Instead of resetting the process
+//! when the timeout passes, the simulation asks [`expired`] whether the chip
+//! would have reset.
+
+use std::sync::Mutex;
+
+use crate::timer::{Duration, Instant};
+
+struct Watchdog {
+    timeout: Duration,
+    last_feed: Instant,
+}
+
+static WATCHDOG: Mutex<Option<Watchdog>> = Mutex::new(None);
+
+/// Start the watchdog with the given timeout.
+pub fn enable(timeout: Duration) {
+    *WATCHDOG.lock().unwrap() = Some(Watchdog {
+        timeout,
+        last_feed: Instant::now(),
+    });
+}
+
+/// Stop the watchdog.
+#[cfg(test)]
+pub fn disable() {
+    *WATCHDOG.lock().unwrap() = None;
+}
+
+/// Restart the timeout.
+pub fn feed() {
+    if let Some(watchdog) = WATCHDOG.lock().unwrap().as_mut() {
+        watchdog.last_feed = Instant::now();
+    }
+}
+
+/// Whether the hardware would have reset the chip by now.
+pub fn expired() -> bool {
+    WATCHDOG
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|watchdog| watchdog.last_feed.elapsed() >= watchdog.timeout)
+}
@@ -0,0 +1,335 @@
+//! Symmetric multiprocessing.
+//!
+//! The ESP32 has two cores, PRO and APP. Each runs its own [`Scheduler`]
+//! over the tasks placed on it; a task stays on its core once it runs. The
+//! task's [`Affinity`] picks that core when it is spawned through [`spawn`]:
+//! pinned tasks go to their core, floating tasks to the core with fewer
+//! tasks. The task waits in the core's inbox until that core's next pass, and
+//! the core's doorbell interrupt ends its idle wait.
+//!
+//! Wakeups cross cores the same way: a waker invoked on one core for a task
+//! on the other rings that core's doorbell after setting the task's signal.
+//!
+//! Kernel objects are shared between cores and guarded by critical sections,
+//! which on hardware mask interrupts and also take a lock shared by both
+//! cores. State that belongs to the task running on a core, such as the
+//! current task, is kept per core in a [`PerCore`].
+//!
+//! The tick timer interrupt runs on the PRO core; it forwards the APP core's
+//! tickless alarms by ringing its doorbell (see [`crate::timer::set_alarm`]),
+//! and rings it on every tick while the APP core runs a thread time slice,
+//! which the doorbell interrupt then counts (see [`crate::preempt`]).
+//!
+//! [`Scheduler`]: crate::scheduler::Scheduler
+
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+
+use critical_section::Mutex;
+use heapless::Deque;
+
+use crate::{
+    arch::{Cpu, Multicore},
+    scheduler::{SchedulerError, TaskBox},
+    timer::{Duration, Instant, MAX_IDLE},
+};
+
+/// Number of cores.
+pub const NUM_CORES: usize = 2;
+
+/// Tasks that can wait in each core's inbox.
+pub const INBOX_CAPACITY: usize = 4;
+
+/// Longest a core other than the one feeding the hardware watchdog may go
+/// without reporting its health before it counts as hung. An idle core
+/// reports at least every [`MAX_IDLE`].
+pub const REPORT_TIMEOUT: Duration = Duration::from_ticks(MAX_IDLE.ticks() * 2);
+
+/// A CPU core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum CoreId {
+    /// Protocol core; boots the kernel and owns the tick timer.
+    Pro = 0,
+    /// Application core.
+    App = 1,
+}
+
+impl CoreId {
+    /// Position of the core in per-core arrays.
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Core at `index` in per-core arrays.
+    pub const fn from_index(index: usize) -> Self {
+        match index {
+            0 => CoreId::Pro,
+            _ => CoreId::App,
+        }
+    }
+}
+
+/// Cores a task may run on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Affinity {
+    /// Only on the given core.
+    Pinned(CoreId),
+    /// On whichever core has fewer tasks when it is spawned.
+    #[default]
+    Floating,
+}
+
+/// Core running the caller.
+pub fn current_core() -> CoreId {
+    Cpu::core_id()
+}
+
+/// End `core`'s idle wait so it runs a scheduler pass.
+pub fn interrupt_core(core: CoreId) {
+    Cpu::interrupt_core(core);
+}
+
+/// Prepare the calling core to be woken by the other one. Call once on each
+/// core before its scheduler runs.
+pub fn init_core() {
+    Cpu::enable_core_interrupt();
+}
+
+/// One `T` per core, each used by code running on that core.
+pub struct PerCore<T>([T; NUM_CORES]);
+
+impl<T> PerCore<T> {
+    pub const fn new(values: [T; NUM_CORES]) -> Self {
+        Self(values)
+    }
+
+    /// The calling core's value.
+    pub fn get(&self) -> &T {
+        self.of(current_core())
+    }
+
+    /// `core`'s value.
+    pub fn of(&self, core: CoreId) -> &T {
+        &self.0[core.index()]
+    }
+}
+
+/// Task on its way to another core.
+struct SendTask(TaskBox);
+
+// SAFETY: only built by `spawn` from a task that is `Send`.
+unsafe impl Send for SendTask {}
+
+static INBOXES: [Mutex<RefCell<Deque<SendTask, INBOX_CAPACITY>>>; NUM_CORES] =
+    [const { Mutex::new(RefCell::new(Deque::new())) }; NUM_CORES];
+
+/// Tasks on each core, as last reported by its scheduler.
+static LOADS: [AtomicUsize; NUM_CORES] = [const { AtomicUsize::new(0) }; NUM_CORES];
+
+/// Start `task` on the core its [`Affinity`] selects, returning that core.
+/// It joins the core's scheduler at the end of the core's next pass.
+pub fn spawn<T>(task: T) -> Result<CoreId, SchedulerError>
+where
+    T: Into<TaskBox> + Send,
+{
+    let task = task.into();
+    let core = match task.affinity() {
+        Affinity::Pinned(core) => core,
+        Affinity::Floating => least_loaded(),
+    };
+    critical_section::with(|cs| {
+        INBOXES[core.index()]
+            .borrow_ref_mut(cs)
+            .push_back(SendTask(task))
+            .map_err(|_| SchedulerError::NoCapacity)
+    })?;
+    if core != current_core() {
+        interrupt_core(core);
+    }
+    Ok(core)
+}
+
+/// Core with the fewest tasks, counting those still in its inbox, preferring
+/// the calling core on a tie.
+fn least_loaded() -> CoreId {
+    let here = current_core();
+    let load = |core: CoreId| {
+        let queued = critical_section::with(|cs| INBOXES[core.index()].borrow_ref(cs).len());
+        LOADS[core.index()].load(Ordering::Relaxed) + queued
+    };
+    (0..NUM_CORES)
+        .map(CoreId::from_index)
+        .filter(|&core| core != here)
+        .fold(
+            here,
+            |best, core| {
+                if load(core) < load(best) {
+                    core
+                } else {
+                    best
+                }
+            },
+        )
+}
+
+/// Next task waiting for `core`.
+pub(crate) fn take_spawned(core: CoreId) -> Option<TaskBox> {
+    critical_section::with(|cs| INBOXES[core.index()].borrow_ref_mut(cs).pop_front())
+        .map(|task| task.0)
+}
+
+/// Whether tasks are waiting for `core`.
+pub(crate) fn has_spawned(core: CoreId) -> bool {
+    critical_section::with(|cs| !INBOXES[core.index()].borrow_ref(cs).is_empty())
+}
+
+/// Record how many tasks `core`'s scheduler runs.
+pub(crate) fn set_load(core: CoreId, tasks: usize) {
+    LOADS[core.index()].store(tasks, Ordering::Relaxed);
+}
+
+/// When each core last reported to the watchdog, in ticks; `u64::MAX` for a
+/// core that never has.
+static REPORTED: [AtomicU64; NUM_CORES] = [const { AtomicU64::new(u64::MAX) }; NUM_CORES];
+
+/// Cores that reported a watchdog violation since the last check.
+static UNHEALTHY: [AtomicBool; NUM_CORES] = [const { AtomicBool::new(false) }; NUM_CORES];
+
+/// Report the calling core's
+/// [`Scheduler::watchdog_healthy`](crate::scheduler::Scheduler::watchdog_healthy)
+/// to the core that feeds the hardware watchdog. Call on every loop.
+pub fn report_health(healthy: bool) {
+    let core = current_core().index();
+    if !healthy {
+        UNHEALTHY[core].store(true, Ordering::Release);
+    }
+    REPORTED[core].store(Instant::now().ticks(), Ordering::Release);
+}
+
+/// Whether every other core that has reported is still reporting, with no
+/// violations since the previous call.
+pub fn other_cores_healthy() -> bool {
+    let now = Instant::now();
+    let here = current_core().index();
+    let mut healthy = true;
+    for core in (0..NUM_CORES).filter(|&core| core != here) {
+        healthy &= !UNHEALTHY[core].swap(false, Ordering::AcqRel);
+        let reported = REPORTED[core].load(Ordering::Acquire);
+        if reported != u64::MAX {
+            healthy &=
+                now.saturating_duration_since(Instant::from_ticks(reported)) <= REPORT_TIMEOUT;
+        }
+    }
+    healthy
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use core::sync::atomic::AtomicU32;
+    use std::{sync::Mutex as StdMutex, time::Instant as HostInstant};
+
+    use super::*;
+    use crate::{
+        event::TaskNotifier,
+        scheduler::{Scheduler, Task, TaskCommand, TaskContext},
+        sim::{self, cores},
+    };
+
+    struct Job;
+
+    impl Task for Job {
+        fn name(&self) -> &'static str {
+            "job"
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            TaskCommand::Finished
+        }
+    }
+
+    #[test]
+    fn floating_tasks_go_to_the_least_loaded_core() {
+        let _board = sim::board();
+        set_load(CoreId::Pro, 3);
+        set_load(CoreId::App, 1);
+
+        assert_eq!(spawn(Box::new(Job)), Ok(CoreId::App));
+        assert_eq!(spawn(Box::new(Job)), Ok(CoreId::App));
+        // Two queued plus one running on the APP core ties with the PRO core.
+        assert_eq!(spawn(Box::new(Job)), Ok(CoreId::Pro));
+
+        while take_spawned(CoreId::App).is_some() {}
+        while take_spawned(CoreId::Pro).is_some() {}
+        set_load(CoreId::App, 0);
+    }
+
+    static APP_CORE_RUNNING: AtomicBool = AtomicBool::new(false);
+    static RAN_ON: AtomicU32 = AtomicU32::new(u32::MAX);
+    static RECEIVED: AtomicU32 = AtomicU32::new(0);
+    static NOTIFIER: StdMutex<Option<TaskNotifier>> = StdMutex::new(None);
+
+    /// Pinned to the APP core; parks until notified from the PRO core.
+    struct Probe;
+
+    impl Task for Probe {
+        fn name(&self) -> &'static str {
+            "probe"
+        }
+
+        fn affinity(&self) -> Affinity {
+            Affinity::Pinned(CoreId::App)
+        }
+
+        fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+            RAN_ON.store(current_core().index() as u32, Ordering::SeqCst);
+            let bits = ctx.take_notifications(u32::MAX);
+            if bits != 0 {
+                RECEIVED.store(bits, Ordering::SeqCst);
+                return TaskCommand::Finished;
+            }
+            *NOTIFIER.lock().unwrap() = Some(ctx.notifier());
+            TaskCommand::Park(None)
+        }
+    }
+
+    /// Spin on the PRO core until `done` holds, failing after a few seconds.
+    fn wait_until(done: impl Fn() -> bool) {
+        let start = HostInstant::now();
+        while !done() {
+            assert!(start.elapsed().as_secs() < 5, "APP core did not respond");
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn pinned_task_runs_on_app_core_and_is_woken_across_cores() {
+        let _board = sim::board();
+        APP_CORE_RUNNING.store(true, Ordering::SeqCst);
+        let app_core = cores::start_core(CoreId::App, || {
+            let mut scheduler = Scheduler::new().on_core(CoreId::App);
+            while APP_CORE_RUNNING.load(Ordering::SeqCst) {
+                scheduler.run_ready();
+                scheduler.idle();
+            }
+        });
+
+        assert_eq!(spawn(Box::new(Probe)), Ok(CoreId::App));
+        wait_until(|| NOTIFIER.lock().unwrap().is_some());
+        assert_eq!(RAN_ON.load(Ordering::SeqCst), CoreId::App.index() as u32);
+
+        // The APP core is idle waiting for an event; the notification must
+        // interrupt it.
+        let notifier = NOTIFIER.lock().unwrap().take().unwrap();
+        notifier.notify(0b10);
+        wait_until(|| RECEIVED.load(Ordering::SeqCst) != 0);
+        assert_eq!(RECEIVED.load(Ordering::SeqCst), 0b10);
+
+        APP_CORE_RUNNING.store(false, Ordering::SeqCst);
+        interrupt_core(CoreId::App);
+        app_core.join().unwrap();
+    }
+}
@@ -0,0 +1,498 @@
+//! Software timers.
+//!
+//! A [`TimerService`] runs small timed actions (debounce timeouts, display
+//! auto-off, blink codes) without a task of their own. Timers are one-shot or
+//! periodic and, when they expire, call a function, notify a task or set bits
+//! in an [`EventGroup`]. Any task can create, start, stop and reset them.
+//!
+//! Armed timers sit in a hashed timer wheel of [`WHEEL_SLOTS`] buckets
+//! indexed by expiry tick, so starting and stopping a timer only touches one
+//! bucket. The service's [`TimerTask`] parks until the earliest expiry and
+//! then turns the wheel over the ticks that have passed, so armed timers do
+//! not keep the CPU awake in tickless mode. Actions run in that task's context
+//! and must be short.
+
+use core::cell::RefCell;
+
+use critical_section::{CriticalSection, Mutex};
+use heapless::Vec;
+
+use crate::{
+    event::{EventGroup, TaskNotifier, WaitQueue},
+    scheduler::{Task, TaskCommand, TaskContext, TaskPriority},
+    timer::{Duration, Instant},
+};
+
+/// Maximum number of timers per service.
+pub const MAX_TIMERS: usize = 16;
+
+/// Buckets in the timer wheel; one tick each.
+pub const WHEEL_SLOTS: usize = 32;
+
+/// What an expired timer does.
+#[derive(Clone)]
+#[allow(dead_code, reason = "the firmware arms no timers of its own")]
+pub enum TimerAction {
+    /// Call the function from the timer task.
+    Callback(fn()),
+    /// Set notification bits on a task.
+    Notify(TaskNotifier, u32),
+    /// Set bits in an event group.
+    Events(&'static EventGroup, u32),
+}
+
+impl TimerAction {
+    fn fire(self) {
+        match self {
+            TimerAction::Callback(callback) => callback(),
+            TimerAction::Notify(notifier, bits) => notifier.notify(bits),
+            TimerAction::Events(group, bits) => group.set(bits),
+        }
+    }
+}
+
+/// Whether a timer re-arms itself after expiring.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code, reason = "the firmware arms no timers of its own")]
+pub enum TimerMode {
+    OneShot,
+    Periodic,
+}
+
+/// Errors from timer operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerError {
+    /// All timers are in use.
+    NoCapacity,
+    /// The timer was deleted.
+    NoSuchTimer,
+}
+
+/// Handle to a timer created with [`TimerService::create`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerHandle {
+    index: u8,
+    generation: u16,
+}
+
+struct Entry {
+    interval: Duration,
+    mode: TimerMode,
+    action: TimerAction,
+    /// Expiry while armed.
+    expiry: Option<Instant>,
+    /// Next armed entry in the same wheel bucket.
+    next: Option<u8>,
+}
+
+struct Wheel {
+    entries: [Option<Entry>; MAX_TIMERS],
+    /// Bumped when an entry is deleted, invalidating its handles.
+    generations: [u16; MAX_TIMERS],
+    buckets: [Option<u8>; WHEEL_SLOTS],
+    /// First tick not yet processed.
+    cursor: Instant,
+}
+
+impl Wheel {
+    const fn new() -> Self {
+        Self {
+            entries: [const { None }; MAX_TIMERS],
+            generations: [0; MAX_TIMERS],
+            buckets: [None; WHEEL_SLOTS],
+            cursor: Instant::from_ticks(0),
+        }
+    }
+
+    fn entry(&mut self, handle: TimerHandle) -> Result<&mut Entry, TimerError> {
+        let index = handle.index as usize;
+        if self.generations[index] != handle.generation {
+            return Err(TimerError::NoSuchTimer);
+        }
+        self.entries[index].as_mut().ok_or(TimerError::NoSuchTimer)
+    }
+
+    /// Arm entry `index` to expire at `expiry`.
+    fn insert(&mut self, index: u8, expiry: Instant) {
+        // A tick the wheel has already passed would only be seen a whole
+        // revolution later.
+        let expiry = expiry.max(self.cursor);
+        let bucket = bucket(expiry);
+        if let Some(entry) = self.entries[index as usize].as_mut() {
+            entry.expiry = Some(expiry);
+            entry.next = self.buckets[bucket];
+            self.buckets[bucket] = Some(index);
+        }
+    }
+
+    /// Disarm entry `index` if it is armed.
+    fn remove(&mut self, index: u8) {
+        let Some(expiry) = self.entries[index as usize]
+            .as_mut()
+            .and_then(|entry| entry.expiry.take())
+        else {
+            return;
+        };
+        let unlinked = self.entries[index as usize]
+            .as_mut()
+            .and_then(|entry| entry.next.take());
+        let mut link = &mut self.buckets[bucket(expiry)];
+        while let Some(current) = *link {
+            if current == index {
+                *link = unlinked;
+                return;
+            }
+            link = match self.entries[current as usize].as_mut() {
+                Some(entry) => &mut entry.next,
+                None => return,
+            };
+        }
+    }
+
+    /// Disarm every timer due at or before `now`, re-arming periodic ones,
+    /// and return their actions.
+    fn expire(&mut self, now: Instant) -> Vec<TimerAction, MAX_TIMERS> {
+        let mut due = Vec::new();
+        if now < self.cursor {
+            return due;
+        }
+        // After a long gap every bucket is visited once.
+        let span = ((now - self.cursor).ticks() + 1).min(WHEEL_SLOTS as u64);
+        for offset in 0..span {
+            let bucket = bucket(self.cursor + Duration::from_ticks(offset));
+            let mut next = self.buckets[bucket];
+            while let Some(index) = next {
+                let Some(entry) = self.entries[index as usize].as_ref() else {
+                    break;
+                };
+                next = entry.next;
+                if entry.expiry.is_some_and(|expiry| expiry <= now) {
+                    self.take_due(index, now, &mut due);
+                }
+            }
+        }
+        self.cursor = now + Duration::from_ticks(1);
+        due
+    }
+
+    /// Earliest expiry of any armed timer.
+    fn next_expiry(&self) -> Option<Instant> {
+        self.entries
+            .iter()
+            .flatten()
+            .filter_map(|entry| entry.expiry)
+            .min()
+    }
+
+    /// Take the action of expired entry `index` and re-arm it if periodic.
+    fn take_due(&mut self, index: u8, now: Instant, due: &mut Vec<TimerAction, MAX_TIMERS>) {
+        let expiry = self.entries[index as usize]
+            .as_ref()
+            .and_then(|entry| entry.expiry);
+        self.remove(index);
+        let Some(entry) = self.entries[index as usize].as_ref() else {
+            return;
+        };
+        let _ = due.push(entry.action.clone());
+        if let (TimerMode::Periodic, Some(expiry)) = (entry.mode, expiry) {
+            // Stay in phase, skipping periods that passed while the task was late.
+            let interval = entry.interval.max(Duration::from_ticks(1));
+            let mut next = expiry + interval;
+            if next <= now {
+                let missed = (now - next).ticks() / interval.ticks() + 1;
+                next += Duration::from_ticks(missed * interval.ticks());
+            }
+            self.insert(index, next);
+        }
+    }
+}
+
+fn bucket(expiry: Instant) -> usize {
+    (expiry.ticks() % WHEEL_SLOTS as u64) as usize
+}
+
+/// Set of software timers driven by a [`TimerTask`].
+pub struct TimerService {
+    wheel: Mutex<RefCell<Wheel>>,
+    /// The timer task, woken when the earliest expiry may have changed.
+    daemon: WaitQueue,
+}
+
+impl TimerService {
+    pub const fn new() -> Self {
+        Self {
+            wheel: Mutex::new(RefCell::new(Wheel::new())),
+            daemon: WaitQueue::new(),
+        }
+    }
+
+    /// Create a stopped timer that runs `action` `interval` after it starts
+    /// and, in [`TimerMode::Periodic`], every `interval` after that.
+    #[allow(dead_code, reason = "the firmware arms no timers of its own")]
+    pub fn create(
+        &self,
+        interval: Duration,
+        mode: TimerMode,
+        action: TimerAction,
+    ) -> Result<TimerHandle, TimerError> {
+        critical_section::with(|cs| {
+            let mut wheel = self.wheel.borrow_ref_mut(cs);
+            let index = wheel
+                .entries
+                .iter()
+                .position(Option::is_none)
+                .ok_or(TimerError::NoCapacity)?;
+            wheel.entries[index] = Some(Entry {
+                interval,
+                mode,
+                action,
+                expiry: None,
+                next: None,
+            });
+            Ok(TimerHandle {
+                index: index as u8,
+                generation: wheel.generations[index],
+            })
+        })
+    }
+
+    /// Arm the timer to expire one interval from now. A running timer keeps
+    /// its expiry.
+    #[allow(dead_code, reason = "the firmware arms no timers of its own")]
+    pub fn start(&self, handle: TimerHandle) -> Result<(), TimerError> {
+        self.arm(handle, false)
+    }
+
+    /// Restart the timer one interval from now, whether or not it is running.
+    #[allow(dead_code, reason = "the firmware arms no timers of its own")]
+    pub fn reset(&self, handle: TimerHandle) -> Result<(), TimerError> {
+        self.arm(handle, true)
+    }
+
+    /// Change the interval and restart the timer.
+    #[allow(dead_code, reason = "the firmware arms no timers of its own")]
+    pub fn set_interval(&self, handle: TimerHandle, interval: Duration) -> Result<(), TimerError> {
+        self.with_timer(handle, |_, wheel| {
+            wheel.entry(handle)?.interval = interval;
+            Ok(())
+        })?;
+        self.reset(handle)
+    }
+
+    /// Stop the timer without deleting it.
+    #[allow(dead_code, reason = "the firmware arms no timers of its own")]
+    pub fn stop(&self, handle: TimerHandle) -> Result<(), TimerError> {
+        self.with_timer(handle, |_, wheel| {
+            wheel.entry(handle)?;
+            wheel.remove(handle.index);
+            Ok(())
+        })
+    }
+
+    /// Stop the timer and free it; the handle becomes invalid.
+    #[allow(dead_code, reason = "the firmware arms no timers of its own")]
+    pub fn delete(&self, handle: TimerHandle) -> Result<(), TimerError> {
+        self.with_timer(handle, |_, wheel| {
+            wheel.entry(handle)?;
+            wheel.remove(handle.index);
+            let index = handle.index as usize;
+            wheel.entries[index] = None;
+            wheel.generations[index] = wheel.generations[index].wrapping_add(1);
+            Ok(())
+        })
+    }
+
+    /// Whether the timer is armed.
+    #[allow(dead_code, reason = "the firmware arms no timers of its own")]
+    pub fn is_active(&self, handle: TimerHandle) -> bool {
+        self.with_timer(handle, |_, wheel| Ok(wheel.entry(handle)?.expiry.is_some()))
+            .unwrap_or(false)
+    }
+
+    /// Task that runs this service's timers.
+    pub fn task(&'static self) -> TimerTask {
+        TimerTask { service: self }
+    }
+
+    fn arm(&self, handle: TimerHandle, restart: bool) -> Result<(), TimerError> {
+        self.with_timer(handle, |cs, wheel| {
+            let entry = wheel.entry(handle)?;
+            if entry.expiry.is_some() && !restart {
+                return Ok(());
+            }
+            let interval = entry.interval;
+            wheel.remove(handle.index);
+            wheel.insert(handle.index, Instant::now() + interval);
+            self.daemon.wake_all(cs);
+            Ok(())
+        })
+    }
+
+    fn with_timer<R>(
+        &self,
+        handle: TimerHandle,
+        f: impl FnOnce(CriticalSection<'_>, &mut Wheel) -> Result<R, TimerError>,
+    ) -> Result<R, TimerError> {
+        if handle.index as usize >= MAX_TIMERS {
+            return Err(TimerError::NoSuchTimer);
+        }
+        critical_section::with(|cs| f(cs, &mut self.wheel.borrow_ref_mut(cs)))
+    }
+}
+
+/// Kernel timer service used by tasks.
+pub static TIMERS: TimerService = TimerService::new();
+
+/// Task that fires the expired timers of a [`TimerService`].
+pub struct TimerTask {
+    service: &'static TimerService,
+}
+
+impl Task for TimerTask {
+    fn name(&self) -> &'static str {
+        "timers"
+    }
+
+    fn priority(&self) -> TaskPriority {
+        TaskPriority::High
+    }
+
+    fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+        let due = critical_section::with(|cs| {
+            self.service.daemon.register(cs, ctx.waker());
+            self.service.wheel.borrow_ref_mut(cs).expire(ctx.now)
+        });
+        for action in due {
+            action.fire();
+        }
+        let next = critical_section::with(|cs| self.service.wheel.borrow_ref(cs).next_expiry());
+        TaskCommand::Park(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::{scheduler::Scheduler, sim};
+
+    fn run_until(scheduler: &mut Scheduler, tick: u64) {
+        while Instant::now().ticks() < tick {
+            sim::clock::advance(1);
+            scheduler.run_ready();
+        }
+    }
+
+    #[test]
+    fn one_shot_fires_once_and_periodic_keeps_firing() {
+        static SERVICE: TimerService = TimerService::new();
+        static FIRED: AtomicU32 = AtomicU32::new(0);
+        static TICKS: EventGroup = EventGroup::new();
+        let _board = sim::board();
+        let mut scheduler = Scheduler::new();
+        scheduler.spawn(Box::new(SERVICE.task())).unwrap();
+
+        let once = SERVICE
+            .create(
+                Duration::from_ticks(5),
+                TimerMode::OneShot,
+                TimerAction::Callback(|| {
+                    FIRED.fetch_add(1, Ordering::Relaxed);
+                }),
+            )
+            .unwrap();
+        let periodic = SERVICE
+            .create(
+                Duration::from_ticks(10),
+                TimerMode::Periodic,
+                TimerAction::Events(&TICKS, 1),
+            )
+            .unwrap();
+        SERVICE.start(once).unwrap();
+        SERVICE.start(periodic).unwrap();
+        scheduler.run_ready();
+
+        run_until(&mut scheduler, 4);
+        assert_eq!(FIRED.load(Ordering::Relaxed), 0);
+        run_until(&mut scheduler, 5);
+        assert_eq!(FIRED.load(Ordering::Relaxed), 1);
+        assert!(!SERVICE.is_active(once));
+
+        run_until(&mut scheduler, 10);
+        assert_eq!(TICKS.clear(1), 1);
+        run_until(&mut scheduler, 19);
+        assert_eq!(TICKS.get(), 0);
+        run_until(&mut scheduler, 20);
+        assert_eq!(TICKS.clear(1), 1);
+        assert_eq!(FIRED.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn stop_reset_and_delete() {
+        static SERVICE: TimerService = TimerService::new();
+        static EVENTS: EventGroup = EventGroup::new();
+        let _board = sim::board();
+        let mut scheduler = Scheduler::new();
+        scheduler.spawn(Box::new(SERVICE.task())).unwrap();
+        let timer = SERVICE
+            .create(
+                Duration::from_ticks(3),
+                TimerMode::OneShot,
+                TimerAction::Events(&EVENTS, 1),
+            )
+            .unwrap();
+
+        SERVICE.start(timer).unwrap();
+        SERVICE.stop(timer).unwrap();
+        run_until(&mut scheduler, 5);
+        assert_eq!(EVENTS.get(), 0);
+
+        // Resetting postpones the expiry; starting again does not.
+        SERVICE.start(timer).unwrap();
+        run_until(&mut scheduler, 7);
+        SERVICE.start(timer).unwrap();
+        SERVICE.reset(timer).unwrap();
+        run_until(&mut scheduler, 9);
+        assert_eq!(EVENTS.get(), 0);
+        run_until(&mut scheduler, 10);
+        assert_eq!(EVENTS.clear(1), 1);
+
+        // Beyond one wheel revolution.
+        SERVICE
+            .set_interval(timer, Duration::from_ticks(40))
+            .unwrap();
+        run_until(&mut scheduler, 49);
+        assert_eq!(EVENTS.get(), 0);
+        run_until(&mut scheduler, 50);
+        assert_eq!(EVENTS.get(), 1);
+
+        SERVICE.delete(timer).unwrap();
+        assert_eq!(SERVICE.start(timer), Err(TimerError::NoSuchTimer));
+    }
+
+    #[test]
+    fn timers_fire_after_a_long_gap() {
+        static SERVICE: TimerService = TimerService::new();
+        static EVENTS: EventGroup = EventGroup::new();
+        let _board = sim::board();
+        let mut scheduler = Scheduler::new();
+        scheduler.spawn(Box::new(SERVICE.task())).unwrap();
+        for bit in [1, 2] {
+            let timer = SERVICE
+                .create(
+                    Duration::from_ticks(bit as u64 * 7),
+                    TimerMode::OneShot,
+                    TimerAction::Events(&EVENTS, bit),
+                )
+                .unwrap();
+            SERVICE.start(timer).unwrap();
+        }
+
+        sim::clock::advance(100);
+        scheduler.run_ready();
+
+        assert_eq!(EVENTS.get(), 3);
+    }
+}
@@ -1,17 +1,24 @@
 //! Task stack management utilities.
 //!
 //! Provides guard-protected stack allocations backed by the global heap.
+//!
+//! Stacks are painted with a fill pattern when allocated, so
+//! [`TaskStack::high_water_mark`] can tell how deep a task has ever used its
+//! stack and `Task::stack_size` can be sized from measurements.
 
 use alloc::alloc::{alloc, dealloc};
 use core::{
     alloc::Layout,
     mem,
     ptr::{self, NonNull},
+    slice,
 };
 
 const STACK_ALIGN: usize = 16;
 const CANARY: u32 = 0xDEADBEEF;
 const CANARY_BYTES: usize = mem::size_of::<u32>();
+/// Fill byte for unused stack.
+const PAINT: u8 = 0xA5;
 
 fn allocation_size(stack_size: usize) -> usize {
     stack_size + CANARY_BYTES * 2
@@ -32,12 +39,16 @@ pub struct TaskStack {
 impl TaskStack {
     /// Allocate a new stack of the requested size.
     pub fn new(size: usize) -> Option<Self> {
+        #[cfg(test)]
+        if crate::sim::harness::stack_alloc_fails() {
+            return None;
+        }
         let layout = stack_layout(size)?;
         let raw = unsafe { alloc(layout) };
         let base = NonNull::<u8>::new(raw)?;
 
         unsafe {
-            ptr::write_bytes(base.as_ptr(), 0, allocation_size(size));
+            ptr::write_bytes(base.as_ptr(), PAINT, allocation_size(size));
             (base.as_ptr() as *mut u32).write_unaligned(CANARY);
             base.as_ptr()
                 .add(CANARY_BYTES + size)
@@ -56,23 +67,35 @@ impl TaskStack {
     }
 
     /// Pointer to the bottom of the usable stack region.
-    #[allow(dead_code)]
     pub fn bottom(&self) -> *mut u8 {
         self.stack_ptr.as_ptr()
     }
 
     /// Pointer to the top (exclusive) of the usable stack region.
-    #[allow(dead_code)]
+    #[allow(dead_code, reason = "only Xtensa context setup uses the top")]
     pub fn top(&self) -> *mut u8 {
         unsafe { self.stack_ptr.as_ptr().add(self.size) }
     }
 
     /// Total usable size of the stack in bytes.
-    #[allow(dead_code)]
     pub fn len(&self) -> usize {
         self.size
     }
 
+    /// Most bytes of the stack in use at any time so far. The stack grows down
+    /// from [`Self::top`], so this is measured from the top to the deepest
+    /// byte that no longer holds the paint pattern. Data that happens to
+    /// equal the pattern can make it read a few bytes low.
+    pub fn high_water_mark(&self) -> usize {
+        // SAFETY: the region is allocated for the lifetime of `self`, and the
+        // task using it is not running while the scheduler inspects it.
+        let stack = unsafe { slice::from_raw_parts(self.stack_ptr.as_ptr(), self.size) };
+        stack
+            .iter()
+            .position(|&byte| byte != PAINT)
+            .map_or(0, |deepest| self.size - deepest)
+    }
+
     /// Check guard words for overflow.
     pub fn verify(&self) -> bool {
         unsafe {
@@ -91,6 +114,18 @@ impl TaskStack {
             lower == CANARY && upper == CANARY
         }
     }
+
+    /// Overwrite the lower guard word, as a stack overflow would.
+    #[cfg(test)]
+    pub fn clobber_guard(&self) {
+        unsafe {
+            self.stack_ptr
+                .as_ptr()
+                .sub(CANARY_BYTES)
+                .cast::<u32>()
+                .write_unaligned(!CANARY);
+        }
+    }
 }
 
 impl Drop for TaskStack {
@@ -103,3 +138,26 @@ impl Drop for TaskStack {
 
 /// Default task stack size (bytes).
 pub const DEFAULT_STACK_SIZE: usize = 4 * 1024;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim;
+
+    #[test]
+    fn high_water_mark_tracks_deepest_write() {
+        // Stack allocation can be made to fail through the board.
+        let _board = sim::board();
+        let stack = TaskStack::new(256).unwrap();
+        assert_eq!(stack.high_water_mark(), 0);
+
+        unsafe {
+            stack.top().sub(16).write(0);
+            stack.top().sub(100).write(1);
+            stack.top().sub(40).write(2);
+        }
+
+        assert_eq!(stack.high_water_mark(), 100);
+        assert!(stack.verify());
+    }
+}
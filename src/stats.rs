@@ -0,0 +1,217 @@
+//! Per-task runtime statistics.
+//!
+//! The scheduler times every poll and records how late each task ran compared
+//! to its wakeup deadline. [`Scheduler::stats`](crate::scheduler::Scheduler::stats)
+//! returns a [`SchedulerStats`] snapshot covering the window since the last
+//! [`Scheduler::reset_stats`](crate::scheduler::Scheduler::reset_stats), which
+//! prints as a `top`-style table.
+
+use core::fmt;
+
+use heapless::Vec;
+
+use crate::{
+    scheduler::{TaskId, TaskPriority, MAX_TASKS},
+    timer::Duration,
+};
+
+/// Counters kept for each task.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TaskStats {
+    /// Number of times the task was polled.
+    pub polls: u32,
+    /// Total time spent inside `poll`, in microseconds.
+    pub busy_us: u64,
+    /// Longest single poll, in microseconds.
+    pub max_poll_us: u64,
+    /// Polls that started after the task's wakeup deadline had passed.
+    pub missed_deadlines: u32,
+    /// Largest delay between a wakeup deadline and the poll that served it.
+    pub max_lateness: Duration,
+    /// Watchdog budget violations (see [`crate::watchdog`]).
+    pub watchdog_violations: u32,
+}
+
+impl TaskStats {
+    /// Account for one poll that took `busy_us` and started `lateness` after
+    /// its deadline.
+    pub(crate) fn record_poll(&mut self, busy_us: u64, lateness: Duration) {
+        self.polls = self.polls.saturating_add(1);
+        self.busy_us = self.busy_us.saturating_add(busy_us);
+        self.max_poll_us = self.max_poll_us.max(busy_us);
+        if lateness > Duration::ZERO {
+            self.missed_deadlines = self.missed_deadlines.saturating_add(1);
+            self.max_lateness = self.max_lateness.max(lateness);
+        }
+    }
+}
+
+/// Statistics for one task.
+#[derive(Debug, Clone, Copy)]
+pub struct TaskSnapshot {
+    pub id: TaskId,
+    pub name: &'static str,
+    pub priority: TaskPriority,
+    pub stats: TaskStats,
+    /// Reserved stack size, in bytes.
+    pub stack_size: usize,
+    /// Stack high-water mark, in bytes (see [`crate::stack::TaskStack::high_water_mark`]).
+    pub stack_used: usize,
+}
+
+/// Statistics for all live tasks of a scheduler of `N` tasks over one
+/// measurement window.
+#[derive(Debug, Clone)]
+pub struct SchedulerStats<const N: usize = MAX_TASKS> {
+    /// Length of the window, in microseconds.
+    pub elapsed_us: u64,
+    pub tasks: Vec<TaskSnapshot, N>,
+}
+
+impl<const N: usize> SchedulerStats<N> {
+    /// Share of the window `task` spent being polled, in percent.
+    pub fn cpu_percent(&self, task: &TaskSnapshot) -> u32 {
+        percent(task.stats.busy_us, self.elapsed_us)
+    }
+
+    /// Time in the window not spent polling any live task, in microseconds.
+    pub fn idle_us(&self) -> u64 {
+        let busy: u64 = self.tasks.iter().map(|task| task.stats.busy_us).sum();
+        self.elapsed_us.saturating_sub(busy)
+    }
+
+    /// Share of the window not spent polling tasks, in percent.
+    #[allow(dead_code, reason = "for callers formatting their own report")]
+    pub fn idle_percent(&self) -> u32 {
+        percent(self.idle_us(), self.elapsed_us)
+    }
+}
+
+fn percent(part: u64, whole: u64) -> u32 {
+    if whole == 0 {
+        return 0;
+    }
+    (part.min(whole) * 100 / whole) as u32
+}
+
+impl<const N: usize> fmt::Display for SchedulerStats<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>4} {:<12} {:<6} {:>4} {:>8} {:>9} {:>5} {:>8} {:>10}",
+            "ID", "NAME", "PRIO", "CPU%", "POLLS", "MAX(us)", "LATE", "WORST(ms)", "STACK(B)"
+        )?;
+        for task in &self.tasks {
+            writeln!(
+                f,
+                "{:>4} {:<12} {:<6} {:>4} {:>8} {:>9} {:>5} {:>8} {:>5}/{}",
+                task.id,
+                task.name,
+                priority_label(task.priority),
+                self.cpu_percent(task),
+                task.stats.polls,
+                task.stats.max_poll_us,
+                task.stats.missed_deadlines,
+                task.stats.max_lateness.as_millis(),
+                task.stack_used,
+                task.stack_size,
+            )?;
+        }
+        write!(
+            f,
+            "idle {}% of {} ms",
+            percent(self.idle_us(), self.elapsed_us),
+            self.elapsed_us / 1_000
+        )
+    }
+}
+
+fn priority_label(priority: TaskPriority) -> &'static str {
+    match priority {
+        TaskPriority::Low => "low",
+        TaskPriority::Normal => "normal",
+        TaskPriority::High => "high",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+
+    use super::*;
+    use crate::{
+        scheduler::{Scheduler, Task, TaskCommand, TaskContext},
+        sim,
+        timer::Instant,
+    };
+
+    /// Busy for `busy_ms` of every `period_ms` (sleeps are measured from the
+    /// start of the pass).
+    struct Worker {
+        busy_ms: u32,
+        period_ms: u32,
+    }
+
+    impl Task for Worker {
+        fn name(&self) -> &'static str {
+            "worker"
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            sim::clock::advance_ms(self.busy_ms);
+            TaskCommand::SleepMs(self.period_ms)
+        }
+    }
+
+    #[test]
+    fn busy_task_reports_its_cpu_share() {
+        let _board = sim::board();
+        let mut scheduler = Scheduler::new();
+        scheduler
+            .spawn(Box::new(Worker {
+                busy_ms: 2,
+                period_ms: 8,
+            }))
+            .unwrap();
+
+        while Instant::now().ticks() < 80 {
+            scheduler.run_ready();
+            sim::clock::advance(1);
+        }
+
+        let stats = scheduler.stats();
+        let worker = &stats.tasks[0];
+        assert_eq!(stats.elapsed_us, 80_000);
+        assert_eq!(worker.stats.polls, 10);
+        assert_eq!(worker.stats.max_poll_us, 2_000);
+        assert_eq!(stats.cpu_percent(worker), 25);
+        assert_eq!(stats.idle_percent(), 75);
+        assert_eq!(worker.stats.missed_deadlines, 0);
+
+        scheduler.reset_stats();
+        assert_eq!(scheduler.stats().tasks[0].stats, TaskStats::default());
+    }
+
+    #[test]
+    fn late_polls_count_as_missed_deadlines() {
+        let _board = sim::board();
+        let mut scheduler = Scheduler::new();
+        scheduler
+            .spawn(Box::new(Worker {
+                busy_ms: 0,
+                period_ms: 8,
+            }))
+            .unwrap();
+
+        // Only poll every 5 ms: deadlines at 8, 18, 28 are served 2 ms late.
+        for _ in 0..7 {
+            scheduler.run_ready();
+            sim::clock::advance_ms(5);
+        }
+
+        let worker = scheduler.stats().tasks[0];
+        assert_eq!(worker.stats.polls, 4);
+        assert_eq!(worker.stats.missed_deadlines, 3);
+        assert_eq!(worker.stats.max_lateness, Duration::from_millis(2));
+    }
+}
@@ -0,0 +1,409 @@
+//! Supervision of failing tasks.
+//!
+//! A [`Supervisor`] is a task that starts a group of children from factories
+//! and restarts them when they fail, so a stack overflow or an error exit
+//! does not silently take a feature down. A child fails when it overruns its
+//! stack or exits with a non-zero code; a zero exit means it is done, and a
+//! killed child stays down.
+//!
+//! Each child has a [`RestartPolicy`] and an optional limit on restarts per
+//! time window. Exceeding the limit escalates: by default the system reboots.
+//! With [`Strategy::OneForAll`] the failure of one child restarts the whole
+//! group, for tasks that depend on each other's state.
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::{
+    println,
+    scheduler::{ExitStatus, JoinHandle, Task, TaskBox, TaskCommand, TaskContext, TaskPriority},
+    timer::{Duration, Instant},
+};
+
+/// When a failed child is started again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Leave it stopped.
+    #[allow(dead_code, reason = "the firmware restarts every supervised task")]
+    Never,
+    /// Restart it right away.
+    Always,
+    /// Restart after `initial`, doubling the delay on each consecutive
+    /// failure up to `max`. A child that ran for `max` starts over at
+    /// `initial`.
+    Backoff { initial: Duration, max: Duration },
+}
+
+/// How the failure of one child affects the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Only the failed child is restarted.
+    #[allow(dead_code, reason = "the firmware's supervisor uses OneForAll")]
+    OneForOne,
+    /// All children are killed and restarted together.
+    OneForAll,
+}
+
+/// Called when a child is restarted more often than its limit allows.
+pub type EscalationHook = fn(&'static str);
+
+/// Builds a fresh instance of a supervised task.
+pub type TaskFactory = Box<dyn FnMut() -> TaskBox>;
+
+/// A supervised task and its restart rules.
+pub struct ChildSpec {
+    factory: TaskFactory,
+    policy: RestartPolicy,
+    /// At most this many restarts within the window.
+    limit: Option<(u32, Duration)>,
+}
+
+impl ChildSpec {
+    /// Child built by `factory`, restarted with [`RestartPolicy::Always`].
+    pub fn new(factory: impl FnMut() -> TaskBox + 'static) -> Self {
+        Self {
+            factory: Box::new(factory),
+            policy: RestartPolicy::Always,
+            limit: None,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: RestartPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Escalate once the child needs more than `max_restarts` restarts
+    /// within `window`.
+    pub fn with_limit(mut self, max_restarts: u32, window: Duration) -> Self {
+        self.limit = Some((max_restarts, window));
+        self
+    }
+}
+
+struct Child {
+    spec: ChildSpec,
+    name: &'static str,
+    running: Option<JoinHandle>,
+    /// When to start the child next, if it is stopped.
+    start_at: Option<Instant>,
+    started: Instant,
+    /// Failures in a row, for the backoff delay.
+    streak: u32,
+    window_start: Instant,
+    window_restarts: u32,
+}
+
+impl Child {
+    /// Delay before restarting after a failure at `now`, or `None` to leave
+    /// the child stopped.
+    fn restart_delay(&mut self, now: Instant) -> Option<Duration> {
+        let delay = match self.spec.policy {
+            RestartPolicy::Never => return None,
+            RestartPolicy::Always => Duration::ZERO,
+            RestartPolicy::Backoff { initial, max } => {
+                if now - self.started >= max {
+                    self.streak = 0;
+                }
+                let factor = 1u64 << self.streak.min(32);
+                self.streak += 1;
+                Duration::from_ticks(initial.ticks().saturating_mul(factor)).min(max)
+            }
+        };
+        Some(delay)
+    }
+
+    /// Count a restart against the limit; `false` if it is exceeded.
+    fn within_limit(&mut self, now: Instant) -> bool {
+        let Some((max_restarts, window)) = self.spec.limit else {
+            return true;
+        };
+        if now - self.window_start >= window {
+            self.window_start = now;
+            self.window_restarts = 0;
+        }
+        self.window_restarts += 1;
+        self.window_restarts <= max_restarts
+    }
+}
+
+/// Task that starts a group of children and restarts them when they fail.
+pub struct Supervisor {
+    name: &'static str,
+    strategy: Strategy,
+    children: Vec<Child>,
+    escalate: EscalationHook,
+    escalated: bool,
+}
+
+impl Supervisor {
+    pub fn new(name: &'static str, strategy: Strategy) -> Self {
+        Self {
+            name,
+            strategy,
+            children: Vec::new(),
+            escalate: reboot,
+            escalated: false,
+        }
+    }
+
+    /// Add a child; children start in the order they are added.
+    pub fn with_child(mut self, spec: ChildSpec) -> Self {
+        self.children.push(Child {
+            spec,
+            name: "?",
+            running: None,
+            start_at: Some(Instant::from_ticks(0)),
+            started: Instant::from_ticks(0),
+            streak: 0,
+            window_start: Instant::from_ticks(0),
+            window_restarts: 0,
+        });
+        self
+    }
+
+    /// Replace the default escalation, a system reboot.
+    #[allow(dead_code, reason = "the firmware keeps the default reboot")]
+    pub fn with_escalation(mut self, hook: EscalationHook) -> Self {
+        self.escalate = hook;
+        self
+    }
+
+    /// Handle the exit of child `index` at `now`. Returns `false` if the
+    /// failure escalated.
+    fn child_exited(&mut self, index: usize, status: ExitStatus, now: Instant) -> bool {
+        let child = &mut self.children[index];
+        let failed = matches!(status, ExitStatus::StackOverflow)
+            || matches!(status, ExitStatus::Exited(code) if code != 0);
+        if !failed {
+            return true;
+        }
+        let Some(delay) = child.restart_delay(now) else {
+            println!("{}: task {} failed ({:?})", self.name, child.name, status);
+            return true;
+        };
+        if !child.within_limit(now) {
+            println!(
+                "{}: task {} failed too often, escalating",
+                self.name, child.name
+            );
+            (self.escalate)(child.name);
+            return false;
+        }
+        println!(
+            "{}: restarting task {} in {} ms ({:?})",
+            self.name,
+            child.name,
+            delay.as_millis(),
+            status
+        );
+        let start_at = Some(now + delay);
+        match self.strategy {
+            Strategy::OneForOne => child.start_at = start_at,
+            Strategy::OneForAll => {
+                for child in self.children.iter_mut() {
+                    child.start_at = start_at;
+                }
+            }
+        }
+        true
+    }
+}
+
+impl Task for Supervisor {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn priority(&self) -> TaskPriority {
+        TaskPriority::High
+    }
+
+    fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+        if self.escalated {
+            return TaskCommand::Park(None);
+        }
+        let now = ctx.now;
+
+        for index in 0..self.children.len() {
+            let status = self.children[index]
+                .running
+                .as_ref()
+                .and_then(JoinHandle::try_join);
+            if let Some(status) = status {
+                self.children[index].running = None;
+                if !self.child_exited(index, status, now) {
+                    self.escalated = true;
+                    return TaskCommand::Park(None);
+                }
+            }
+        }
+
+        for child in self.children.iter_mut() {
+            // One-for-all restarts stop the survivors first.
+            if child.start_at.is_some() {
+                if let Some(running) = child.running.take() {
+                    // It may have exited during this pass already.
+                    let _ = ctx.spawner().kill(running.id());
+                }
+            }
+            if child.start_at.is_some_and(|start_at| start_at <= now) {
+                let task = (child.spec.factory)();
+                child.name = task.name();
+                match ctx.spawner().spawn(task) {
+                    Ok(handle) => {
+                        child.running = Some(handle);
+                        child.start_at = None;
+                        child.started = now;
+                    }
+                    Err(err) => {
+                        println!("{}: cannot start {}: {:?}", self.name, child.name, err);
+                        child.start_at = Some(now + Duration::from_ticks(1));
+                    }
+                }
+            }
+        }
+
+        for running in self
+            .children
+            .iter()
+            .filter_map(|child| child.running.as_ref())
+        {
+            // Exits are handled on the next poll.
+            let _ = running.poll_join(ctx.waker());
+        }
+        let next_start = self
+            .children
+            .iter()
+            .filter_map(|child| child.start_at)
+            .min();
+        TaskCommand::Park(next_start)
+    }
+}
+
+/// Default escalation: reset the chip.
+#[cfg(feature = "esp32")]
+fn reboot(_task: &'static str) {
+    esp_hal::system::software_reset();
+}
+
+/// Default escalation: record a reset in the simulation.
+#[cfg(feature = "sim")]
+fn reboot(_task: &'static str) {
+    crate::sim::system::software_reset();
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::{scheduler::Scheduler, sim};
+
+    /// Counts its starts and exits with an error on its first poll.
+    struct Crasher(&'static AtomicU32);
+
+    impl Task for Crasher {
+        fn name(&self) -> &'static str {
+            "crasher"
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            TaskCommand::Exit(1)
+        }
+    }
+
+    /// Never finishes on its own.
+    struct Steady(&'static AtomicU32);
+
+    impl Task for Steady {
+        fn name(&self) -> &'static str {
+            "steady"
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            TaskCommand::Park(None)
+        }
+    }
+
+    fn run_until(scheduler: &mut Scheduler, tick: u64) {
+        while Instant::now().ticks() < tick {
+            scheduler.run_ready();
+            sim::clock::advance(1);
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_between_restarts() {
+        static STARTS: AtomicU32 = AtomicU32::new(0);
+        let _board = sim::board();
+        let mut scheduler = Scheduler::new();
+        let supervisor = Supervisor::new("sup", Strategy::OneForOne).with_child(
+            ChildSpec::new(|| Box::new(Crasher(&STARTS)).into()).with_policy(
+                RestartPolicy::Backoff {
+                    initial: Duration::from_ticks(10),
+                    max: Duration::from_ticks(1_000),
+                },
+            ),
+        );
+        scheduler.spawn(Box::new(supervisor)).unwrap();
+
+        // The supervisor sees the first failure at tick 2 and the second at
+        // 14; each restart task runs a tick after it is spawned.
+        run_until(&mut scheduler, 13);
+        assert_eq!(STARTS.load(Ordering::Relaxed), 1);
+        run_until(&mut scheduler, 14);
+        assert_eq!(STARTS.load(Ordering::Relaxed), 2);
+        run_until(&mut scheduler, 35);
+        assert_eq!(STARTS.load(Ordering::Relaxed), 2);
+        run_until(&mut scheduler, 36);
+        assert_eq!(STARTS.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn one_for_all_restarts_siblings() {
+        static CRASHES: AtomicU32 = AtomicU32::new(0);
+        static STEADY: AtomicU32 = AtomicU32::new(0);
+        let _board = sim::board();
+        let mut scheduler = Scheduler::new();
+        let supervisor = Supervisor::new("sup", Strategy::OneForAll)
+            .with_child(ChildSpec::new(|| Box::new(Steady(&STEADY)).into()))
+            .with_child(
+                ChildSpec::new(|| Box::new(Crasher(&CRASHES)).into()).with_policy(
+                    RestartPolicy::Backoff {
+                        initial: Duration::from_ticks(5),
+                        max: Duration::from_ticks(5),
+                    },
+                ),
+            );
+        scheduler.spawn(Box::new(supervisor)).unwrap();
+
+        run_until(&mut scheduler, 4);
+        assert_eq!(STEADY.load(Ordering::Relaxed), 1);
+        assert_eq!(CRASHES.load(Ordering::Relaxed), 1);
+        // The steady sibling was killed with the group.
+        assert_eq!(scheduler.task_count(), 1);
+
+        run_until(&mut scheduler, 10);
+        assert_eq!(STEADY.load(Ordering::Relaxed), 2);
+        assert_eq!(CRASHES.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn exceeding_restart_limit_escalates_to_reboot() {
+        static STARTS: AtomicU32 = AtomicU32::new(0);
+        let _board = sim::board();
+        let mut scheduler = Scheduler::new();
+        let supervisor = Supervisor::new("sup", Strategy::OneForOne).with_child(
+            ChildSpec::new(|| Box::new(Crasher(&STARTS)).into())
+                .with_limit(3, Duration::from_millis(1_000)),
+        );
+        scheduler.spawn(Box::new(supervisor)).unwrap();
+
+        run_until(&mut scheduler, 20);
+
+        assert_eq!(STARTS.load(Ordering::Relaxed), 4);
+        assert!(sim::system::reset_requested());
+    }
+}
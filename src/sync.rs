@@ -0,0 +1,747 @@
+//! Blocking synchronization between tasks.
+//!
+//! Unlike `critical_section`, these primitives never mask interrupts while
+//! the protected data is in use: a task that cannot take a [`Mutex`] or
+//! [`Semaphore`] blocks with [`TaskCommand::Wait`] (or `.await`s [`Mutex::lock`]
+//! / [`Semaphore::acquire`]) and the scheduler runs other tasks meanwhile.
+//!
+//! [`Mutex`] implements priority inheritance: while a task waits for a lock,
+//! the owner runs at the waiter's priority if that is higher than its own, so
+//! a `Low` task holding a lock cannot be starved by `Normal` tasks while a
+//! `High` task waits on it. Each waiter's loan lasts until the owner unlocks
+//! or the waiter stops waiting (it times out, is killed or drops the
+//! [`Mutex::lock`] future), so an owner holding several locks keeps the
+//! highest priority still waiting on any of them.
+//!
+//! [`TaskCommand::Wait`]: crate::scheduler::TaskCommand::Wait
+
+use alloc::sync::Arc;
+use core::{
+    cell::{Cell, RefCell, UnsafeCell},
+    future::{poll_fn, Future},
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    ptr,
+    task::{Context, Poll, Waker},
+};
+
+use critical_section::{CriticalSection, Mutex as CsMutex};
+
+use crate::{
+    event::{WaitCondition, WaitQueue},
+    executor::TaskSignal,
+    scheduler::{current_task, TaskPriority},
+};
+
+/// Type-erased view of a [`Mutex`] used by [`WaitCondition`].
+pub trait MutexState: Sync {
+    /// Whether the mutex is free; if not, register `waker` for the next
+    /// unlock and lend the owner the calling task's priority on behalf of
+    /// `waiter`.
+    fn unlocked_or_register(
+        &self,
+        cs: CriticalSection<'_>,
+        waiter: &TaskSignal,
+        waker: &Waker,
+    ) -> bool;
+
+    /// Take back the priority `waiter` lent the owner and drop its `waker`,
+    /// once it stops waiting.
+    fn withdraw(&self, cs: CriticalSection<'_>, waiter: &TaskSignal, waker: &Waker);
+}
+
+struct LockState {
+    locked: bool,
+    /// Owning task, used for priority inheritance. `None` when locked
+    /// outside of a task (for example during boot).
+    owner: Option<Arc<TaskSignal>>,
+    /// Number of waiters that lent each [`TaskPriority`] level. The owner
+    /// holds one loan for every level with waiters; each waiter keeps the
+    /// level it lent so that it can take it back.
+    lenders: [usize; 3],
+}
+
+impl LockState {
+    /// Levels lent by at least one waiter.
+    fn lent_levels(&self) -> impl Iterator<Item = TaskPriority> + '_ {
+        [TaskPriority::Low, TaskPriority::Normal, TaskPriority::High]
+            .into_iter()
+            .filter(|&priority| self.lenders[priority as usize] > 0)
+    }
+
+    /// Hand the lock to `owner`, moving the loans of the waiters to it.
+    fn set_owner(&mut self, owner: Option<Arc<TaskSignal>>) {
+        if let Some(previous) = &self.owner {
+            self.lent_levels()
+                .for_each(|priority| previous.withdraw(priority));
+        }
+        if let Some(owner) = &owner {
+            self.lent_levels().for_each(|priority| owner.lend(priority));
+        }
+        self.owner = owner;
+    }
+
+    /// Lend the owner `priority` on behalf of `waiter`, replacing the loan
+    /// `lent` it made before.
+    fn lend(
+        &mut self,
+        waiter: &TaskSignal,
+        priority: TaskPriority,
+        lent: &mut Option<TaskPriority>,
+    ) {
+        self.withdraw(lent);
+        if self
+            .owner
+            .as_deref()
+            .is_some_and(|owner| ptr::eq(owner, waiter))
+        {
+            return;
+        }
+        let lenders = &mut self.lenders[priority as usize];
+        *lenders += 1;
+        if *lenders == 1 {
+            if let Some(owner) = &self.owner {
+                owner.lend(priority);
+            }
+        }
+        *lent = Some(priority);
+    }
+
+    /// Take back the loan `lent`, if there is one.
+    fn withdraw(&mut self, lent: &mut Option<TaskPriority>) {
+        let Some(priority) = lent.take() else {
+            return;
+        };
+        let lenders = &mut self.lenders[priority as usize];
+        *lenders -= 1;
+        if *lenders == 0 {
+            if let Some(owner) = &self.owner {
+                owner.withdraw(priority);
+            }
+        }
+    }
+}
+
+/// Mutual exclusion lock that blocks the calling task instead of interrupts.
+pub struct Mutex<T> {
+    state: CsMutex<RefCell<LockState>>,
+    waiters: WaitQueue,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: access to `value` is serialized by `state.locked`.
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: CsMutex::new(RefCell::new(LockState {
+                locked: false,
+                owner: None,
+                lenders: [0; 3],
+            })),
+            waiters: WaitQueue::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Take the lock if it is free.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let owner = current_task().map(|task| task.signal);
+        critical_section::with(|cs| {
+            let mut state = self.state.borrow_ref_mut(cs);
+            if state.locked {
+                return None;
+            }
+            state.locked = true;
+            state.set_owner(owner);
+            Some(MutexGuard { mutex: self })
+        })
+    }
+
+    /// Wait until the lock is free, then take it.
+    #[allow(dead_code, reason = "the firmware runs no AsyncTask")]
+    pub fn lock(&self) -> impl Future<Output = MutexGuard<'_, T>> + '_ {
+        Lock {
+            mutex: self,
+            lent: None,
+            waker: None,
+        }
+    }
+
+    /// Whether the lock is currently held.
+    #[allow(dead_code, reason = "the firmware only uses try_lock")]
+    pub fn is_locked(&self) -> bool {
+        critical_section::with(|cs| self.state.borrow_ref(cs).locked)
+    }
+
+    /// Whether the lock is free; if not, register `waker` and lend the owner
+    /// the calling task's priority in place of the loan `lent`.
+    fn unlocked_or_wait(
+        &self,
+        cs: CriticalSection<'_>,
+        waker: &Waker,
+        lent: &mut Option<TaskPriority>,
+    ) -> bool {
+        let mut state = self.state.borrow_ref_mut(cs);
+        if !state.locked {
+            return true;
+        }
+        self.waiters.register(cs, waker);
+        if let Some(waiter) = current_task() {
+            state.lend(&waiter.signal, waiter.priority, lent);
+        }
+        false
+    }
+
+    fn unlock(&self) {
+        critical_section::with(|cs| {
+            let mut state = self.state.borrow_ref_mut(cs);
+            state.set_owner(None);
+            state.locked = false;
+            self.waiters.wake_all(cs);
+        });
+    }
+}
+
+/// Future returned by [`Mutex::lock`]. Withdraws the priority it lent the
+/// owner and its waker if dropped before taking the lock.
+struct Lock<'a, T> {
+    mutex: &'a Mutex<T>,
+    /// Priority lent to the owner by the last wait.
+    lent: Option<TaskPriority>,
+    /// Waker registered by the last wait.
+    waker: Option<Waker>,
+}
+
+impl<'a, T> Future for Lock<'a, T> {
+    type Output = MutexGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mutex = self.mutex;
+        if let Some(guard) = mutex.try_lock() {
+            return Poll::Ready(guard);
+        }
+        self.waker = Some(cx.waker().clone());
+        let mut lent = self.lent;
+        critical_section::with(|cs| {
+            if mutex.unlocked_or_wait(cs, cx.waker(), &mut lent) {
+                // Unlocked between the two checks; retry right away.
+                cx.waker().wake_by_ref();
+            }
+        });
+        self.lent = lent;
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Lock<'_, T> {
+    fn drop(&mut self) {
+        critical_section::with(|cs| {
+            self.mutex.state.borrow_ref_mut(cs).withdraw(&mut self.lent);
+            if let Some(waker) = &self.waker {
+                self.mutex.waiters.remove(cs, waker);
+            }
+        });
+    }
+}
+
+impl<T: Send> Mutex<T> {
+    /// Condition satisfied once the lock is free.
+    #[allow(dead_code, reason = "the firmware's tasks never contend for a lock")]
+    pub fn unlocked(&'static self) -> WaitCondition {
+        WaitCondition::Unlocked(self)
+    }
+}
+
+impl<T: Send> MutexState for Mutex<T> {
+    fn unlocked_or_register(
+        &self,
+        cs: CriticalSection<'_>,
+        waiter: &TaskSignal,
+        waker: &Waker,
+    ) -> bool {
+        let mut lent = waiter.lock_loan();
+        let unlocked = self.unlocked_or_wait(cs, waker, &mut lent);
+        waiter.set_lock_loan(lent);
+        unlocked
+    }
+
+    fn withdraw(&self, cs: CriticalSection<'_>, waiter: &TaskSignal, waker: &Waker) {
+        let mut lent = waiter.lock_loan();
+        self.state.borrow_ref_mut(cs).withdraw(&mut lent);
+        waiter.set_lock_loan(lent);
+        self.waiters.remove(cs, waker);
+    }
+}
+
+/// Access to the data of a locked [`Mutex`]; unlocks when dropped.
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard proves the lock is held.
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard proves the lock is held.
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+/// Counting semaphore. Safe to release from an ISR.
+#[allow(dead_code, reason = "the firmware counts no resources")]
+pub struct Semaphore {
+    permits: CsMutex<Cell<u32>>,
+    waiters: WaitQueue,
+}
+
+#[allow(dead_code, reason = "the firmware counts no resources")]
+impl Semaphore {
+    pub const fn new(permits: u32) -> Self {
+        Self {
+            permits: CsMutex::new(Cell::new(permits)),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Take a permit if one is available.
+    pub fn try_acquire(&self) -> bool {
+        critical_section::with(|cs| {
+            let permits = self.permits.borrow(cs);
+            match permits.get() {
+                0 => false,
+                n => {
+                    permits.set(n - 1);
+                    true
+                }
+            }
+        })
+    }
+
+    /// Wait for a permit, then take it.
+    pub fn acquire(&self) -> impl Future<Output = ()> + '_ {
+        poll_fn(move |cx| {
+            critical_section::with(|cs| {
+                let permits = self.permits.borrow(cs);
+                match permits.get() {
+                    0 => {
+                        self.waiters.register(cs, cx.waker());
+                        Poll::Pending
+                    }
+                    n => {
+                        permits.set(n - 1);
+                        Poll::Ready(())
+                    }
+                }
+            })
+        })
+    }
+
+    /// Return a permit and wake waiting tasks.
+    pub fn release(&self) {
+        critical_section::with(|cs| {
+            let permits = self.permits.borrow(cs);
+            permits.set(permits.get().saturating_add(1));
+            self.waiters.wake_all(cs);
+        });
+    }
+
+    /// Permits currently available.
+    pub fn available(&self) -> u32 {
+        critical_section::with(|cs| self.permits.borrow(cs).get())
+    }
+
+    /// Condition satisfied once a permit is available.
+    pub fn acquirable(&'static self) -> WaitCondition {
+        WaitCondition::Acquirable(self)
+    }
+
+    pub(crate) fn acquirable_or_register(&self, cs: CriticalSection<'_>, waker: &Waker) -> bool {
+        let ready = self.permits.borrow(cs).get() > 0;
+        if !ready {
+            self.waiters.register(cs, waker);
+        }
+        ready
+    }
+
+    pub(crate) fn unregister(&self, cs: CriticalSection<'_>, waker: &Waker) {
+        self.waiters.remove(cs, waker);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc, vec::Vec};
+
+    use super::*;
+    use crate::{
+        executor::AsyncTask,
+        policy::FixedPriority,
+        scheduler::{Scheduler, Task, TaskCommand, TaskContext, TaskPriority, MAX_TASKS},
+        sim,
+    };
+
+    type PollLog = Rc<RefCell<Vec<&'static str>>>;
+
+    /// Takes `LOCK` on its first poll and releases it on its `hold`-th.
+    struct Holder {
+        lock: &'static Mutex<u32>,
+        guard: Option<MutexGuard<'static, u32>>,
+        polls: u32,
+        hold: u32,
+        log: PollLog,
+    }
+
+    impl Task for Holder {
+        fn name(&self) -> &'static str {
+            "low"
+        }
+
+        fn priority(&self) -> TaskPriority {
+            TaskPriority::Low
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            self.log.borrow_mut().push("low");
+            self.polls += 1;
+            if self.polls == 1 {
+                self.guard = self.lock.try_lock();
+            } else if self.polls == self.hold {
+                self.guard = None;
+                return TaskCommand::Finished;
+            }
+            TaskCommand::Continue
+        }
+    }
+
+    /// Task that is always ready, logging each poll.
+    struct Busy(PollLog);
+
+    impl Task for Busy {
+        fn name(&self) -> &'static str {
+            "normal"
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            self.0.borrow_mut().push("normal");
+            TaskCommand::Continue
+        }
+    }
+
+    /// High-priority task blocking on the lock until it gets it.
+    struct Waiter {
+        lock: &'static Mutex<u32>,
+        log: PollLog,
+    }
+
+    impl Task for Waiter {
+        fn name(&self) -> &'static str {
+            "high"
+        }
+
+        fn priority(&self) -> TaskPriority {
+            TaskPriority::High
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            match self.lock.try_lock() {
+                Some(mut value) => {
+                    *value += 1;
+                    self.log.borrow_mut().push("high locked");
+                    TaskCommand::Finished
+                }
+                None => {
+                    self.log.borrow_mut().push("high");
+                    TaskCommand::Wait(self.lock.unlocked(), None)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn waiting_high_task_boosts_low_owner() {
+        static LOCK: Mutex<u32> = Mutex::new(0);
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        let holder = Holder {
+            lock: &LOCK,
+            guard: None,
+            polls: 0,
+            hold: 3,
+            log: log.clone(),
+        };
+        scheduler.spawn(Box::leak(Box::new(holder))).unwrap();
+        scheduler
+            .spawn(Box::leak(Box::new(Busy(log.clone()))))
+            .unwrap();
+
+        scheduler.run_ready();
+        assert_eq!(*log.borrow(), ["normal", "low"]);
+        assert!(LOCK.is_locked());
+
+        let waiter = Waiter {
+            lock: &LOCK,
+            log: log.clone(),
+        };
+        scheduler.spawn(Box::leak(Box::new(waiter))).unwrap();
+        log.borrow_mut().clear();
+        scheduler.run_ready();
+        assert_eq!(*log.borrow(), ["high", "normal", "low"]);
+
+        // Boosted to High, the owner now runs ahead of the Normal task and
+        // its unlock lets the waiter in straight away.
+        log.borrow_mut().clear();
+        scheduler.run_ready();
+        assert_eq!(*log.borrow(), ["low", "high locked", "normal"]);
+        assert_eq!(*LOCK.try_lock().unwrap(), 1);
+    }
+
+    /// Waits for the lock at `priority` until it gets it.
+    struct Contender {
+        lock: &'static Mutex<u32>,
+        priority: TaskPriority,
+    }
+
+    impl Task for Contender {
+        fn name(&self) -> &'static str {
+            "contender"
+        }
+
+        fn priority(&self) -> TaskPriority {
+            self.priority
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            match self.lock.try_lock() {
+                Some(_) => TaskCommand::Finished,
+                None => TaskCommand::Wait(self.lock.unlocked(), None),
+            }
+        }
+    }
+
+    #[test]
+    fn boost_survives_more_waiters_than_max_tasks() {
+        static LOCK: Mutex<u32> = Mutex::new(0);
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler: Scheduler<{ MAX_TASKS + 4 }> = Scheduler::with_policy(FixedPriority);
+        let holder = Holder {
+            lock: &LOCK,
+            guard: None,
+            polls: 0,
+            hold: 4,
+            log: log.clone(),
+        };
+        scheduler.spawn(Box::leak(Box::new(holder))).unwrap();
+        scheduler
+            .spawn(Box::leak(Box::new(Busy(log.clone()))))
+            .unwrap();
+        scheduler.run_ready();
+        assert!(LOCK.is_locked());
+
+        for _ in 0..MAX_TASKS {
+            let contender = Contender {
+                lock: &LOCK,
+                priority: TaskPriority::Low,
+            };
+            scheduler.spawn(Box::leak(Box::new(contender))).unwrap();
+        }
+        scheduler.run_ready();
+
+        // The high waiter is one more than MAX_TASKS waiting on the lock.
+        let waiter = Waiter {
+            lock: &LOCK,
+            log: log.clone(),
+        };
+        scheduler.spawn(Box::leak(Box::new(waiter))).unwrap();
+        log.borrow_mut().clear();
+        scheduler.run_ready();
+        assert_eq!(*log.borrow(), ["high", "normal", "low"]);
+
+        log.borrow_mut().clear();
+        scheduler.run_ready();
+        assert_eq!(*log.borrow(), ["low", "high locked", "normal"]);
+    }
+
+    /// Takes two locks on its first poll, releases `second` on its second
+    /// and `first` on its third.
+    struct NestedHolder {
+        first: &'static Mutex<u32>,
+        second: &'static Mutex<u32>,
+        guards: (
+            Option<MutexGuard<'static, u32>>,
+            Option<MutexGuard<'static, u32>>,
+        ),
+        polls: u32,
+        log: PollLog,
+    }
+
+    impl Task for NestedHolder {
+        fn name(&self) -> &'static str {
+            "low"
+        }
+
+        fn priority(&self) -> TaskPriority {
+            TaskPriority::Low
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            self.log.borrow_mut().push("low");
+            self.polls += 1;
+            match self.polls {
+                1 => self.guards = (self.first.try_lock(), self.second.try_lock()),
+                2 => self.guards.1 = None,
+                _ => {
+                    self.guards.0 = None;
+                    return TaskCommand::Finished;
+                }
+            }
+            TaskCommand::Continue
+        }
+    }
+
+    #[test]
+    fn owner_keeps_boost_while_waiter_remains_on_another_lock() {
+        static FIRST: Mutex<u32> = Mutex::new(0);
+        static SECOND: Mutex<u32> = Mutex::new(0);
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        let holder = NestedHolder {
+            first: &FIRST,
+            second: &SECOND,
+            guards: (None, None),
+            polls: 0,
+            log: log.clone(),
+        };
+        scheduler.spawn(Box::leak(Box::new(holder))).unwrap();
+        scheduler
+            .spawn(Box::leak(Box::new(Busy(log.clone()))))
+            .unwrap();
+        scheduler.run_ready();
+        assert!(FIRST.is_locked() && SECOND.is_locked());
+
+        let waiter = Waiter {
+            lock: &FIRST,
+            log: log.clone(),
+        };
+        scheduler.spawn(Box::leak(Box::new(waiter))).unwrap();
+        scheduler.run_ready();
+        assert!(!SECOND.is_locked());
+
+        // Unlocking the other lock leaves the loan on the first in place.
+        log.borrow_mut().clear();
+        scheduler.run_ready();
+        assert_eq!(*log.borrow(), ["low", "high locked", "normal"]);
+    }
+
+    /// High-priority task that waits for the lock once, then gives up.
+    struct Impatient {
+        lock: &'static Mutex<u32>,
+        log: PollLog,
+        waited: bool,
+    }
+
+    impl Task for Impatient {
+        fn name(&self) -> &'static str {
+            "high"
+        }
+
+        fn priority(&self) -> TaskPriority {
+            TaskPriority::High
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            if self.waited {
+                self.log.borrow_mut().push("high gave up");
+                return TaskCommand::Finished;
+            }
+            self.waited = true;
+            self.log.borrow_mut().push("high");
+            TaskCommand::Wait(
+                self.lock.unlocked(),
+                Some(crate::timer::Duration::from_millis(5)),
+            )
+        }
+    }
+
+    #[test]
+    fn timed_out_waiter_withdraws_boost() {
+        static LOCK: Mutex<u32> = Mutex::new(0);
+        let _board = sim::board();
+        let log = PollLog::default();
+        let mut scheduler = Scheduler::new();
+        let holder = Holder {
+            lock: &LOCK,
+            guard: None,
+            polls: 0,
+            hold: 10,
+            log: log.clone(),
+        };
+        scheduler.spawn(Box::leak(Box::new(holder))).unwrap();
+        scheduler
+            .spawn(Box::leak(Box::new(Busy(log.clone()))))
+            .unwrap();
+        scheduler.run_ready();
+
+        let waiter = Impatient {
+            lock: &LOCK,
+            log: log.clone(),
+            waited: false,
+        };
+        scheduler.spawn(Box::leak(Box::new(waiter))).unwrap();
+        scheduler.run_ready();
+        log.borrow_mut().clear();
+        scheduler.run_ready();
+        assert_eq!(*log.borrow(), ["low", "normal"]);
+
+        // Once the waiter times out, the owner is back to its own priority.
+        sim::clock::advance_ms(5);
+        scheduler.run_ready();
+        log.borrow_mut().clear();
+        scheduler.run_ready();
+        assert_eq!(*log.borrow(), ["normal", "low"]);
+        assert!(LOCK.is_locked());
+    }
+
+    #[test]
+    fn semaphore_blocks_async_task_until_release() {
+        static PERMITS: Semaphore = Semaphore::new(1);
+        let _board = sim::board();
+        let acquired = Rc::new(Cell::new(0));
+        let task_acquired = acquired.clone();
+        let mut scheduler = Scheduler::new();
+        let task = AsyncTask::new("acquirer", async move {
+            for _ in 0..2 {
+                PERMITS.acquire().await;
+                task_acquired.set(task_acquired.get() + 1);
+            }
+        });
+        scheduler.spawn(Box::leak(Box::new(task))).unwrap();
+
+        scheduler.run_ready();
+        scheduler.run_ready();
+        assert_eq!(acquired.get(), 1);
+        assert_eq!(PERMITS.available(), 0);
+
+        PERMITS.release();
+        scheduler.run_ready();
+        assert_eq!(acquired.get(), 2);
+        assert!(!PERMITS.try_acquire());
+    }
+}
@@ -1,25 +1,461 @@
-//! Minimal syscall interface for cooperative kernel.
+//! System call interface.
+//!
+//! Every kernel service an application task needs is a [`Syscall`], served
+//! by [`handle_syscall`] on behalf of the task being polled on the calling
+//! core. Application code uses the typed wrappers in [`crate::os`] rather
+//! than building calls itself.
+//!
+//! Calls that block (yielding, sleeping, exiting, and sending to a full or
+//! receiving from an empty channel) suspend the caller, so they need a
+//! [`ThreadTask`](crate::thread::ThreadTask). Poll-based and async tasks get
+//! [`SyscallError::WouldBlock`] instead and return the matching
+//! [`TaskCommand`](crate::scheduler::TaskCommand) themselves.
+//!
+//! Drivers are reached through descriptors: the firmware registers each
+//! device's handle at boot with [`register_device`], and tasks [`open`] it by
+//! [`Device`] to read and write bytes.
+//!
+//! [`open`]: crate::os::open
 
-use crate::scheduler::Scheduler;
-use crate::timer;
+use core::{any::Any, cell::RefCell};
 
+use critical_section::Mutex;
+
+use crate::{
+    channel::Channel,
+    drivers::gpio::{ButtonsHandle, LedHandle},
+    event::WaitCondition,
+    println,
+    scheduler::{self, JoinHandle, SchedulerError, TaskBox, TaskId},
+    thread,
+    timer::{Duration, Instant},
+};
+
+/// Number of each call, stable across firmware versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
+#[allow(dead_code, reason = "stable numbering for a trap-based entry")]
 pub enum SyscallNumber {
     Yield = 0,
-    SleepMs = 1,
+    Sleep = 1,
+    Spawn = 2,
+    Exit = 3,
+    Time = 4,
+    TaskId = 5,
+    ChannelSend = 6,
+    ChannelRecv = 7,
+    DriverOpen = 8,
+    DriverRead = 9,
+    DriverWrite = 10,
+    HeapStats = 11,
+}
+
+/// A system call and its arguments.
+pub enum Syscall<'a> {
+    /// Let other ready tasks run.
+    Yield,
+    /// Block for at least the given time.
+    Sleep(Duration),
+    /// Start a task on the caller's scheduler after the current pass.
+    Spawn(TaskBox),
+    /// End the calling task with an exit code.
+    Exit(i32),
+    /// Current system time.
+    Time,
+    /// Id of the calling task.
+    TaskId,
+    /// Move the message out of `msg`, an `Option<T>` for the channel's
+    /// message type `T`, into the channel. On failure the message is left
+    /// in `msg`.
+    ChannelSend {
+        channel: &'static dyn SyscallChannel,
+        msg: &'a mut dyn Any,
+    },
+    /// Take a message from the channel into `msg`, an `Option<T>` for the
+    /// channel's message type `T`.
+    ChannelRecv {
+        channel: &'static dyn SyscallChannel,
+        msg: &'a mut dyn Any,
+    },
+    /// Get a descriptor for a registered device.
+    DriverOpen(Device),
+    /// Read from a device into `buf`.
+    DriverRead { fd: Fd, buf: &'a mut [u8] },
+    /// Write `buf` to a device.
+    DriverWrite { fd: Fd, buf: &'a [u8] },
+    /// Heap usage.
+    HeapStats,
+}
+
+impl Syscall<'_> {
+    /// Number of this call.
+    #[allow(dead_code, reason = "stable numbering for a trap-based entry")]
+    pub fn number(&self) -> SyscallNumber {
+        match self {
+            Syscall::Yield => SyscallNumber::Yield,
+            Syscall::Sleep(_) => SyscallNumber::Sleep,
+            Syscall::Spawn(_) => SyscallNumber::Spawn,
+            Syscall::Exit(_) => SyscallNumber::Exit,
+            Syscall::Time => SyscallNumber::Time,
+            Syscall::TaskId => SyscallNumber::TaskId,
+            Syscall::ChannelSend { .. } => SyscallNumber::ChannelSend,
+            Syscall::ChannelRecv { .. } => SyscallNumber::ChannelRecv,
+            Syscall::DriverOpen(_) => SyscallNumber::DriverOpen,
+            Syscall::DriverRead { .. } => SyscallNumber::DriverRead,
+            Syscall::DriverWrite { .. } => SyscallNumber::DriverWrite,
+            Syscall::HeapStats => SyscallNumber::HeapStats,
+        }
+    }
 }
 
+/// Value returned by a successful call.
 pub enum SyscallResult {
+    /// The call has no result.
     None,
+    /// Join handle of the task started by [`Syscall::Spawn`].
+    Spawned(JoinHandle),
+    Time(Instant),
+    TaskId(TaskId),
+    Fd(Fd),
+    /// Bytes read or written.
+    Bytes(usize),
+    HeapStats(HeapStats),
+}
+
+/// Why a call failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallError {
+    /// The call needs a calling task, but none is being polled.
+    NotInTask,
+    /// The call would block, but the caller is not a thread task.
+    WouldBlock,
+    /// The task table is full.
+    NoCapacity,
+    /// The heap has no room for the task's stack.
+    OutOfMemory,
+    /// No driver is registered for the device.
+    NoSuchDevice,
+    /// The device does not support the operation.
+    Unsupported,
+    /// An argument does not fit the call, such as a message of the wrong type.
+    InvalidArgument,
+}
+
+impl From<SchedulerError> for SyscallError {
+    fn from(err: SchedulerError) -> Self {
+        match err {
+            SchedulerError::NoCapacity => SyscallError::NoCapacity,
+            SchedulerError::OutOfMemory => SyscallError::OutOfMemory,
+            SchedulerError::NoSuchTask => SyscallError::InvalidArgument,
+        }
+    }
+}
+
+/// Heap usage in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub used: usize,
+    pub free: usize,
+}
+
+/// Devices tasks can open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code, reason = "opened by applications through os::open")]
+pub enum Device {
+    /// System console. Writes print UTF-8 text, one line per `\n`; it cannot
+    /// be read.
+    Console,
+    /// Status LED. Reads give one byte, 1 if lit; writing a byte lights it if
+    /// non-zero.
+    Led,
+    /// Front-panel buttons. Reads give one byte with a bit set per button
+    /// held down: [`BUTTON_UP`], [`BUTTON_DOWN`] and [`BUTTON_SELECT`]. They
+    /// cannot be written.
+    Buttons,
+}
+
+/// [`Device::Buttons`] bit for the scroll-up button.
+pub const BUTTON_UP: u8 = 1 << 0;
+/// [`Device::Buttons`] bit for the scroll-down button.
+pub const BUTTON_DOWN: u8 = 1 << 1;
+/// [`Device::Buttons`] bit for the select button.
+pub const BUTTON_SELECT: u8 = 1 << 2;
+
+/// Descriptor of an opened device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fd(Device);
+
+/// Driver handle of a device, registered at boot.
+pub enum DeviceHandle {
+    Led(LedHandle),
+    Buttons(ButtonsHandle),
+}
+
+/// Drivers registered for the devices that need one.
+struct Devices {
+    led: Option<LedHandle>,
+    buttons: Option<ButtonsHandle>,
+}
+
+static DEVICES: Mutex<RefCell<Devices>> = Mutex::new(RefCell::new(Devices {
+    led: None,
+    buttons: None,
+}));
+
+/// Make a driver available to tasks, replacing any registered before.
+pub fn register_device(handle: DeviceHandle) {
+    critical_section::with(|cs| {
+        let mut devices = DEVICES.borrow_ref_mut(cs);
+        match handle {
+            DeviceHandle::Led(led) => devices.led = Some(led),
+            DeviceHandle::Buttons(buttons) => devices.buttons = Some(buttons),
+        }
+    });
+}
+
+/// Forget every registered driver.
+#[cfg(test)]
+pub(crate) fn unregister_devices() {
+    critical_section::with(|cs| {
+        let mut devices = DEVICES.borrow_ref_mut(cs);
+        devices.led = None;
+        devices.buttons = None;
+    });
+}
+
+fn led() -> Result<LedHandle, SyscallError> {
+    critical_section::with(|cs| DEVICES.borrow_ref(cs).led).ok_or(SyscallError::NoSuchDevice)
+}
+
+fn buttons() -> Result<ButtonsHandle, SyscallError> {
+    critical_section::with(|cs| DEVICES.borrow_ref(cs).buttons).ok_or(SyscallError::NoSuchDevice)
+}
+
+/// Channel operations behind [`Syscall::ChannelSend`] and
+/// [`Syscall::ChannelRecv`], with the message type erased.
+pub trait SyscallChannel: Sync {
+    /// Move the message out of `msg` (an `Option<T>`) into the channel.
+    fn try_send_any(&self, msg: &mut dyn Any) -> Result<(), SyscallError>;
+
+    /// Take a message into `msg` (an `Option<T>`).
+    fn try_recv_any(&self, msg: &mut dyn Any) -> Result<(), SyscallError>;
+
+    /// Condition a blocked sender waits for.
+    fn send_condition(&'static self) -> WaitCondition;
+
+    /// Condition a blocked receiver waits for.
+    fn recv_condition(&'static self) -> WaitCondition;
 }
 
-pub fn handle_syscall(num: SyscallNumber, arg0: u32, scheduler: &mut Scheduler) -> SyscallResult {
-    match num {
-        SyscallNumber::Yield => SyscallResult::None,
-        SyscallNumber::SleepMs => {
-            let ticks = timer::ms_to_ticks(arg0).max(1);
-            scheduler.current_task_sleep(ticks);
-            SyscallResult::None
+impl<T: Send + 'static, const N: usize> SyscallChannel for Channel<T, N> {
+    fn try_send_any(&self, msg: &mut dyn Any) -> Result<(), SyscallError> {
+        let slot = msg
+            .downcast_mut::<Option<T>>()
+            .ok_or(SyscallError::InvalidArgument)?;
+        let value = slot.take().ok_or(SyscallError::InvalidArgument)?;
+        self.try_send(value).map_err(|value| {
+            *slot = Some(value);
+            SyscallError::WouldBlock
+        })
+    }
+
+    fn try_recv_any(&self, msg: &mut dyn Any) -> Result<(), SyscallError> {
+        let slot = msg
+            .downcast_mut::<Option<T>>()
+            .ok_or(SyscallError::InvalidArgument)?;
+        *slot = Some(self.try_recv().ok_or(SyscallError::WouldBlock)?);
+        Ok(())
+    }
+
+    fn send_condition(&'static self) -> WaitCondition {
+        self.sendable()
+    }
+
+    fn recv_condition(&'static self) -> WaitCondition {
+        self.receivable()
+    }
+}
+
+/// Run `call` for the task being polled on this core.
+pub fn handle_syscall(call: Syscall<'_>) -> Result<SyscallResult, SyscallError> {
+    match call {
+        Syscall::Yield => {
+            block()?;
+            thread::yield_now();
+            Ok(SyscallResult::None)
+        }
+        Syscall::Sleep(duration) => {
+            block()?;
+            thread::sleep_for(duration);
+            Ok(SyscallResult::None)
         }
+        Syscall::Spawn(task) => {
+            let handle = scheduler::with_current_spawner(|spawner| spawner.spawn(task))
+                .ok_or(SyscallError::NotInTask)??;
+            Ok(SyscallResult::Spawned(handle))
+        }
+        Syscall::Exit(code) => {
+            block()?;
+            thread::exit(code)
+        }
+        Syscall::Time => Ok(SyscallResult::Time(Instant::now())),
+        Syscall::TaskId => scheduler::current_task()
+            .map(|task| SyscallResult::TaskId(task.id))
+            .ok_or(SyscallError::NotInTask),
+        Syscall::ChannelSend { channel, msg } => loop {
+            match channel.try_send_any(msg) {
+                Err(SyscallError::WouldBlock) if thread::in_thread() => {
+                    thread::wait(channel.send_condition())
+                }
+                result => break result.map(|()| SyscallResult::None),
+            }
+        },
+        Syscall::ChannelRecv { channel, msg } => loop {
+            match channel.try_recv_any(msg) {
+                Err(SyscallError::WouldBlock) if thread::in_thread() => {
+                    thread::wait(channel.recv_condition())
+                }
+                result => break result.map(|()| SyscallResult::None),
+            }
+        },
+        Syscall::DriverOpen(device) => {
+            match device {
+                Device::Console => {}
+                Device::Led => drop(led()?),
+                Device::Buttons => drop(buttons()?),
+            }
+            Ok(SyscallResult::Fd(Fd(device)))
+        }
+        Syscall::DriverRead { fd, buf } => read(fd.0, buf).map(SyscallResult::Bytes),
+        Syscall::DriverWrite { fd, buf } => write(fd.0, buf).map(SyscallResult::Bytes),
+        Syscall::HeapStats => Ok(SyscallResult::HeapStats(heap_stats())),
+    }
+}
+
+/// Fail unless the caller may block.
+fn block() -> Result<(), SyscallError> {
+    if thread::in_thread() {
+        Ok(())
+    } else {
+        Err(SyscallError::WouldBlock)
+    }
+}
+
+fn read(device: Device, buf: &mut [u8]) -> Result<usize, SyscallError> {
+    let Some(byte) = buf.first_mut() else {
+        return Ok(0);
+    };
+    let value = match device {
+        Device::Console => return Err(SyscallError::Unsupported),
+        Device::Led => led()?
+            .try_with(|led| led.is_set_high() as u8)
+            .ok_or(SyscallError::NoSuchDevice)?,
+        Device::Buttons => buttons()?
+            .try_with(|buttons| {
+                let state = buttons.state();
+                (state.scroll_up as u8 * BUTTON_UP)
+                    | (state.scroll_down as u8 * BUTTON_DOWN)
+                    | (state.select as u8 * BUTTON_SELECT)
+            })
+            .ok_or(SyscallError::NoSuchDevice)?,
+    };
+    *byte = value;
+    Ok(1)
+}
+
+fn write(device: Device, buf: &[u8]) -> Result<usize, SyscallError> {
+    match device {
+        Device::Console => {
+            let text = core::str::from_utf8(buf).map_err(|_| SyscallError::InvalidArgument)?;
+            for line in text.strip_suffix('\n').unwrap_or(text).split('\n') {
+                println!("{}", line);
+            }
+            Ok(buf.len())
+        }
+        Device::Led => {
+            let Some(&lit) = buf.last() else {
+                return Ok(0);
+            };
+            led()?
+                .try_with(|led| {
+                    if lit != 0 {
+                        led.set_high();
+                    } else {
+                        led.set_low();
+                    }
+                })
+                .ok_or(SyscallError::NoSuchDevice)?;
+            Ok(buf.len())
+        }
+        Device::Buttons => Err(SyscallError::Unsupported),
+    }
+}
+
+#[cfg(feature = "esp32")]
+fn heap_stats() -> HeapStats {
+    crate::heap::stats()
+}
+
+#[cfg(feature = "sim")]
+fn heap_stats() -> HeapStats {
+    crate::sim::heap::stats()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{
+        drivers::gpio::{self, Buttons, Input, Output},
+        os, sim,
+    };
+
+    #[test]
+    fn devices_are_read_and_written_through_descriptors() {
+        let _board = sim::board();
+        assert_eq!(os::open(Device::Led), Err(SyscallError::NoSuchDevice));
+
+        let led = Output::new();
+        let led_probe = led.probe();
+        let buttons = Buttons {
+            scroll_up: Input::pulled_up(),
+            scroll_down: Input::pulled_up(),
+            select: Input::pulled_up(),
+        };
+        let select = buttons.select.probe();
+        register_device(DeviceHandle::Led(gpio::init_led(led).unwrap()));
+        register_device(DeviceHandle::Buttons(gpio::init_buttons(buttons).unwrap()));
+
+        let fd = os::open(Device::Led).unwrap();
+        assert_eq!(os::write(fd, &[1]), Ok(1));
+        assert!(led_probe.is_high());
+        let mut byte = [0];
+        assert_eq!(os::read(fd, &mut byte), Ok(1));
+        assert_eq!(byte, [1]);
+
+        let fd = os::open(Device::Buttons).unwrap();
+        select.press();
+        assert_eq!(os::read(fd, &mut byte), Ok(1));
+        assert_eq!(byte, [BUTTON_SELECT]);
+        assert_eq!(os::write(fd, &[0]), Err(SyscallError::Unsupported));
+
+        let fd = os::open(Device::Console).unwrap();
+        assert_eq!(os::write(fd, b"one\ntwo\n"), Ok(8));
+        assert_eq!(sim::console::take_lines(), ["one", "two"]);
+        assert_eq!(os::read(fd, &mut byte), Err(SyscallError::Unsupported));
+    }
+
+    #[test]
+    fn heap_stats_count_live_allocations() {
+        const SIZE: usize = 4 * 1024 * 1024;
+        let before = os::heap_stats();
+        let block = vec![0u8; SIZE];
+        let during = os::heap_stats();
+        drop(block);
+
+        // Other tests allocate concurrently, so allow some slack.
+        assert!(during.used >= before.used + SIZE / 2);
+        assert_eq!(during.used + during.free, isize::MAX as usize);
     }
 }
@@ -1,24 +1,30 @@
 //! Cooperative task implementations used by the kernel scheduler.
 
-use core::{
-    fmt::Write as _,
-    sync::atomic::{AtomicBool, Ordering},
-};
+use core::fmt::Write as _;
 
-use esp_hal::gpio::Input;
-use esp_println::println;
 use heapless::{String, Vec};
 
 use crate::{
     bootloader_info::PartitionInfo,
-    drivers::{gpio::LedHandle, oled::OledHandle},
-    ml,
+    channel::Channel,
+    drivers::{
+        gpio::{ButtonState, ButtonsHandle, LedHandle, BUTTON_CHANGED, BUTTON_EVENTS},
+        oled::OledHandle,
+    },
+    ml, os,
+    pool::TaskPool,
+    println,
     scheduler::{Task, TaskCommand, TaskContext, TaskPriority},
+    smp::{Affinity, CoreId},
+    timer::{Duration, Instant},
+    watchdog::WatchdogConfig,
 };
 
 /// Number of lines visible on the OLED at once.
 const VISIBLE_LINES: usize = 5;
 const MAX_MENU_ITEMS: usize = 16;
+/// Time allowed for button contacts to settle after an edge.
+const DEBOUNCE_MS: u32 = 20;
 
 type MenuLabel = String<32>;
 type MenuItems = Vec<MenuItem, MAX_MENU_ITEMS>;
@@ -29,7 +35,6 @@ struct MenuItem {
     feature: MenuFeature,
 }
 
-#[allow(dead_code)]
 #[derive(Clone, Copy, Debug)]
 enum MenuFeature {
     About,
@@ -45,10 +50,7 @@ enum MenuFeature {
 /// UI task rendering boot information and handling scroll buttons.
 pub struct UiTask {
     display: Option<OledHandle>,
-    led_handle: LedHandle,
-    scroll_up: Input<'static>,
-    scroll_down: Input<'static>,
-    select_button: Input<'static>,
+    buttons: ButtonsHandle,
     menu_items: MenuItems,
     selected_index: usize,
     view_offset: usize,
@@ -59,6 +61,8 @@ pub struct UiTask {
     up_pressed: bool,
     down_pressed: bool,
     select_pressed: bool,
+    /// Set by the "Run ML Inference" entry; the job is spawned from `poll`.
+    ml_requested: bool,
     dirty: bool,
 }
 
@@ -70,15 +74,13 @@ enum UiMode {
 #[derive(Clone, Copy)]
 enum DetailView {
     About,
+    Diagnostics,
 }
 
 impl UiTask {
     pub fn new(
         display: Option<OledHandle>,
-        led_handle: LedHandle,
-        scroll_up: Input<'static>,
-        scroll_down: Input<'static>,
-        select_button: Input<'static>,
+        buttons: ButtonsHandle,
         app_name: &'static str,
         app_version: &'static str,
         partitions: [PartitionInfo; 4],
@@ -145,10 +147,7 @@ impl UiTask {
 
         Self {
             display,
-            led_handle,
-            scroll_up,
-            scroll_down,
-            select_button,
+            buttons,
             menu_items,
             selected_index: 0,
             view_offset: 0,
@@ -159,6 +158,7 @@ impl UiTask {
             up_pressed: false,
             down_pressed: false,
             select_pressed: false,
+            ml_requested: false,
             dirty: true,
         }
     }
@@ -241,6 +241,30 @@ impl UiTask {
 
                 let _ = lines.push(MenuLabel::new());
 
+                let mut line = MenuLabel::new();
+                let _ = line.push_str("> <OK>");
+                let _ = lines.push(line);
+            }
+            DetailView::Diagnostics => {
+                let heap = os::heap_stats();
+                let uptime = Duration::from_ticks(os::now().ticks());
+
+                let mut line = MenuLabel::new();
+                let _ = line.push_str("Diagnostics");
+                let _ = lines.push(line);
+
+                let mut line = MenuLabel::new();
+                let _ = write!(line, "Uptime: {} s", uptime.as_millis() / 1_000);
+                let _ = lines.push(line);
+
+                let mut line = MenuLabel::new();
+                let _ = write!(line, "Heap used: {} KiB", heap.used / 1024);
+                let _ = lines.push(line);
+
+                let mut line = MenuLabel::new();
+                let _ = write!(line, "Heap free: {} KiB", heap.free / 1024);
+                let _ = lines.push(line);
+
                 let mut line = MenuLabel::new();
                 let _ = line.push_str("> <OK>");
                 let _ = lines.push(line);
@@ -258,19 +282,23 @@ impl UiTask {
     }
 
     fn handle_input(&mut self) {
+        let Some(buttons) = self.buttons.try_with(|buttons| buttons.state()) else {
+            return;
+        };
+
         match self.mode {
-            UiMode::Menu => self.handle_menu_input(),
-            UiMode::Detail(_) => self.handle_detail_input(),
+            UiMode::Menu => self.handle_menu_input(buttons),
+            UiMode::Detail(_) => self.handle_detail_input(buttons),
         }
     }
 
-    fn handle_menu_input(&mut self) {
+    fn handle_menu_input(&mut self, buttons: ButtonState) {
         let total = self.total_items();
         if total == 0 {
             return;
         }
 
-        if self.scroll_up.is_low() {
+        if buttons.scroll_up {
             if !self.up_pressed && self.selected_index > 0 {
                 self.selected_index -= 1;
                 self.dirty = true;
@@ -280,7 +308,7 @@ impl UiTask {
             self.up_pressed = false;
         }
 
-        if self.scroll_down.is_low() {
+        if buttons.scroll_down {
             if !self.down_pressed && self.selected_index + 1 < total {
                 self.selected_index += 1;
                 self.dirty = true;
@@ -298,7 +326,7 @@ impl UiTask {
             }
         }
 
-        if self.select_button.is_low() {
+        if buttons.select {
             if !self.select_pressed {
                 self.select_pressed = true;
                 self.activate_selection();
@@ -308,8 +336,8 @@ impl UiTask {
         }
     }
 
-    fn handle_detail_input(&mut self) {
-        if self.select_button.is_low() {
+    fn handle_detail_input(&mut self, buttons: ButtonState) {
+        if buttons.select {
             if !self.select_pressed {
                 self.select_pressed = true;
                 self.mode = UiMode::Menu;
@@ -328,21 +356,17 @@ impl UiTask {
                     self.mode = UiMode::Detail(DetailView::About);
                     self.dirty = true;
                 }
+                MenuFeature::Diagnostics => {
+                    self.mode = UiMode::Detail(DetailView::Diagnostics);
+                    self.dirty = true;
+                }
                 MenuFeature::ToggleLed => {
-                    LED_HEARTBEAT_ENABLED.store(false, Ordering::Relaxed);
-                    let new_state = !LED_CURRENT_STATE.load(Ordering::Relaxed);
-                    LED_CURRENT_STATE.store(new_state, Ordering::Relaxed);
-                    let _ = self.led_handle.try_with(|led| {
-                        if new_state {
-                            let _ = led.set_high();
-                        } else {
-                            let _ = led.set_low();
-                        }
-                    });
-                    println!(
-                        "LED manual toggle -> {}",
-                        if new_state { "ON" } else { "OFF" }
-                    );
+                    if LED_COMMANDS.try_send(LedCommand::Toggle).is_err() {
+                        println!("LED command queue full");
+                    }
+                }
+                MenuFeature::RunMl => {
+                    self.ml_requested = true;
                 }
                 feature => {
                     println!("Feature {:?} not implemented", feature);
@@ -361,29 +385,79 @@ impl Task for UiTask {
         TaskPriority::High
     }
 
-    fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+    fn watchdog(&self) -> Option<WatchdogConfig> {
+        // A full redraw pushes a frame over I2C. Blocks on buttons, so no
+        // liveness check.
+        Some(WatchdogConfig::new(Duration::from_millis(200)))
+    }
+
+    fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+        // A button edge woke us: let the contacts settle before sampling.
+        // Further bounces during the delay extend it.
+        if BUTTON_EVENTS.clear(BUTTON_CHANGED) & BUTTON_CHANGED != 0 {
+            return TaskCommand::SleepMs(DEBOUNCE_MS);
+        }
+
         self.handle_input();
 
+        if core::mem::take(&mut self.ml_requested) {
+            match ML_JOBS.claim(InferenceJob) {
+                Ok(job) => {
+                    if let Err(err) = ctx.spawner().spawn(job) {
+                        println!("ML job spawn failed: {:?}", err);
+                    }
+                }
+                Err(_) => println!("ML inference already running"),
+            }
+        }
+
         if self.dirty {
             self.render();
             self.dirty = false;
         }
 
-        TaskCommand::SleepMs(50)
+        TaskCommand::Wait(BUTTON_EVENTS.any(BUTTON_CHANGED), None)
     }
 }
 
-static LED_HEARTBEAT_ENABLED: AtomicBool = AtomicBool::new(true);
-static LED_CURRENT_STATE: AtomicBool = AtomicBool::new(false);
+/// Requests handled by [`LedTask`].
+#[derive(Clone, Copy, Debug)]
+enum LedCommand {
+    /// Stop the heartbeat and flip the LED.
+    Toggle,
+}
+
+static LED_COMMANDS: Channel<LedCommand, 4> = Channel::new();
+
+const HEARTBEAT_PERIOD: Duration = Duration::from_millis(500);
 
-/// Simple LED heartbeat task.
+/// Simple LED heartbeat task, also driven by [`LedCommand`]s from the UI.
 pub struct LedTask {
     led: LedHandle,
+    heartbeat: bool,
+    lit: bool,
+    next_toggle: Instant,
 }
 
 impl LedTask {
     pub fn new(led: LedHandle) -> Self {
-        Self { led }
+        Self {
+            led,
+            heartbeat: true,
+            lit: false,
+            next_toggle: Instant::from_ticks(0),
+        }
+    }
+
+    fn set(&mut self, lit: bool) {
+        self.lit = lit;
+        let _ = self.led.try_with(|led| {
+            if lit {
+                led.set_high();
+            } else {
+                led.set_low();
+            }
+        });
     }
 }
 
@@ -392,26 +466,44 @@ impl Task for LedTask {
         "led"
     }
 
-    fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
-        if !LED_HEARTBEAT_ENABLED.load(Ordering::Relaxed) {
-            return TaskCommand::SleepMs(100);
+    fn watchdog(&self) -> Option<WatchdogConfig> {
+        Some(WatchdogConfig::new(Duration::from_millis(10)))
+    }
+
+    fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+        while let Some(command) = LED_COMMANDS.try_recv() {
+            match command {
+                LedCommand::Toggle => {
+                    self.heartbeat = false;
+                    self.set(!self.lit);
+                    println!(
+                        "LED manual toggle -> {}",
+                        if self.lit { "ON" } else { "OFF" }
+                    );
+                }
+            }
         }
 
-        let new_state = !LED_CURRENT_STATE.load(Ordering::Relaxed);
-        LED_CURRENT_STATE.store(new_state, Ordering::Relaxed);
+        if !self.heartbeat {
+            return TaskCommand::Wait(LED_COMMANDS.receivable(), None);
+        }
 
-        let _ = self.led.try_with(|led| {
-            if new_state {
-                let _ = led.set_high();
-            } else {
-                let _ = led.set_low();
+        if ctx.now >= self.next_toggle {
+            self.set(!self.lit);
+            // Stay in phase with the previous toggle unless a whole period
+            // was missed.
+            self.next_toggle += HEARTBEAT_PERIOD;
+            if self.next_toggle <= ctx.now {
+                self.next_toggle = ctx.now + HEARTBEAT_PERIOD;
             }
-        });
+        }
 
-        TaskCommand::SleepMs(500)
+        TaskCommand::Wait(LED_COMMANDS.receivable(), Some(self.next_toggle - ctx.now))
     }
 }
 
+const INFERENCE_PERIOD: Duration = Duration::from_millis(100);
+
 /// Periodic ML inference task.
 pub struct MlTask;
 
@@ -426,9 +518,138 @@ impl Task for MlTask {
         "ml"
     }
 
+    fn watchdog(&self) -> Option<WatchdogConfig> {
+        Some(
+            WatchdogConfig::new(Duration::from_millis(50))
+                .with_liveness(Duration::from_millis(1_000)),
+        )
+    }
+
+    fn period(&self) -> Option<Duration> {
+        Some(INFERENCE_PERIOD)
+    }
+
+    /// Inference runs on the APP core so it cannot hold up the UI.
+    fn affinity(&self) -> Affinity {
+        Affinity::Pinned(CoreId::App)
+    }
+
+    fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+        if ctx.overruns > 0 {
+            println!("ML inference skipped {} periods", ctx.overruns);
+        }
+        ml::run_inference();
+        TaskCommand::Periodic(INFERENCE_PERIOD)
+    }
+}
+
+/// One-shot inference launched from the UI menu.
+struct InferenceJob;
+
+/// Room for a single job, so repeated presses cannot pile up runs.
+static ML_JOBS: TaskPool<InferenceJob, 1> = TaskPool::new();
+
+impl Task for InferenceJob {
+    fn name(&self) -> &'static str {
+        "ml-job"
+    }
+
     fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
         ml::run_inference();
-        TaskCommand::SleepMs(100)
+        println!("ML inference complete");
+        TaskCommand::Finished
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+
+    use super::*;
+    use crate::{
+        bootloader_info::get_partition_info,
+        drivers::{
+            gpio::{self, Buttons, Input},
+            oled,
+        },
+        scheduler::Scheduler,
+        sim::{self, display::OledDisplay},
+    };
+
+    #[test]
+    fn led_task_toggles_heartbeat_every_500ms() {
+        let _board = sim::board();
+        let led = gpio::Output::new();
+        let probe = led.probe();
+        let mut scheduler = Scheduler::new();
+        let task = Box::leak(Box::new(LedTask::new(gpio::init_led(led).unwrap())));
+        scheduler.spawn(task).unwrap();
+
+        scheduler.run_ready();
+        let first = probe.is_high();
+        sim::clock::advance_ms(499);
+        scheduler.run_ready();
+        assert_eq!(probe.is_high(), first);
+
+        sim::clock::advance_ms(1);
+        scheduler.run_ready();
+        assert_eq!(probe.is_high(), !first);
+    }
+
+    #[test]
+    fn led_toggle_command_stops_heartbeat() {
+        let _board = sim::board();
+        let led = gpio::Output::new();
+        let probe = led.probe();
+        let mut scheduler = Scheduler::new();
+        let task = Box::leak(Box::new(LedTask::new(gpio::init_led(led).unwrap())));
+        scheduler.spawn(task).unwrap();
+
+        scheduler.run_ready();
+        assert!(probe.is_high());
+
+        LED_COMMANDS.try_send(LedCommand::Toggle).unwrap();
+        scheduler.run_ready();
+        assert!(!probe.is_high());
+
+        sim::clock::advance_ms(2_000);
+        scheduler.run_ready();
+        assert!(!probe.is_high());
+    }
+
+    #[test]
+    fn ui_task_scrolls_menu_on_button_press() {
+        let _board = sim::board();
+        let display = OledDisplay::new();
+        let screen = display.probe();
+        let buttons = Buttons {
+            scroll_up: Input::pulled_up(),
+            scroll_down: Input::pulled_up(),
+            select: Input::pulled_up(),
+        };
+        let down_button = buttons.scroll_down.probe();
+        let ui = UiTask::new(
+            Some(oled::init_oled(display).unwrap()),
+            gpio::init_buttons(buttons).unwrap(),
+            "app",
+            "1.0",
+            get_partition_info(),
+        );
+        let mut scheduler = Scheduler::new();
+        scheduler.spawn(Box::leak(Box::new(ui))).unwrap();
+
+        scheduler.run_ready();
+        assert_eq!(screen.lines()[0], "> About TrustG33k OS");
+
+        // The UI sleeps until a button edge, then samples after the debounce.
+        sim::clock::advance_ms(1_000);
+        down_button.press();
+        scheduler.run_ready();
+        assert_eq!(screen.lines()[0], "> About TrustG33k OS");
+
+        sim::clock::advance_ms(DEBOUNCE_MS);
+        scheduler.run_ready();
+        assert_eq!(screen.lines()[0], "  About TrustG33k OS");
+        assert_eq!(screen.lines()[1], "> App: app");
+    }
+}
@@ -0,0 +1,244 @@
+//! Task-local storage.
+//!
+//! A [`LocalKey`] declared with [`task_local!`](crate::task_local) gives every
+//! task its own value. The value is created by the key's initializer the
+//! first time the task uses it and dropped when the task is reaped, so kernel
+//! services can keep per-task state (an errno-style last error, allocation
+//! counters) without the task passing it around.
+//!
+//! Values are reached with [`LocalKey::with`] from anywhere inside a task's
+//! poll, including the body of a thread task or an async task, or with
+//! [`TaskContext::with_local`](crate::scheduler::TaskContext::with_local).
+//! Like `std::thread_local!`, they are shared references: use `Cell` or
+//! `RefCell` for values that change.
+//!
+//! ```ignore
+//! crate::task_local! {
+//!     static LAST_ERROR: Cell<i32> = Cell::new(0);
+//! }
+//!
+//! LAST_ERROR.with(|err| err.set(-1));
+//! ```
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    any::Any,
+    cell::RefCell,
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use crate::smp::{self, PerCore};
+
+/// Declare [`LocalKey`] statics.
+#[macro_export]
+macro_rules! task_local {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)+) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::task_local::LocalKey<$ty> =
+                $crate::task_local::LocalKey::new(|| $init);
+        )+
+    };
+}
+
+/// Error from [`LocalKey::try_with`] outside of a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessError;
+
+/// Key to a value each task has its own copy of.
+pub struct LocalKey<T: 'static> {
+    init: fn() -> T,
+}
+
+impl<T: 'static> LocalKey<T> {
+    #[doc(hidden)]
+    #[allow(dead_code, reason = "the firmware declares no task locals")]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self { init }
+    }
+
+    /// Run `f` with the current task's value, creating it first if needed.
+    ///
+    /// Panics outside of a task.
+    #[allow(dead_code, reason = "the firmware's tasks keep no task-local state")]
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        self.try_with(f)
+            .expect("task-local accessed outside of a task")
+    }
+
+    /// Run `f` with the current task's value, or fail outside of a task.
+    #[allow(dead_code, reason = "the firmware's tasks keep no task-local state")]
+    pub fn try_with<R>(&'static self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        let locals = CURRENT_LOCALS.get().load(Ordering::Acquire);
+        if locals.is_null() {
+            return Err(AccessError);
+        }
+        // SAFETY: the pointer is only set while the task owning the values
+        // is being polled on this core, and the scheduler does not move or
+        // drop its slot during the poll.
+        Ok(unsafe { &*locals }.with(self, f))
+    }
+}
+
+/// Task-local values of one task, keyed by the address of their [`LocalKey`].
+#[derive(Default)]
+pub struct TaskLocals {
+    values: RefCell<Vec<(usize, Box<dyn Any>)>>,
+}
+
+impl TaskLocals {
+    /// Run `f` with the value for `key`, creating it first if needed.
+    pub fn with<T: 'static, R>(&self, key: &'static LocalKey<T>, f: impl FnOnce(&T) -> R) -> R {
+        let id = key as *const LocalKey<T> as usize;
+        let value = match self.find::<T>(id) {
+            Some(value) => value,
+            None => {
+                // The initializer may use other task-locals, so it runs
+                // without holding the borrow.
+                let value = (key.init)();
+                match self.find::<T>(id) {
+                    Some(value) => value,
+                    None => {
+                        let value = Box::new(value);
+                        let ptr: *const T = &*value;
+                        self.values.borrow_mut().push((id, value));
+                        ptr
+                    }
+                }
+            }
+        };
+        // SAFETY: values are boxed, so they stay at the same address when
+        // the list grows, and are only dropped together with `self`.
+        f(unsafe { &*value })
+    }
+
+    fn find<T: 'static>(&self, id: usize) -> Option<*const T> {
+        self.values
+            .borrow()
+            .iter()
+            .find(|(key, _)| *key == id)
+            .and_then(|(_, value)| value.downcast_ref::<T>())
+            .map(|value| value as *const T)
+    }
+}
+
+/// Values of the task being polled on each core; null between polls.
+static CURRENT_LOCALS: PerCore<AtomicPtr<TaskLocals>> =
+    PerCore::new([const { AtomicPtr::new(ptr::null_mut()) }; smp::NUM_CORES]);
+
+/// Make `locals` the values [`LocalKey::with`] sees on this core, until
+/// called again with `None`.
+pub(crate) fn set_current(locals: Option<&TaskLocals>) {
+    let ptr = locals.map_or(ptr::null_mut(), |locals| locals as *const _ as *mut _);
+    CURRENT_LOCALS.get().store(ptr, Ordering::Release);
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc, vec::Vec};
+    use core::{cell::Cell, sync::atomic::AtomicU32};
+
+    use super::*;
+    use crate::{
+        scheduler::{Scheduler, Task, TaskCommand, TaskContext},
+        sim,
+        thread::{self, ThreadTask},
+    };
+
+    crate::task_local! {
+        static POLLS: Cell<u32> = Cell::new(0);
+        static TRACKED: Tracked = Tracked::new();
+    }
+
+    static LIVE: AtomicU32 = AtomicU32::new(0);
+
+    /// Counts live instances, to check values are dropped.
+    struct Tracked;
+
+    impl Tracked {
+        fn new() -> Self {
+            LIVE.fetch_add(1, Ordering::SeqCst);
+            Tracked
+        }
+    }
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            LIVE.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Counts its own polls in task-local storage and records the count.
+    struct Counter {
+        polls: u32,
+        log: Rc<RefCell<Vec<(&'static str, u32)>>>,
+        name: &'static str,
+    }
+
+    impl Task for Counter {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+            let seen = POLLS.with(|polls| {
+                polls.set(polls.get() + 1);
+                polls.get()
+            });
+            ctx.with_local(&TRACKED, |_| ());
+            self.log.borrow_mut().push((self.name, seen));
+            self.polls -= 1;
+            if self.polls == 0 {
+                TaskCommand::Finished
+            } else {
+                TaskCommand::Continue
+            }
+        }
+    }
+
+    #[test]
+    fn each_task_has_its_own_values_until_it_is_reaped() {
+        let _board = sim::board();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut scheduler = Scheduler::new();
+        for (name, polls) in [("a", 2), ("b", 3)] {
+            let task = Counter {
+                polls,
+                log: log.clone(),
+                name,
+            };
+            scheduler.spawn(Box::new(task)).unwrap();
+        }
+
+        scheduler.run_ready();
+        assert_eq!(LIVE.load(Ordering::SeqCst), 2);
+        scheduler.run_ready();
+        assert_eq!(LIVE.load(Ordering::SeqCst), 1);
+        scheduler.run_ready();
+        assert_eq!(LIVE.load(Ordering::SeqCst), 0);
+
+        let mut log = log.borrow().clone();
+        log.sort();
+        assert_eq!(log, [("a", 1), ("a", 2), ("b", 1), ("b", 2), ("b", 3)]);
+    }
+
+    #[test]
+    fn thread_bodies_reach_their_values_and_others_cannot() {
+        static SEEN: AtomicU32 = AtomicU32::new(0);
+        let _board = sim::board();
+        let mut scheduler = Scheduler::new();
+        let worker = ThreadTask::new("worker", || {
+            POLLS.with(|polls| polls.set(7));
+            thread::yield_now();
+            SEEN.store(POLLS.with(Cell::get), Ordering::SeqCst);
+        });
+        scheduler.spawn(Box::new(worker)).unwrap();
+
+        scheduler.run_ready();
+        assert_eq!(POLLS.try_with(Cell::get), Err(AccessError));
+        scheduler.run_ready();
+
+        assert_eq!(SEEN.load(Ordering::SeqCst), 7);
+    }
+}
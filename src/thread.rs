@@ -0,0 +1,286 @@
+//! Stackful (blocking-style) tasks.
+//!
+//! A [`ThreadTask`] runs an ordinary function on the task's own [`TaskStack`]
+//! instead of the main stack. The function can block from anywhere in its
+//! call stack with [`yield_now`], [`sleep`] or [`wait`]: the kernel saves its
+//! context, switches back to the scheduler and resumes it when the task is
+//! polled again. Context switching goes through [`crate::arch::ContextSwitch`].
+//!
+//! Thread tasks are also the unit of preemption: with time-slicing enabled in
+//! [`crate::preempt`], the tick interrupt switches a thread out when its slice
+//! is used up.
+
+use alloc::boxed::Box;
+use core::{
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use crate::{
+    arch::{Context, ContextSwitch, Cpu},
+    event::WaitCondition,
+    preempt,
+    scheduler::{Task, TaskCommand, TaskContext, TaskPriority},
+    smp::{self, PerCore},
+    stack::DEFAULT_STACK_SIZE,
+    timer::{Duration, Instant},
+};
+
+/// Thread task currently executing on its own stack on each core, if any.
+static CURRENT: PerCore<AtomicPtr<ThreadTask>> =
+    PerCore::new([const { AtomicPtr::new(ptr::null_mut()) }; smp::NUM_CORES]);
+
+/// Task whose body runs on a dedicated stack and may block.
+pub struct ThreadTask {
+    name: &'static str,
+    priority: TaskPriority,
+    stack_size: usize,
+    body: Option<Box<dyn FnOnce()>>,
+    started: bool,
+    finished: bool,
+    /// Command handed to the scheduler when the thread switches out.
+    command: TaskCommand,
+    thread: Context,
+    scheduler: Context,
+}
+
+impl ThreadTask {
+    /// Create a thread running `body` with default priority and stack size.
+    #[allow(dead_code, reason = "the firmware runs no threads")]
+    pub fn new(name: &'static str, body: impl FnOnce() + 'static) -> Self {
+        Self {
+            name,
+            priority: TaskPriority::default(),
+            stack_size: DEFAULT_STACK_SIZE,
+            body: Some(Box::new(body)),
+            started: false,
+            finished: false,
+            command: TaskCommand::Continue,
+            thread: Context::default(),
+            scheduler: Context::default(),
+        }
+    }
+
+    /// Set the task priority.
+    #[allow(dead_code, reason = "the firmware runs no threads")]
+    pub fn with_priority(mut self, priority: TaskPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Set the requested stack size in bytes.
+    #[allow(dead_code, reason = "the firmware runs no threads")]
+    pub fn with_stack_size(mut self, stack_size: usize) -> Self {
+        self.stack_size = stack_size;
+        self
+    }
+}
+
+/// First code executed on a thread's stack.
+extern "C" fn thread_entry(arg: usize) -> ! {
+    let thread = arg as *mut ThreadTask;
+    unsafe {
+        if let Some(body) = (*thread).body.take() {
+            body();
+        }
+        (*thread).finished = true;
+        Cpu::switch(&mut (*thread).thread, &(*thread).scheduler);
+    }
+    unreachable!("finished thread resumed");
+}
+
+impl Task for ThreadTask {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn priority(&self) -> TaskPriority {
+        self.priority
+    }
+
+    fn stack_size(&self) -> usize {
+        self.stack_size.max(Cpu::MIN_STACK_SIZE)
+    }
+
+    fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+        if self.finished {
+            return TaskCommand::Finished;
+        }
+
+        let this: *mut ThreadTask = self;
+        unsafe {
+            if !(*this).started {
+                (*this).started = true;
+                Cpu::init(
+                    &mut (*this).thread,
+                    ctx.stack(),
+                    thread_entry,
+                    this as usize,
+                );
+            }
+
+            CURRENT.get().store(this, Ordering::Release);
+            preempt::begin_slice((*this).priority);
+            Cpu::switch(&mut (*this).scheduler, &(*this).thread);
+            preempt::end_slice();
+            CURRENT.get().store(ptr::null_mut(), Ordering::Release);
+
+            if (*this).finished {
+                TaskCommand::Finished
+            } else {
+                (*this).command
+            }
+        }
+    }
+}
+
+/// Switch from the running thread back to the scheduler.
+fn suspend(command: TaskCommand) {
+    let thread = CURRENT.get().load(Ordering::Acquire);
+    assert!(!thread.is_null(), "blocking call outside of a thread task");
+    unsafe {
+        (*thread).command = command;
+        Cpu::switch(&mut (*thread).thread, &(*thread).scheduler);
+    }
+}
+
+/// Switch the running thread out because its time slice expired.
+///
+/// Called from the tick or doorbell interrupt; does nothing if no thread is
+/// running.
+pub(crate) fn preempt_current() {
+    if in_thread() {
+        suspend(TaskCommand::Continue);
+    }
+}
+
+/// Let other ready tasks run, resuming on the next scheduler pass.
+pub fn yield_now() {
+    suspend(TaskCommand::Continue);
+}
+
+/// Block the current thread for at least `ms` milliseconds.
+#[allow(dead_code, reason = "the firmware runs no threads")]
+pub fn sleep(ms: u32) {
+    sleep_for(Duration::from_millis(ms as u64));
+}
+
+/// Block the current thread for at least `duration`.
+pub fn sleep_for(duration: Duration) {
+    suspend(TaskCommand::Sleep(duration));
+}
+
+/// Block the current thread until `deadline`.
+#[allow(dead_code, reason = "the firmware runs no threads")]
+pub fn sleep_until(deadline: Instant) {
+    while Instant::now() < deadline {
+        suspend(TaskCommand::Park(Some(deadline)));
+    }
+}
+
+/// Block the current thread until `condition` may hold; check it again
+/// after waking.
+pub fn wait(condition: WaitCondition) {
+    suspend(TaskCommand::Wait(condition, None));
+}
+
+/// End the current thread with exit code `code`. Like
+/// [`Scheduler::kill`](crate::scheduler::Scheduler::kill), destructors of
+/// values on its stack do not run.
+pub fn exit(code: i32) -> ! {
+    suspend(TaskCommand::Exit(code));
+    unreachable!("exited thread resumed");
+}
+
+/// Whether the caller runs on a thread task's stack, where it may block.
+pub fn in_thread() -> bool {
+    !CURRENT.get().load(Ordering::Acquire).is_null()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{rc::Rc, vec::Vec};
+    use core::cell::RefCell;
+
+    use super::*;
+    use crate::{scheduler::Scheduler, sim};
+
+    type Log = Rc<RefCell<Vec<(&'static str, u64)>>>;
+
+    fn record(log: &Log, what: &'static str) {
+        log.borrow_mut().push((what, Instant::now().ticks()));
+    }
+
+    /// Blocks several calls deep to show the whole call stack is preserved.
+    fn nested_wait(log: &Log, depth: u32) {
+        if depth == 0 {
+            sleep(10);
+            record(log, "woke");
+        } else {
+            nested_wait(log, depth - 1);
+        }
+    }
+
+    #[test]
+    fn thread_sleeps_from_nested_call() {
+        let _board = sim::board();
+        let log = Log::default();
+        let thread_log = log.clone();
+        let mut scheduler = Scheduler::new();
+        let thread = ThreadTask::new("sleeper", move || {
+            record(&thread_log, "start");
+            nested_wait(&thread_log, 8);
+            record(&thread_log, "end");
+        });
+        scheduler.spawn(Box::leak(Box::new(thread))).unwrap();
+
+        for _ in 0..20 {
+            scheduler.run_ready();
+            sim::clock::advance(1);
+        }
+
+        assert_eq!(*log.borrow(), [("start", 0), ("woke", 10), ("end", 10)]);
+        assert_eq!(scheduler.task_count(), 0);
+    }
+
+    #[test]
+    fn threads_interleave_on_yield() {
+        let _board = sim::board();
+        let log = Log::default();
+        let mut scheduler = Scheduler::new();
+        for name in ["a", "b"] {
+            let log = log.clone();
+            let thread = ThreadTask::new(name, move || {
+                for _ in 0..2 {
+                    record(&log, name);
+                    yield_now();
+                }
+            });
+            scheduler.spawn(Box::leak(Box::new(thread))).unwrap();
+        }
+
+        for _ in 0..3 {
+            scheduler.run_ready();
+        }
+
+        let names: Vec<_> = log.borrow().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["a", "b", "a", "b"]);
+    }
+
+    #[test]
+    fn stack_high_water_mark_is_reported_per_task() {
+        let _board = sim::board();
+        let log = Log::default();
+        let thread_log = log.clone();
+        let mut scheduler = Scheduler::new();
+        let thread = ThreadTask::new("deep", move || nested_wait(&thread_log, 8));
+        scheduler.spawn(Box::leak(Box::new(thread))).unwrap();
+
+        scheduler.run_ready();
+
+        let stats = scheduler.stats();
+        let task = &stats.tasks[0];
+        assert!(task.stack_used > 0);
+        assert!(task.stack_used < task.stack_size);
+    }
+}
@@ -2,36 +2,209 @@
 //!
 //! Provides system-wide timekeeping using the ESP32 timer peripheral (TIMG0).
 //! The timer generates a periodic interrupt that increments a global tick
-//! counter, forming the "heartbeat" of the cooperative OS.
+//! counter, forming the "heartbeat" of the cooperative OS. The `sim` build
+//! replaces the interrupt with the virtual tick source in [`crate::sim::clock`].
+//!
+//! Time is kept as a 64-bit tick count so it never wraps in practice (about
+//! 584 million years at 1 kHz); kernel code should use [`Instant`] and
+//! [`Duration`] rather than raw tick arithmetic.
+//!
+//! In [`TickMode::Tickless`], which the firmware uses when built with the
+//! `tickless` feature, the timer runs as a one-shot alarm instead: ticks are
+//! derived from the free-running microsecond counter, and the scheduler's
+//! idle hook ([`set_alarm`]) reprograms the alarm to the next task deadline so
+//! the CPU is not woken every millisecond while idle. The simulation jumps the
+//! virtual clock straight to the alarm.
+//!
+//! The timer interrupt is handled on the PRO core. Each core's idle hook
+//! records its own next deadline; the alarm fires at the earliest of them and
+//! the interrupt rings the doorbell of any other core whose deadline has
+//! passed (see [`crate::smp`]).
 
-use core::cell::RefCell;
-use core::sync::atomic::{AtomicU32, Ordering};
+#[cfg(feature = "esp32")]
+use core::cell::{Cell, RefCell};
+use core::ops::{Add, AddAssign, Sub};
 
+#[cfg(feature = "esp32")]
 use critical_section::Mutex;
+#[cfg(feature = "esp32")]
 use esp_hal::{
     interrupt::{self, IsrCallback, Priority},
     peripherals::{Interrupt, TIMG0},
-    timer::{timg::TimerGroup, PeriodicTimer},
-    time::Duration,
+    time::Duration as HalDuration,
+    timer::{timg::TimerGroup, OneShotTimer, PeriodicTimer},
     Blocking,
 };
 
+#[cfg(feature = "esp32")]
+use crate::smp::{self, CoreId};
+
 /// System tick frequency in Hz (1000 Hz = 1ms per tick)
 pub const TICK_FREQUENCY_HZ: u32 = 1_000;
 
-/// Global system tick counter (32-bit → wraps after ~49 days at 1 kHz)
-static SYSTEM_TICKS: AtomicU32 = AtomicU32::new(0);
+/// Length of one tick in microseconds.
+const TICK_PERIOD_US: u64 = 1_000_000 / TICK_FREQUENCY_HZ as u64;
+
+/// Longest a tickless alarm may be set into the future, so the main loop
+/// still gets to feed the hardware watchdog while every task waits.
+pub const MAX_IDLE: Duration = Duration::from_millis(1_000);
+
+/// Shortest alarm delay; nearer (or past) deadlines are pushed out to this
+/// so the alarm is never programmed in the past.
+const MIN_ALARM_US: u64 = 10;
+
+/// How the system timer drives the tick count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg(feature = "esp32")]
+pub enum TickMode {
+    /// Interrupt on every tick.
+    Periodic,
+    /// One-shot alarm at the next deadline; ticks come from the free-running
+    /// counter.
+    Tickless,
+}
+
+/// Global system tick counter (64-bit; the Xtensa core has no 64-bit atomics
+/// so it is guarded by a critical section).
+#[cfg(feature = "esp32")]
+static SYSTEM_TICKS: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
 
 /// Stored hardware timer instance so we can acknowledge interrupts.
 /// Wrapped in a critical-section Mutex to allow safe access from ISRs.
-type HwTimer = PeriodicTimer<'static, Blocking>;
+#[cfg(feature = "esp32")]
+enum HwTimer {
+    Periodic(PeriodicTimer<'static, Blocking>),
+    OneShot(OneShotTimer<'static, Blocking>),
+}
+#[cfg(feature = "esp32")]
 static TIMER: Mutex<RefCell<Option<HwTimer>>> = Mutex::new(RefCell::new(None));
 
-/// Initialize the system timer (TIMG0 timer0) to generate periodic interrupts.
+/// Free-running counter value at tick 0 in tickless mode; `None` while the
+/// periodic interrupt counts ticks.
+#[cfg(feature = "esp32")]
+static TICKLESS_EPOCH_US: Mutex<Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+
+/// Next deadline of each idle core, as passed to [`set_alarm`].
+#[cfg(feature = "esp32")]
+static CORE_DEADLINES: Mutex<Cell<[Option<Instant>; smp::NUM_CORES]>> =
+    Mutex::new(Cell::new([None; smp::NUM_CORES]));
+
+/// A point in time measured in system ticks since startup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    ticks: u64,
+}
+
+impl Instant {
+    /// An instant that is never reached.
+    pub const MAX: Self = Self { ticks: u64::MAX };
+
+    /// Current system time.
+    pub fn now() -> Self {
+        Self::from_ticks(get_ticks())
+    }
+
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Self { ticks }
+    }
+
+    /// Ticks since system startup.
+    pub const fn ticks(self) -> u64 {
+        self.ticks
+    }
+
+    /// Time elapsed since `earlier`, or zero if `earlier` is in the future.
+    pub fn saturating_duration_since(self, earlier: Instant) -> Duration {
+        Duration::from_ticks(self.ticks.saturating_sub(earlier.ticks))
+    }
+
+    /// Time elapsed since this instant.
+    pub fn elapsed(self) -> Duration {
+        Instant::now().saturating_duration_since(self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant::from_ticks(self.ticks.saturating_add(rhs.ticks))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.saturating_duration_since(rhs)
+    }
+}
+
+/// A span of time measured in system ticks.
+///
+/// Constructors from wall-clock units round up to the next whole tick so a
+/// non-zero request never becomes a zero-length sleep.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Duration {
+    ticks: u64,
+}
+
+impl Duration {
+    pub const ZERO: Self = Self { ticks: 0 };
+
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Self { ticks }
+    }
+
+    /// Duration of at least `ms` milliseconds.
+    pub const fn from_millis(ms: u64) -> Self {
+        Self::from_ticks(ms.saturating_mul(TICK_FREQUENCY_HZ as u64).div_ceil(1_000))
+    }
+
+    /// Duration of at least `us` microseconds.
+    pub const fn from_micros(us: u64) -> Self {
+        Self::from_ticks(
+            us.saturating_mul(TICK_FREQUENCY_HZ as u64)
+                .div_ceil(1_000_000),
+        )
+    }
+
+    pub const fn ticks(self) -> u64 {
+        self.ticks
+    }
+
+    /// Whole milliseconds in this duration.
+    pub const fn as_millis(self) -> u64 {
+        self.ticks.saturating_mul(1_000) / TICK_FREQUENCY_HZ as u64
+    }
+
+    /// Whole microseconds in this duration.
+    pub const fn as_micros(self) -> u64 {
+        self.ticks.saturating_mul(1_000_000) / TICK_FREQUENCY_HZ as u64
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        Duration::from_ticks(self.ticks.saturating_add(rhs.ticks))
+    }
+}
+
+/// Initialize the system timer (TIMG0 timer0) to interrupt on every tick or,
+/// in [`TickMode::Tickless`], at one-shot alarms.
 ///
 /// # Safety
 /// Must be called exactly once during system startup, before the scheduler starts.
-pub unsafe fn init(timg0: TIMG0<'static>) -> Result<(), &'static str> {
+#[cfg(feature = "esp32")]
+pub unsafe fn init(timg0: TIMG0<'static>, mode: TickMode) -> Result<(), &'static str> {
     critical_section::with(|cs| {
         if TIMER.borrow_ref(cs).is_some() {
             return Err("System timer already initialized");
@@ -39,14 +212,28 @@ pub unsafe fn init(timg0: TIMG0<'static>) -> Result<(), &'static str> {
 
         // Create the timer group driver
         let tg0 = TimerGroup::new(timg0);
-        let mut timer0 = PeriodicTimer::new(tg0.timer0);
-
-        // Configure auto-reload period based on desired tick frequency
-        let period = Duration::from_micros((1_000_000u32 / TICK_FREQUENCY_HZ) as u64);
-        timer0
-            .start(period)
-            .map_err(|_| "Failed to start system timer")?;
-        timer0.listen();
+        let period = HalDuration::from_micros(TICK_PERIOD_US);
+        let timer0 = match mode {
+            TickMode::Periodic => {
+                // Configure auto-reload period based on desired tick frequency
+                let mut timer0 = PeriodicTimer::new(tg0.timer0);
+                timer0
+                    .start(period)
+                    .map_err(|_| "Failed to start system timer")?;
+                timer0.listen();
+                HwTimer::Periodic(timer0)
+            }
+            TickMode::Tickless => {
+                // Tick 0 starts now; the first alarm is the next tick.
+                TICKLESS_EPOCH_US.borrow(cs).set(Some(now_micros()));
+                let mut timer0 = OneShotTimer::new(tg0.timer0);
+                timer0
+                    .schedule(period)
+                    .map_err(|_| "Failed to start system timer")?;
+                timer0.listen();
+                HwTimer::OneShot(timer0)
+            }
+        };
 
         // Route the timer interrupt to our handler at priority level 1
         unsafe {
@@ -66,31 +253,230 @@ pub unsafe fn init(timg0: TIMG0<'static>) -> Result<(), &'static str> {
 }
 
 /// Returns the number of ticks since system startup.
-pub fn get_ticks() -> u32 {
-    SYSTEM_TICKS.load(Ordering::Relaxed)
+#[cfg(feature = "esp32")]
+pub fn get_ticks() -> u64 {
+    critical_section::with(|cs| match TICKLESS_EPOCH_US.borrow(cs).get() {
+        Some(epoch) => now_micros().saturating_sub(epoch) / TICK_PERIOD_US,
+        None => SYSTEM_TICKS.borrow(cs).get(),
+    })
 }
 
-/// Converts milliseconds to ticks, rounding down.
-pub fn ms_to_ticks(ms: u32) -> u32 {
-    ms.saturating_mul(TICK_FREQUENCY_HZ) / 1_000
+/// Returns the number of ticks since system startup.
+#[cfg(feature = "sim")]
+pub fn get_ticks() -> u64 {
+    crate::sim::clock::now()
 }
 
-/// ISR trampoline registered with the HAL interrupt controller.
-extern "C" fn timer_isr_trampoline() {
-    // Increment tick counter first to minimize latency for waiting tasks
-    SYSTEM_TICKS.fetch_add(1, Ordering::Relaxed);
+/// Microseconds since startup at sub-tick resolution, for profiling.
+#[cfg(feature = "esp32")]
+pub fn now_micros() -> u64 {
+    esp_hal::time::Instant::now()
+        .duration_since_epoch()
+        .as_micros()
+}
+
+/// Microseconds since startup. The virtual clock only moves in whole ticks.
+#[cfg(feature = "sim")]
+pub fn now_micros() -> u64 {
+    Duration::from_ticks(get_ticks()).as_micros()
+}
+
+/// Converts milliseconds to ticks, rounding up.
+pub fn ms_to_ticks(ms: u32) -> u64 {
+    Duration::from_millis(ms as u64).ticks()
+}
+
+/// Microseconds from `elapsed_us` (time since tick 0) until the alarm for
+/// `deadline` must fire: the start of the deadline tick, clamped to between
+/// [`MIN_ALARM_US`] and [`MAX_IDLE`]. `None` means no task deadline.
+pub fn alarm_delay_us(elapsed_us: u64, deadline: Option<Instant>) -> u64 {
+    deadline
+        .map_or(u64::MAX, |deadline| {
+            deadline.ticks().saturating_mul(TICK_PERIOD_US)
+        })
+        .saturating_sub(elapsed_us)
+        .clamp(MIN_ALARM_US, MAX_IDLE.as_micros())
+}
 
-    // Acknowledge hardware interrupt
+/// Record the calling core's next `deadline` and program the tickless alarm
+/// for the earliest deadline of any core. Meant as the scheduler's idle hook,
+/// which also runs when the CPU wakes with the next tick as the deadline: the
+/// alarm then fires every tick until the next idle period, whatever interrupt
+/// woke the CPU.
+#[cfg(feature = "esp32")]
+pub fn set_alarm(deadline: Option<Instant>) {
     critical_section::with(|cs| {
-        if let Some(timer) = TIMER.borrow_ref_mut(cs).as_mut() {
-            timer.clear_interrupt();
+        let cell = CORE_DEADLINES.borrow(cs);
+        let mut deadlines = cell.get();
+        deadlines[smp::current_core().index()] = deadline;
+        cell.set(deadlines);
+        if let Some(epoch) = TICKLESS_EPOCH_US.borrow(cs).get() {
+            let earliest = deadlines.iter().flatten().min().copied();
+            let delay = alarm_delay_us(now_micros().saturating_sub(epoch), earliest);
+            schedule_alarm(cs, delay);
         }
     });
 }
 
-/// Manual tick increment helper (used for testing without hardware timer).
-#[allow(dead_code)]
-pub unsafe fn force_tick() {
-    timer_isr_trampoline();
+/// Ring the doorbell of every other core whose deadline has passed.
+#[cfg(feature = "esp32")]
+fn wake_due_cores(cs: critical_section::CriticalSection<'_>) {
+    let now = Instant::now();
+    let here = smp::current_core();
+    let cell = CORE_DEADLINES.borrow(cs);
+    let mut deadlines = cell.get();
+    for (index, deadline) in deadlines.iter_mut().enumerate() {
+        let core = CoreId::from_index(index);
+        if core != here && deadline.is_some_and(|deadline| deadline <= now) {
+            *deadline = None;
+            smp::interrupt_core(core);
+        }
+    }
+    cell.set(deadlines);
+}
+
+/// Program the virtual clock to jump to `deadline` when the calling core
+/// idles.
+#[cfg(feature = "sim")]
+pub fn set_alarm(deadline: Option<Instant>) {
+    let delay = Duration::from_micros(alarm_delay_us(now_micros(), deadline));
+    crate::sim::clock::set_alarm(Some(get_ticks() + delay.ticks()));
+}
+
+#[cfg(feature = "esp32")]
+fn schedule_alarm(cs: critical_section::CriticalSection<'_>, delay_us: u64) {
+    if let Some(HwTimer::OneShot(timer)) = TIMER.borrow_ref_mut(cs).as_mut() {
+        let _ = timer.schedule(HalDuration::from_micros(delay_us));
+    }
+}
+
+/// ISR trampoline registered with the HAL interrupt controller.
+#[cfg(feature = "esp32")]
+extern "C" fn timer_isr_trampoline() {
+    crate::trace_event!(IsrEnter { irq: "timer" });
+    critical_section::with(|cs| {
+        // Acknowledge hardware interrupt
+        match TIMER.borrow_ref_mut(cs).as_mut() {
+            Some(HwTimer::Periodic(timer)) => {
+                // Increment tick counter first to minimize latency for waiting tasks
+                let ticks = SYSTEM_TICKS.borrow(cs);
+                ticks.set(ticks.get().wrapping_add(1));
+                timer.clear_interrupt();
+            }
+            Some(HwTimer::OneShot(timer)) => timer.clear_interrupt(),
+            None => {}
+        }
+
+        // Keep ticking while busy, so thread time slices still expire.
+        if let Some(epoch) = TICKLESS_EPOCH_US.borrow(cs).get() {
+            let elapsed = now_micros().saturating_sub(epoch);
+            let next_tick = Instant::from_ticks(elapsed / TICK_PERIOD_US + 1);
+            schedule_alarm(cs, alarm_delay_us(elapsed, Some(next_tick)));
+        }
+
+        wake_due_cores(cs);
+    });
+    crate::trace_event!(IsrExit { irq: "timer" });
+
+    // Outside the critical section: may switch to the scheduler if the
+    // running thread has used up its time slice.
+    crate::preempt::on_tick();
 }
 
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+
+    use super::*;
+    use crate::{
+        scheduler::{Scheduler, Task, TaskCommand, TaskContext},
+        sim,
+        smp::{self, CoreId},
+    };
+
+    #[test]
+    fn wall_clock_conversions_round_up() {
+        assert_eq!(Duration::from_millis(0).ticks(), 0);
+        assert_eq!(Duration::from_millis(3).ticks(), 3);
+        assert_eq!(Duration::from_micros(1).ticks(), 1);
+        assert_eq!(Duration::from_micros(1_001).ticks(), 2);
+        assert_eq!(ms_to_ticks(u32::MAX), u32::MAX as u64);
+    }
+
+    #[test]
+    fn instants_do_not_wrap_at_32_bits() {
+        let before = Instant::from_ticks(u32::MAX as u64 - 1);
+        let after = before + Duration::from_millis(10);
+        assert!(after > before);
+        assert_eq!(after - before, Duration::from_ticks(10));
+        assert_eq!(before - after, Duration::ZERO);
+    }
+
+    #[test]
+    fn alarm_fires_at_start_of_deadline_tick() {
+        let deadline = Instant::from_ticks(20);
+        assert_eq!(alarm_delay_us(0, Some(deadline)), 20_000);
+        assert_eq!(alarm_delay_us(19_250, Some(deadline)), 750);
+        assert_eq!(alarm_delay_us(19_995, Some(deadline)), MIN_ALARM_US);
+    }
+
+    #[test]
+    fn alarm_delay_is_clamped() {
+        let max = MAX_IDLE.as_micros();
+        assert_eq!(
+            alarm_delay_us(5_000, Some(Instant::from_ticks(2))),
+            MIN_ALARM_US
+        );
+        assert_eq!(alarm_delay_us(5_000, Some(Instant::MAX)), max);
+        assert_eq!(alarm_delay_us(5_000, None), max);
+        assert_eq!(
+            alarm_delay_us(5_000, Some(Instant::from_ticks(10_000))),
+            max
+        );
+    }
+
+    struct Sleeper;
+
+    impl Task for Sleeper {
+        fn name(&self) -> &'static str {
+            "sleeper"
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            TaskCommand::SleepMs(50)
+        }
+    }
+
+    #[test]
+    fn tickless_idle_jumps_to_next_deadline() {
+        let _board = sim::board();
+        let mut scheduler = Scheduler::new();
+        scheduler.set_idle_hook(set_alarm);
+        let sleeper = scheduler.spawn(Box::new(Sleeper)).unwrap();
+
+        scheduler.run_ready();
+        assert!(scheduler.idle());
+        assert_eq!(get_ticks(), 50);
+
+        // With every task parked indefinitely, idling is capped.
+        let _ = scheduler.kill(sleeper);
+        assert!(scheduler.idle());
+        assert_eq!(get_ticks(), 50 + MAX_IDLE.ticks());
+    }
+
+    #[test]
+    fn wake_by_other_interrupt_resumes_ticking() {
+        let _board = sim::board();
+        let mut scheduler = Scheduler::new();
+        scheduler.set_idle_hook(set_alarm);
+        scheduler.spawn(Box::new(Sleeper)).unwrap();
+        scheduler.run_ready();
+
+        // A doorbell ends the halt before the alarm for tick 50.
+        smp::interrupt_core(CoreId::Pro);
+        assert!(scheduler.idle());
+
+        assert_eq!(get_ticks(), 0);
+        assert_eq!(sim::clock::alarm(CoreId::Pro), Some(1));
+    }
+}
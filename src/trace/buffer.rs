@@ -0,0 +1,207 @@
+//! Ring buffer of trace records.
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use heapless::{Deque, Vec};
+
+use crate::{
+    scheduler::{ExitStatus, TaskId},
+    smp::{self, CoreId},
+    timer::{self, Instant},
+};
+
+/// Records kept before the oldest are overwritten.
+pub const TRACE_CAPACITY: usize = 256;
+
+/// Something the kernel did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The scheduler started polling a task.
+    PollStart { task: TaskId, name: &'static str },
+    /// The poll started by the last [`Event::PollStart`] on the core returned.
+    PollEnd { task: TaskId },
+    /// A task went to sleep until the given instant.
+    Sleep { task: TaskId, until: Instant },
+    /// A task parked until woken, or until the timeout if one is given.
+    Block {
+        task: TaskId,
+        timeout: Option<Instant>,
+    },
+    /// A task's waker or notifier was invoked.
+    Wake { task: TaskId },
+    /// A task joined a scheduler.
+    Spawn { task: TaskId, name: &'static str },
+    /// A task ended.
+    Finish { task: TaskId, status: ExitStatus },
+    /// A task overran its stack guard.
+    StackOverflow { task: TaskId },
+    /// An interrupt handler started.
+    IsrEnter { irq: &'static str },
+    /// An interrupt handler returned.
+    IsrExit { irq: &'static str },
+}
+
+/// A timestamped [`Event`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    /// Time of the event, from [`timer::now_micros`].
+    pub timestamp_us: u64,
+    /// Core the event happened on.
+    pub core: CoreId,
+    pub event: Event,
+}
+
+struct TraceBuffer {
+    records: Deque<Record, TRACE_CAPACITY>,
+    /// Records overwritten since the buffer was last taken.
+    dropped: u32,
+}
+
+static BUFFER: Mutex<RefCell<TraceBuffer>> = Mutex::new(RefCell::new(TraceBuffer {
+    records: Deque::new(),
+    dropped: 0,
+}));
+
+/// Append `event`, overwriting the oldest record if the buffer is full.
+/// Safe to call from interrupt handlers and either core.
+pub fn record(event: Event) {
+    let record = Record {
+        timestamp_us: timer::now_micros(),
+        core: smp::current_core(),
+        event,
+    };
+    critical_section::with(|cs| {
+        let mut buffer = BUFFER.borrow_ref_mut(cs);
+        if buffer.records.is_full() {
+            buffer.records.pop_front();
+            buffer.dropped = buffer.dropped.saturating_add(1);
+        }
+        // Cannot fail: room was made above.
+        let _ = buffer.records.push_back(record);
+    });
+}
+
+/// Remove every record, oldest first, along with the number overwritten
+/// since the last call.
+pub fn take() -> (Vec<Record, TRACE_CAPACITY>, u32) {
+    critical_section::with(|cs| {
+        let mut buffer = BUFFER.borrow_ref_mut(cs);
+        let mut records = Vec::new();
+        while let Some(record) = buffer.records.pop_front() {
+            // Cannot fail: both hold `TRACE_CAPACITY` records.
+            let _ = records.push(record);
+        }
+        (records, core::mem::take(&mut buffer.dropped))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, vec::Vec};
+
+    use super::*;
+    use crate::{
+        scheduler::{Scheduler, Task, TaskCommand, TaskContext},
+        sim,
+    };
+
+    /// Sleeps once, then waits for a notification and exits.
+    struct Sleeper {
+        polls: u32,
+    }
+
+    impl Task for Sleeper {
+        fn name(&self) -> &'static str {
+            "sleeper"
+        }
+
+        fn poll(&mut self, ctx: &mut TaskContext) -> TaskCommand {
+            self.polls += 1;
+            match self.polls {
+                1 => TaskCommand::SleepMs(5),
+                2 => {
+                    ctx.notifier().notify(1);
+                    TaskCommand::Park(None)
+                }
+                _ => TaskCommand::Finished,
+            }
+        }
+    }
+
+    #[test]
+    fn scheduler_records_task_lifecycle() {
+        let _board = sim::board();
+        let _ = take();
+        let mut scheduler = Scheduler::new();
+        let id = scheduler.spawn(Box::new(Sleeper { polls: 0 })).unwrap();
+
+        scheduler.run_ready();
+        sim::clock::advance_ms(5);
+        scheduler.run_ready();
+        scheduler.run_ready();
+
+        let (records, dropped) = take();
+        assert_eq!(dropped, 0);
+        assert!(records.iter().all(|record| record.core == CoreId::Pro));
+        let (interrupts, task_events): (Vec<Event>, Vec<Event>) = records
+            .iter()
+            .map(|record| record.event)
+            .partition(|event| matches!(event, Event::IsrEnter { .. } | Event::IsrExit { .. }));
+        // One timer interrupt per tick the clock advanced.
+        assert_eq!(interrupts.len(), 10);
+        assert_eq!(
+            task_events,
+            [
+                Event::Spawn {
+                    task: id,
+                    name: "sleeper"
+                },
+                Event::PollStart {
+                    task: id,
+                    name: "sleeper"
+                },
+                Event::PollEnd { task: id },
+                Event::Sleep {
+                    task: id,
+                    until: Instant::from_ticks(5)
+                },
+                Event::PollStart {
+                    task: id,
+                    name: "sleeper"
+                },
+                Event::Wake { task: id },
+                Event::PollEnd { task: id },
+                Event::Block {
+                    task: id,
+                    timeout: None
+                },
+                Event::PollStart {
+                    task: id,
+                    name: "sleeper"
+                },
+                Event::PollEnd { task: id },
+                Event::Finish {
+                    task: id,
+                    status: ExitStatus::Exited(0)
+                },
+            ]
+        );
+        assert_eq!(records.last().unwrap().timestamp_us, 5_000);
+    }
+
+    #[test]
+    fn full_buffer_overwrites_oldest_records() {
+        let _board = sim::board();
+        let _ = take();
+        for task in 0..TRACE_CAPACITY as u32 + 3 {
+            record(Event::Wake { task });
+        }
+
+        let (records, dropped) = take();
+        assert_eq!(dropped, 3);
+        assert_eq!(records.len(), TRACE_CAPACITY);
+        assert_eq!(records[0].event, Event::Wake { task: 3 });
+        assert_eq!(take().0.len(), 0);
+    }
+}
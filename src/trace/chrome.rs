@@ -0,0 +1,197 @@
+//! Chrome trace export.
+//!
+//! Writes the trace buffer in the JSON object format of the Chrome Trace
+//! Event spec, one event per console line. Save the lines from
+//! `{"traceEvents":[` through `]}` to a `.json` file and open it in
+//! `chrome://tracing` or <https://ui.perfetto.dev>.
+//!
+//! Each core is a thread of process 0. Polls and interrupt handlers are
+//! duration slices named after the task or interrupt, so handlers show up
+//! nested inside the poll they interrupted; the other events are instant
+//! markers carrying the task id.
+
+use core::fmt;
+
+use super::buffer::{self, Event, Record};
+use crate::{
+    println,
+    smp::{CoreId, NUM_CORES},
+    timer::{Duration, Instant},
+};
+
+/// Print and clear the trace buffer.
+pub fn export() {
+    let (records, dropped) = buffer::take();
+    println!("{{\"traceEvents\":[");
+    for index in 0..NUM_CORES {
+        let separator = if index == 0 { "" } else { "," };
+        let core = CoreId::from_index(index);
+        println!(
+            "{}{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":{},\"args\":{{\"name\":\"{:?} core\"}}}}",
+            separator,
+            index,
+            core
+        );
+    }
+    if dropped > 0 {
+        let start = records.first().map_or(0, |record| record.timestamp_us);
+        println!(
+            ",{{\"name\":\"{} events lost\",\"ph\":\"i\",\"s\":\"g\",\"ts\":{},\"pid\":0,\"tid\":0}}",
+            dropped, start
+        );
+    }
+    for record in &records {
+        println!(",{}", ChromeEvent(record));
+    }
+    println!("]}}");
+}
+
+/// One record as a trace event object.
+struct ChromeEvent<'a>(&'a Record);
+
+impl fmt::Display for ChromeEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let record = self.0;
+        let common = Common(record);
+        match record.event {
+            Event::PollStart { task, name } => write!(
+                f,
+                "{{\"name\":\"{}\",\"cat\":\"task\",\"ph\":\"B\",{},\"args\":{{\"task\":{}}}}}",
+                Escaped(name),
+                common,
+                task
+            ),
+            Event::PollEnd { .. } | Event::IsrExit { .. } => {
+                write!(f, "{{\"ph\":\"E\",{}}}", common)
+            }
+            Event::IsrEnter { irq } => write!(
+                f,
+                "{{\"name\":\"irq {}\",\"cat\":\"isr\",\"ph\":\"B\",{}}}",
+                Escaped(irq),
+                common
+            ),
+            Event::Sleep { task, until } => write!(
+                f,
+                "{{\"name\":\"sleep\",{},{},\"args\":{{\"task\":{},\"until\":{}}}}}",
+                INSTANT,
+                common,
+                task,
+                micros(until)
+            ),
+            Event::Block { task, timeout } => {
+                write!(
+                    f,
+                    "{{\"name\":\"block\",{},{},\"args\":{{\"task\":{}",
+                    INSTANT, common, task
+                )?;
+                if let Some(timeout) = timeout {
+                    write!(f, ",\"timeout\":{}", micros(timeout))?;
+                }
+                write!(f, "}}}}")
+            }
+            Event::Wake { task } => write!(
+                f,
+                "{{\"name\":\"wake\",{},{},\"args\":{{\"task\":{}}}}}",
+                INSTANT, common, task
+            ),
+            Event::Spawn { task, name } => write!(
+                f,
+                "{{\"name\":\"spawn\",{},{},\"args\":{{\"task\":{},\"name\":\"{}\"}}}}",
+                INSTANT,
+                common,
+                task,
+                Escaped(name)
+            ),
+            Event::Finish { task, status } => write!(
+                f,
+                "{{\"name\":\"finish\",{},{},\"args\":{{\"task\":{},\"status\":\"{:?}\"}}}}",
+                INSTANT, common, task, status
+            ),
+            Event::StackOverflow { task } => write!(
+                f,
+                "{{\"name\":\"stack overflow\",{},{},\"args\":{{\"task\":{}}}}}",
+                INSTANT, common, task
+            ),
+        }
+    }
+}
+
+/// Fields of a thread-scoped instant event.
+const INSTANT: &str = "\"cat\":\"task\",\"ph\":\"i\",\"s\":\"t\"";
+
+/// Timestamp, process and thread fields of a record.
+struct Common<'a>(&'a Record);
+
+impl fmt::Display for Common<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "\"ts\":{},\"pid\":0,\"tid\":{}",
+            self.0.timestamp_us,
+            self.0.core.index()
+        )
+    }
+}
+
+/// String with the characters JSON requires escaped.
+struct Escaped<'a>(&'a str);
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' | '\\' => write!(f, "\\{}", c)?,
+                c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Microseconds since startup of `instant`.
+fn micros(instant: Instant) -> u64 {
+    Duration::from_ticks(instant.ticks()).as_micros()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scheduler::ExitStatus, sim, trace::record};
+
+    #[test]
+    fn export_prints_chrome_trace_json() {
+        let _board = sim::board();
+        let _ = buffer::take();
+        sim::clock::set(2);
+        record(Event::PollStart {
+            task: 1,
+            name: "ui \"main\"",
+        });
+        record(Event::Block {
+            task: 1,
+            timeout: Some(Instant::from_ticks(7)),
+        });
+        record(Event::PollEnd { task: 1 });
+        record(Event::Finish {
+            task: 1,
+            status: ExitStatus::Killed,
+        });
+
+        export();
+
+        assert_eq!(
+            sim::console::take_lines(),
+            [
+                "{\"traceEvents\":[",
+                "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":0,\"args\":{\"name\":\"Pro core\"}}",
+                ",{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":1,\"args\":{\"name\":\"App core\"}}",
+                ",{\"name\":\"ui \\\"main\\\"\",\"cat\":\"task\",\"ph\":\"B\",\"ts\":2000,\"pid\":0,\"tid\":0,\"args\":{\"task\":1}}",
+                ",{\"name\":\"block\",\"cat\":\"task\",\"ph\":\"i\",\"s\":\"t\",\"ts\":2000,\"pid\":0,\"tid\":0,\"args\":{\"task\":1,\"timeout\":7000}}",
+                ",{\"ph\":\"E\",\"ts\":2000,\"pid\":0,\"tid\":0}",
+                ",{\"name\":\"finish\",\"cat\":\"task\",\"ph\":\"i\",\"s\":\"t\",\"ts\":2000,\"pid\":0,\"tid\":0,\"args\":{\"task\":1,\"status\":\"Killed\"}}",
+                "]}",
+            ]
+        );
+    }
+}
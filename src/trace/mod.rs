@@ -0,0 +1,39 @@
+//! Kernel event tracing.
+//!
+//! With the `trace` feature the scheduler records what it does (polls,
+//! sleeps, wakeups, spawns, exits, stack guard trips) and interrupt handlers
+//! record their entry and exit into a ring buffer of timestamped
+//! records. When the buffer is full the oldest records are overwritten,
+//! so it always holds the most recent history, for example the few hundred
+//! milliseconds leading up to a UI stutter.
+//!
+//! [`export`] prints the buffer to the console as a Chrome trace, which
+//! `chrome://tracing` and the Perfetto UI load directly; see [`chrome`].
+//!
+//! Without the feature [`trace_event!`](crate::trace_event) expands to
+//! nothing and the buffer does not exist, so tracing costs neither time nor
+//! memory.
+
+#[cfg(feature = "trace")]
+mod buffer;
+#[cfg(feature = "trace")]
+pub mod chrome;
+
+#[cfg(feature = "trace")]
+pub use buffer::{record, Event};
+#[cfg(feature = "trace")]
+pub use chrome::export;
+
+/// Record a trace [`Event`] variant, given without the `Event::` prefix.
+/// Expands to nothing without the `trace` feature.
+///
+/// ```ignore
+/// crate::trace_event!(Wake { task: id });
+/// ```
+#[macro_export]
+macro_rules! trace_event {
+    ($($event:tt)+) => {{
+        #[cfg(feature = "trace")]
+        $crate::trace::record($crate::trace::Event::$($event)+);
+    }};
+}
@@ -0,0 +1,191 @@
+//! Task watchdog.
+//!
+//! A task opts in through [`Task::watchdog`](crate::scheduler::Task::watchdog)
+//! by declaring how long a single poll may take and, optionally, how often it
+//! must be polled. The scheduler logs every violation with the task's name;
+//! the main loop feeds the hardware watchdog only while
+//! [`Scheduler::watchdog_healthy`](crate::scheduler::Scheduler::watchdog_healthy)
+//! reports no violations. A task stuck inside `poll` stops the main loop
+//! altogether, so the hardware watchdog resets the chip in both cases.
+
+#[cfg(feature = "esp32")]
+use core::cell::RefCell;
+
+#[cfg(feature = "esp32")]
+use critical_section::Mutex;
+#[cfg(feature = "esp32")]
+use esp_hal::{
+    peripherals::TIMG1,
+    time::Duration as HalDuration,
+    timer::timg::{MwdtStage, MwdtStageAction, TimerGroup, Wdt},
+};
+
+use crate::timer::Duration;
+
+/// Time without a feed after which the hardware watchdog resets the chip.
+pub const HW_TIMEOUT: Duration = Duration::from_millis(5_000);
+
+/// Watchdog budget declared by a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchdogConfig {
+    /// Longest a single poll may take.
+    pub max_poll: Duration,
+    /// Longest the task may go without being polled, if it is checked.
+    pub liveness: Option<Duration>,
+}
+
+impl WatchdogConfig {
+    /// Budget limiting each poll to `max_poll`.
+    pub const fn new(max_poll: Duration) -> Self {
+        Self {
+            max_poll,
+            liveness: None,
+        }
+    }
+
+    /// Also require the task to be polled at least once per `interval`.
+    pub const fn with_liveness(mut self, interval: Duration) -> Self {
+        self.liveness = Some(interval);
+        self
+    }
+}
+
+#[cfg(feature = "esp32")]
+static HW_WATCHDOG: Mutex<RefCell<Option<Wdt<TIMG1<'static>>>>> = Mutex::new(RefCell::new(None));
+
+/// Start the TIMG1 watchdog with a system reset after [`HW_TIMEOUT`].
+#[cfg(feature = "esp32")]
+pub fn init(timg1: TIMG1<'static>) -> Result<(), &'static str> {
+    critical_section::with(|cs| {
+        let mut cell = HW_WATCHDOG.borrow_ref_mut(cs);
+        if cell.is_some() {
+            return Err("Watchdog already initialized");
+        }
+
+        let mut wdt = TimerGroup::new(timg1).wdt;
+        wdt.set_timeout(
+            MwdtStage::Stage0,
+            HalDuration::from_millis(HW_TIMEOUT.as_millis()),
+        );
+        wdt.set_stage_action(MwdtStage::Stage0, MwdtStageAction::ResetSystem);
+        wdt.enable();
+        wdt.feed();
+        *cell = Some(wdt);
+        Ok(())
+    })
+}
+
+/// Start the simulated watchdog with a timeout of [`HW_TIMEOUT`].
+#[cfg(feature = "sim")]
+pub fn init() -> Result<(), &'static str> {
+    crate::sim::watchdog::enable(HW_TIMEOUT);
+    Ok(())
+}
+
+/// Restart the hardware watchdog timeout.
+#[cfg(feature = "esp32")]
+pub fn feed() {
+    critical_section::with(|cs| {
+        if let Some(wdt) = HW_WATCHDOG.borrow_ref_mut(cs).as_mut() {
+            wdt.feed();
+        }
+    });
+}
+
+/// Restart the simulated watchdog timeout.
+#[cfg(feature = "sim")]
+pub fn feed() {
+    crate::sim::watchdog::feed();
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+
+    use super::*;
+    use crate::{
+        scheduler::{Scheduler, Task, TaskCommand, TaskContext},
+        sim,
+    };
+
+    /// Busy for `busy_ms` per poll, then sleeps for `sleep_ms`.
+    struct Supervised {
+        busy_ms: u32,
+        sleep_ms: u32,
+        config: WatchdogConfig,
+    }
+
+    impl Task for Supervised {
+        fn name(&self) -> &'static str {
+            "supervised"
+        }
+
+        fn watchdog(&self) -> Option<WatchdogConfig> {
+            Some(self.config)
+        }
+
+        fn poll(&mut self, _ctx: &mut TaskContext) -> TaskCommand {
+            sim::clock::advance_ms(self.busy_ms);
+            TaskCommand::SleepMs(self.sleep_ms)
+        }
+    }
+
+    /// Run the main loop for `ms` ticks, feeding the watchdog while healthy.
+    fn run_for(scheduler: &mut Scheduler, ms: u64) {
+        let end = crate::timer::Instant::now() + Duration::from_millis(ms);
+        while crate::timer::Instant::now() < end {
+            scheduler.run_ready();
+            if scheduler.watchdog_healthy() {
+                feed();
+            }
+            sim::clock::advance(1);
+        }
+    }
+
+    #[test]
+    fn overrunning_poll_is_logged_and_starves_hardware_watchdog() {
+        let _board = sim::board();
+        init().unwrap();
+        let mut scheduler = Scheduler::new();
+        scheduler
+            .spawn(Box::new(Supervised {
+                busy_ms: 20,
+                sleep_ms: 1,
+                config: WatchdogConfig::new(Duration::from_millis(10)),
+            }))
+            .unwrap();
+
+        run_for(&mut scheduler, HW_TIMEOUT.as_millis());
+
+        let lines = sim::console::take_lines();
+        assert!(lines[0].contains("task supervised polled for 20000 us"));
+        assert!(scheduler.stats().tasks[0].stats.watchdog_violations > 0);
+        assert!(sim::watchdog::expired());
+    }
+
+    #[test]
+    fn missed_liveness_interval_withholds_feeds_until_polled() {
+        let _board = sim::board();
+        init().unwrap();
+        let mut scheduler = Scheduler::new();
+        scheduler
+            .spawn(Box::new(Supervised {
+                busy_ms: 0,
+                sleep_ms: 500,
+                config: WatchdogConfig::new(Duration::from_millis(10))
+                    .with_liveness(Duration::from_millis(100)),
+            }))
+            .unwrap();
+
+        scheduler.run_ready();
+        sim::clock::advance_ms(101);
+        assert!(!scheduler.watchdog_healthy());
+        assert!(!scheduler.watchdog_healthy());
+        assert_eq!(sim::console::take_lines().len(), 1);
+
+        sim::clock::advance_ms(399);
+        scheduler.run_ready();
+        assert!(scheduler.watchdog_healthy());
+        assert_eq!(scheduler.stats().tasks[0].stats.watchdog_violations, 1);
+    }
+}